@@ -0,0 +1,236 @@
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use common::capture::{settings::dntime::{DNTime, Exposure, Iso, Aperture}, calibration::FrameKind, Bracketed, FileType};
+use log::{info, debug, warn};
+use thiserror::Error;
+use tokio::{fs, time::Instant};
+use uuid::Uuid;
+
+use super::{ Capture, CaptureCommand, CaptureResult, CaptureError, Captured };
+use super::process::{self, Limits, ProcessError};
+
+/// seconds an automatic exposure is expected to take at most.
+const AUTO_EXPOSURE: f64 = 30.0;
+
+/// Captures by running `command.program` with the arguments of `command.args`.
+pub struct Command {
+    /// type of the file the command writes, see `command.file_type`.
+    file_type: FileType,
+    /// file the command writes to. unique per camera.
+    output: PathBuf,
+}
+
+#[derive(Error, Debug)]
+enum CommandError {
+    #[error("cancelled.")]
+    Cancelled,
+
+    #[error("{0}")]
+    Process(ProcessError),
+
+    #[error("{program:?} exited with {code}. {stderr}")]
+    Failed { program: String, code: String, stderr: String },
+
+    #[error("output of {0:?} does not match command.success_pattern.")]
+    NoMatch(String),
+
+    #[error("{0:?} did not write {1:?}. {2}")]
+    NoOutput(String, std::path::PathBuf, std::io::Error),
+
+    #[error("IO Error. {0}")]
+    IO(std::io::Error),
+}
+
+impl From<ProcessError> for CommandError {
+    fn from(e: ProcessError) -> CommandError {
+        match e {
+            ProcessError::Cancelled => CommandError::Cancelled,
+            e => CommandError::Process(e),
+        }
+    }
+}
+
+impl CommandError {
+    fn into_capture(self) -> CaptureError {
+        match self {
+            CommandError::Cancelled => CaptureError::Cancelled,
+            e => CaptureError::Module(Box::new(e)),
+        }
+    }
+}
+
+impl Command {
+    /// `id` is the one of the camera in `cameras`, `None` for the single camera of `general.module`.
+    pub fn new(id: Option<&str>, file_type: FileType) -> Self {
+        info!("capturing with {program:?}.", program = crate::CONFIG.command.program);
+        let name = match id {
+            Some(id) => format!("command-capture-{id}.{ext}", ext = file_type.ext()),
+            None => format!("command-capture.{ext}", ext = file_type.ext()),
+        };
+        Command { file_type, output: crate::CONFIG.general.tmp_path.join(name) }
+    }
+}
+
+#[async_trait]
+impl Capture for Command {
+    async fn capture(&mut self, cmd: CaptureCommand) -> Result<Captured, CaptureError> {
+        let CaptureCommand { cancel_token, time, is_night, settings } = cmd;
+        let start = Instant::now();
+
+        let file = capture_frame(&cancel_token, &settings, 0.0, &self.output, self.file_type).await
+            .map_err(CommandError::into_capture)?;
+        let mut subframes = vec![];
+        for _ in 1..settings.stack.map_or(1, |s| s.subframes) {
+            subframes.push(capture_frame(&cancel_token, &settings, 0.0, &self.output, self.file_type).await
+                .map_err(CommandError::into_capture)?);
+        }
+        let mut bracket = vec![];
+        for offset in settings.bracket.iter().flat_map(|b| b.offsets.iter().copied()) {
+            let settings = settings.offset(offset);
+            let file = capture_frame(&cancel_token, &settings, offset, &self.output, self.file_type).await
+                .map_err(CommandError::into_capture)?;
+            bracket.push(Bracketed { offset, file, settings: Some(settings) });
+        }
+        info!("capture complete after {:.1} seconds.", start.elapsed().as_secs_f64());
+
+        Ok(CaptureResult { uuid: Uuid::new_v4(), time, is_night, file_type: self.file_type, file, settings: Some(settings), bracket, subframes, kind: FrameKind::Light, raw: None, camera: None }.into())
+    }
+
+    fn file_types(&self) -> Vec<FileType> {
+        vec![self.file_type]
+    }
+}
+
+/// Limits of a run that exposes with `settings`, see `command.timeout`.
+fn limits(settings: &DNTime) -> Limits {
+    let config = &crate::CONFIG.command;
+    let exposure = match settings.exposure {
+        Exposure::Manual(f) => f,
+        Exposure::Auto => AUTO_EXPOSURE,
+    };
+    Limits { timeout: Some(config.timeout + Duration::from_secs_f64(exposure)), grace_period: config.grace_period, max_output: None }
+}
+
+/// Runs the command once. `ev` is the offset within a bracket. `output` is the file it writes, a `file_type`.
+async fn capture_frame(cancel_token: &tokio::sync::Notify, settings: &DNTime, ev: f64, output: &Path, file_type: FileType) -> Result<Vec<u8>, CommandError> {
+    let config = &crate::CONFIG.command;
+    let tmp_path = &crate::CONFIG.general.tmp_path;
+    let program = config.program.display().to_string();
+
+    fs::create_dir_all(tmp_path).await
+        .map_err(CommandError::IO)?;
+    if let Err(e) = fs::remove_file(output).await {
+        if e.kind() != std::io::ErrorKind::NotFound { return Err(CommandError::IO(e)); }
+    }
+
+    let args = generate_args(&config.args, settings, ev, output);
+    debug!("running {program} {args}", args = args.join(" "));
+    let mut command = tokio::process::Command::new(&config.program);
+    command.args(&args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let result = process::run(command, cancel_token, &limits(settings)).await?;
+
+    let stderr = String::from_utf8_lossy(&result.stderr).trim().to_string();
+    let code = result.status.code();
+    if !code.is_some_and(|c| config.success_codes.contains(&c)) {
+        return Err(CommandError::Failed { program, code: code.map_or(String::from("a signal"), |c| c.to_string()), stderr });
+    }
+    if let Some(pattern) = &config.success_pattern {
+        if !pattern.is_match(&String::from_utf8_lossy(&result.stdout)) && !pattern.is_match(&stderr) {
+            return Err(CommandError::NoMatch(program));
+        }
+    }
+
+    let file = fs::read(output).await
+        .map_err(|e| CommandError::NoOutput(program.clone(), output.to_path_buf(), e))?;
+    fs::remove_file(output).await
+        .map_err(CommandError::IO)?;
+    match FileType::detect(&file) {
+        Some(t) if t != file_type => warn!("{program} wrote a {t:?}, but {file_type:?} is declared."),
+        _ => {},
+    }
+    Ok(file)
+}
+
+/// Fills the placeholders of `template`, see `defaults/command.toml`.
+fn generate_args(template: &[String], settings: &DNTime, ev: f64, output: &Path) -> Vec<String> {
+    let exposure = match settings.exposure { Exposure::Manual(f) => Some(f), Exposure::Auto => None };
+    let iso = match settings.iso { Iso::Manual(u) => Some(u), Iso::Auto => None };
+    let aperture = match settings.aperture { Aperture::Manual(f) => Some(f), Aperture::Auto | Aperture::Implicit => None };
+    let value = |placeholder: &str| -> Option<String> {
+        match placeholder {
+            "exposure" => exposure.map(|f| f.to_string()),
+            "exposure_ms" => exposure.map(|f| format!("{}", (f * 1e3).round())),
+            "exposure_us" => exposure.map(|f| format!("{}", (f * 1e6).round())),
+            "iso" => iso.map(|u| u.to_string()),
+            "gain" => iso.map(|u| (u as f64 / 100.0).to_string()),
+            "aperture" => aperture.map(|f| f.to_string()),
+            "ev" => Some(ev.to_string()),
+            "output" => Some(output.display().to_string()),
+            _ => None,
+        }
+    };
+
+    let mut args = vec![];
+    'entries: for entry in template {
+        let mut words = vec![];
+        for word in entry.split_whitespace() {
+            let mut filled = String::new();
+            let mut rest = word;
+            while let Some((before, after)) = rest.split_once('{') {
+                let (placeholder, after) = after.split_once('}').unwrap_or((after, ""));
+                match value(placeholder) {
+                    Some(v) => { filled.push_str(before); filled.push_str(&v); },
+                    None => {
+                        debug!("dropping {entry:?}. {placeholder} is automatic.");
+                        continue 'entries;
+                    },
+                }
+                rest = after;
+            }
+            filled.push_str(rest);
+            words.push(filled);
+        }
+        args.extend(words);
+    }
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::capture::settings::dntime::Frame;
+
+    fn settings(exposure: Exposure, iso: Iso) -> DNTime {
+        DNTime { frame: Frame::None, exposure, iso, aperture: Aperture::Implicit, bracket: None, stack: None }
+    }
+
+    fn template(entries: &[&str]) -> Vec<String> {
+        entries.iter().map(|e| e.to_string()).collect()
+    }
+
+    #[test]
+    fn generate_args_fills_placeholders() {
+        let template = template(&["--nopreview", "--shutter {exposure_us}", "--gain {gain}", "--output {output}"]);
+        let args = generate_args(&template, &settings(Exposure::Manual(0.5), Iso::Manual(400)), 0.0, Path::new("/tmp/frame.jpg"));
+        assert_eq!(args, ["--nopreview", "--shutter", "500000", "--gain", "4", "--output", "/tmp/frame.jpg"]);
+    }
+
+    #[test]
+    fn generate_args_fills_within_words() {
+        let template = template(&["--exposure={exposure_ms}ms,iso={iso}", "--ev={ev}"]);
+        let args = generate_args(&template, &settings(Exposure::Manual(2.0), Iso::Manual(100)), -1.5, Path::new("out"));
+        assert_eq!(args, ["--exposure=2000ms,iso=100", "--ev=-1.5"]);
+    }
+
+    #[test]
+    fn generate_args_drops_automatic_entries() {
+        let template = template(&["--shutter {exposure}", "--iso {iso}", "--f {aperture}", "--unknown {nope}", "{output}"]);
+        let args = generate_args(&template, &settings(Exposure::Auto, Iso::Manual(800)), 0.0, Path::new("out"));
+        assert_eq!(args, ["--iso", "800", "out"]);
+    }
+}
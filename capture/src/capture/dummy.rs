@@ -1,60 +1,131 @@
 use std::time::Duration;
 
 use async_trait::async_trait;
-use common::capture::FileType;
+use chrono::{DateTime, Local};
+use common::capture::{settings::dntime::{DNTime, Exposure}, calibration::FrameKind, Bracketed, FileType};
 use log::{info, debug};
+use rand::{Rng, SeedableRng, rngs::StdRng};
 use thiserror::Error;
 use tokio::time::sleep;
 use uuid::Uuid;
 
-use super::{ Capture, CaptureCommand, CaptureResult, CaptureError };
+use crate::CONFIG;
+use crate::config::dummy::Format;
+use crate::sun;
+use super::{ Capture, CaptureCommand, CaptureResult, CaptureError, Captured };
+use super::synthetic::Sky;
 
+/// Renders a synthetic sky for the sun's position at capture time, see `dummy`.
 pub struct Dummy {
-
+    rng: StdRng,
+    sky: Sky,
 }
 
 #[derive(Error, Debug)]
 enum DummyError {
     #[error("dummy rolled a dice and decided to throw an error.")]
-    Error
+    Error,
+    #[error("failed to encode the image. {0}")]
+    Encode(#[from] image::ImageError),
 }
 
 impl Dummy {
     pub fn new() -> Self {
         info!("new dummy created.");
-        Dummy { }
+        let rng = match CONFIG.dummy.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        Dummy { rng, sky: Sky::new(CONFIG.dummy.seed.unwrap_or_default()) }
+    }
+
+    async fn frame(&mut self, settings: &DNTime, time: DateTime<Local>) -> Result<Vec<u8>, DummyError> {
+        expose(settings).await;
+        let altitude = sun::altitude(time.timestamp_millis());
+        Ok(self.sky.render(&mut self.rng, settings, altitude, time, &CONFIG.dummy)?)
     }
 }
 
 #[async_trait]
 impl Capture for Dummy {
-    async fn capture(&mut self, cmd: CaptureCommand) -> Result<CaptureResult, CaptureError> {
+    async fn capture(&mut self, cmd: CaptureCommand) -> Result<Captured, CaptureError> {
         debug!("dummy received a capture command {cmd:?}");
         tokio::select! {
             res = async {
-                if rand::random::<f64>() > 0.9 {
-                    Err(CaptureError::Module(Box::new(DummyError::Error)))
-                } else {
-                    match cmd.settings.exposure {
-                        common::capture::settings::dntime::Exposure::Auto => {
-                            sleep(Duration::from_secs_f64(0.1)).await;
-                            debug!("ClickClack");
-                        },
-                        common::capture::settings::dntime::Exposure::Manual(f) => {
-                            debug!("Click");
-                            sleep(Duration::from_secs_f64(f)).await;
-                            debug!("Clack");
-                        },
-                    }
-
-                    let uuid = Uuid::new_v4();
-                    let time = cmd.time;
-                    let is_night = cmd.is_night;
-
-                    Ok(CaptureResult { uuid, time, is_night, file_type: FileType::Dummy, file: vec![0, 0, 0] })
+                if self.rng.gen::<f64>() < CONFIG.dummy.failure_probability {
+                    return Err(DummyError::Error);
                 }
-            } => { res },
+                let file = self.frame(&cmd.settings, cmd.time).await?;
+                let mut subframes = vec![];
+                for _ in 1..cmd.settings.stack.map_or(1, |s| s.subframes) {
+                    subframes.push(self.frame(&cmd.settings, Local::now()).await?);
+                }
+                let mut bracket = vec![];
+                for offset in cmd.settings.bracket.iter().flat_map(|b| b.offsets.iter().copied()) {
+                    let settings = cmd.settings.offset(offset);
+                    let file = self.frame(&settings, Local::now()).await?;
+                    bracket.push(Bracketed { offset, file, settings: Some(settings) });
+                }
+
+                let uuid = Uuid::new_v4();
+                let time = cmd.time;
+                let is_night = cmd.is_night;
+
+                Ok(CaptureResult { uuid, time, is_night, file_type: file_type(CONFIG.dummy.format), file, settings: Some(cmd.settings.clone()), bracket, subframes, kind: FrameKind::Light, raw: None, camera: None }.into())
+            } => { res.map_err(|e| CaptureError::Module(Box::new(e))) },
             () = cmd.cancel_token.notified() => { Err(CaptureError::Cancelled) },
         }
     }
-}
\ No newline at end of file
+
+    fn file_types(&self) -> Vec<FileType> {
+        vec![file_type(CONFIG.dummy.format)]
+    }
+}
+
+/// Type of the images encoded as `format`.
+fn file_type(format: Format) -> FileType {
+    match format {
+        Format::Png => FileType::Png,
+        Format::Tiff => FileType::Tiff,
+        Format::Jpg => FileType::Jpeg,
+        Format::Dummy => FileType::Dummy,
+    }
+}
+
+async fn expose(settings: &DNTime) {
+    match settings.exposure {
+        Exposure::Auto => {
+            sleep(Duration::from_secs_f64(0.1)).await;
+            debug!("ClickClack");
+        },
+        Exposure::Manual(f) => {
+            debug!("Click");
+            sleep(Duration::from_secs_f64(f)).await;
+            debug!("Clack");
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Local;
+    use common::capture::settings::dntime::{Aperture, Frame, Iso};
+    use crate::config::dummy::Dummy as DummyConfig;
+
+    #[test]
+    fn encoded_as_declared_file_type() {
+        let settings = DNTime { frame: Frame::None, exposure: Exposure::Manual(30.0), iso: Iso::Manual(800), aperture: Aperture::Implicit, bracket: None, stack: None };
+        let sky = Sky::new(1);
+        for format in [Format::Png, Format::Tiff, Format::Jpg] {
+            let config = DummyConfig { format, width: 32, height: 16, failure_probability: 0.0, seed: Some(1) };
+            let b = sky.render(&mut StdRng::seed_from_u64(1), &settings, -20.0, Local::now(), &config).unwrap();
+            assert_eq!(FileType::detect(&b), Some(file_type(format)), "{format:?}");
+            let image = image::load_from_memory(&b).unwrap();
+            assert_eq!((image.width(), image.height()), (32, 16), "{format:?}");
+        }
+        let config = DummyConfig { format: Format::Dummy, width: 32, height: 16, failure_probability: 0.0, seed: None };
+        assert_eq!(sky.render(&mut StdRng::seed_from_u64(1), &settings, -20.0, Local::now(), &config).unwrap(), vec![0, 0, 0]);
+        assert_eq!(file_type(Format::Dummy), FileType::Dummy);
+    }
+}
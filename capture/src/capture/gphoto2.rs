@@ -1,147 +1,872 @@
-use std::{path::PathBuf, sync::Arc, process::Stdio};
+use std::{path::{Path, PathBuf}, process::Stdio, time::Duration};
 
 use async_trait::async_trait;
-use chrono::{Local, DateTime};
-use common::capture::{settings::dntime::{DNTime, Exposure, Iso}, FileType};
-use log::{info, debug, error};
+use common::capture::{settings::dntime::{DNTime, Exposure, Iso, Aperture}, calibration::FrameKind, Bracketed, FileType};
+use log::{info, debug, warn, error};
 use thiserror::Error;
 use tokio::{time::Instant, fs, process::Command, sync::Notify};
 use uuid::Uuid;
 
-use super::{ Capture, CaptureCommand, CaptureResult, CaptureError };
+use super::{ Capture, CaptureCommand, CaptureResult, CaptureError, Captured, Status };
+use super::process::{self, Limits, ProcessError};
+use super::profile::{Bulb, Profile};
+use crate::config::gphoto2::Download;
+
+/// config keys under which cameras offer the aperture. canon uses `aperture`, nikon `f-number`.
+const APERTURE_KEYS: &[&str] = &["aperture", "f-number"];
+/// config keys under which cameras offer the shutter speed.
+const SHUTTERSPEED_KEYS: &[&str] = &["shutterspeed", "shutterspeed2"];
+/// config keys under which cameras offer the exposure compensation of automatic exposures.
+const COMPENSATION_KEYS: &[&str] = &["exposurecompensation", "exposurecompensation2"];
+/// config keys under which cameras offer the ISO.
+const ISO_KEYS: &[&str] = &["iso", "isospeed"];
+/// config keys under which cameras report the battery level.
+const BATTERY_KEYS: &[&str] = &["batterylevel"];
+/// seconds an automatic exposure is expected to take at most.
+const AUTO_EXPOSURE: f64 = 30.0;
 
 pub struct GPhoto2 {
+    /// aperture choices of the attached lens. queried on first use and again if a value is missing.
+    apertures: Option<Choices>,
+    /// shutter speed choices of the camera. queried on first use.
+    shutterspeeds: Option<Choices>,
+    /// exposure compensation choices of the camera. queried on first use.
+    compensations: Option<Choices>,
+    /// ISO choices of the camera. queried on first use and when probing.
+    isos: Option<Choices>,
+    /// config names of the camera, see `gphoto2.profile`. detected on first use.
+    profile: Option<&'static Profile>,
+    /// status of the last probe, see `gphoto2.probe_interval`.
+    status: Option<Status>,
+    probed: Option<Instant>,
+    /// consecutive captures gphoto2 hung in, see `gphoto2.recover_after`.
+    hangs: u32,
+    /// port of the camera, see `cameras.port`. `None` uses the first camera gphoto2 detects.
+    port: Option<String>,
+    /// serial number to find the port by, see `cameras.serial`.
+    serial: Option<String>,
+}
+
+/// numeric choices of a config value.
+struct Choices {
+    key: &'static str,
+    /// value and the choice as the camera names it.
+    values: Vec<(f64, String)>,
+    /// choices that are not numeric, like `bulb` or `auto`.
+    other: Vec<String>,
+}
+
+/// how a manual exposure is timed.
+enum Shutter {
+    /// `key=choice` of a shutter speed the camera times itself.
+    Native(String),
+    /// hold the shutter open for the given seconds. `key=choice` selects bulb if the camera has it as a shutter speed.
+    Bulb(f64, Option<String>),
+}
 
+/// a captured frame.
+struct Frame {
+    file: Vec<u8>,
+    file_type: FileType,
+    /// jpg stored alongside the raw if `preview.enable` is set.
+    jpg: Option<Vec<u8>>,
+    /// settings the frame was actually captured with.
+    settings: DNTime,
 }
 
 #[derive(Error, Debug)]
 enum GPhoto2Error {
+    #[error("cancelled.")]
+    Cancelled,
+
     #[error("IO Error. {0}")]
     IO(std::io::Error),
 
-    #[error("Process IO Error. {0}")]
-    Process(std::io::Error),
+    #[error("Process Error. {0}")]
+    Process(ProcessError),
+
+    #[error("gphoto2 did not finish within {0:?} and was killed.")]
+    TimedOut(Duration),
+
+    #[error("gphoto2 hung {0} times in a row. the camera was reset and detected again.")]
+    Recovered(u32),
+
+    #[error("gphoto2 hung {0} times in a row and the camera could not be recovered. {1}")]
+    Unrecoverable(u32, Box<GPhoto2Error>),
+
+    #[error("gphoto2 detected no camera.")]
+    NotDetected,
+
+    #[error("gphoto2 detected no camera at {0}.")]
+    NotAt(String),
+
+    #[error("gphoto2 detected no camera with serial number {0}.")]
+    NoSerial(String),
+
+    #[error("camera does not offer ISO {0}. available: {1}")]
+    Iso(u32, String),
     
     #[error("GPhoto2 exit with a non zero exit code")]
     GPhoto2,
+
+    #[error("camera offers neither of the config values {APERTURE_KEYS:?}.")]
+    NoAperture,
+
+    #[error("camera does not offer aperture f/{0}. available: {1}")]
+    Aperture(f64, String),
+
+    #[error("camera offers none of the config values {COMPENSATION_KEYS:?} to bracket automatic exposures.")]
+    NoCompensation,
+
+    #[error("gphoto2 did not download a file.")]
+    NoDownload,
+
+    #[error("file type of the download {0} is unknown.")]
+    UnknownFileType(String),
+
+    #[error("download of {0} bytes is smaller than gphoto2.min_size or larger than gphoto2.max_size.")]
+    Size(usize),
+}
+
+impl From<ProcessError> for GPhoto2Error {
+    fn from(e: ProcessError) -> GPhoto2Error {
+        match e {
+            ProcessError::Cancelled => GPhoto2Error::Cancelled,
+            ProcessError::TimedOut(_, t) => GPhoto2Error::TimedOut(t),
+            e => GPhoto2Error::Process(e),
+        }
+    }
+}
+
+impl GPhoto2Error {
+    fn into_capture(self) -> CaptureError {
+        match self {
+            GPhoto2Error::Cancelled => CaptureError::Cancelled,
+            e => CaptureError::Module(Box::new(e)),
+        }
+    }
 }
 
 impl GPhoto2 {
-    pub fn new() -> Self {
-        GPhoto2 { }
+    /// `port` and `serial` select the camera if several are attached, see `cameras`.
+    pub fn new(port: Option<String>, serial: Option<String>) -> Self {
+        let config = &crate::CONFIG.gphoto2;
+        if config.download == Download::Stdout && crate::CONFIG.preview.enable {
+            info!("the preview needs two downloads. gphoto2 downloads into {path:?} instead of stdout.", path = config.download_path);
+        }
+        GPhoto2 { apertures: None, shutterspeeds: None, compensations: None, isos: None, profile: None, status: None, probed: None, hangs: 0, port, serial }
+    }
+
+    /// Finds the port of the camera with `self.serial` unless it is known already.
+    async fn locate(&mut self) -> Result<(), GPhoto2Error> {
+        let Some(serial) = &self.serial else { return Ok(()) };
+        if self.port.is_some() { return Ok(()); }
+        for (model, port) in detect().await? {
+            let summary = query(Some(&port), &["--summary"]).await?;
+            // "Serial Number: 3021482"
+            let found = String::from_utf8_lossy(&summary.stdout).lines()
+                .filter_map(|l| l.trim().strip_prefix("Serial Number:"))
+                .any(|s| s.trim() == serial);
+            if found {
+                info!("{model} with serial number {serial} is at {port}.");
+                self.port = Some(port);
+                return Ok(());
+            }
+        }
+        Err(GPhoto2Error::NoSerial(serial.clone()))
+    }
+
+    /// Detects the camera and reads its summary and config choices. The choices replace the cached ones.
+    async fn probe(&mut self) -> Result<Status, GPhoto2Error> {
+        self.locate().await?;
+        let port = self.port.clone();
+        let port = port.as_deref();
+        let model = model(port).await?;
+        self.select_profile(Some(&model));
+
+        let summary = query(port, &["--summary"]).await?;
+        let summary = String::from_utf8_lossy(&summary.stdout);
+        // "Free Space (Bytes): 31727632384 (30258 MB)", once per storage.
+        let free: Vec<u64> = summary.lines()
+            .filter_map(|l| l.trim().strip_prefix("Free Space (Bytes):"))
+            .filter_map(|l| l.split_whitespace().next()?.parse().ok())
+            .collect();
+        let free_space = (!free.is_empty()).then(|| free.iter().sum());
+        // "Battery Level(0x5001):(read only) (type=0x2) Range [0 - 100, step 1] value: 75% (75)"
+        let mut battery = summary.lines()
+            .filter(|l| l.trim_start().starts_with("Battery Level"))
+            .find_map(|l| l.split_once("value: ").and_then(|(_, v)| v.split_whitespace().next()).map(str::to_owned));
+        if battery.is_none() {
+            for key in BATTERY_KEYS {
+                if let Some(config) = get_config(port, key).await? {
+                    battery = config.current;
+                    break;
+                }
+            }
+        }
+
+        self.apertures = query_choices(port, APERTURE_KEYS, parse_aperture).await?;
+        self.shutterspeeds = query_choices(port, SHUTTERSPEED_KEYS, parse_shutterspeed).await?;
+        self.isos = query_choices(port, ISO_KEYS, parse_iso).await?;
+        let names = |c: &Option<Choices>| c.as_ref().map(|c| c.values.iter().map(|(_, n)| n.clone()).collect()).unwrap_or_default();
+        let isos = self.isos.as_ref().map(|c| c.values.iter().map(|(v, _)| *v as u32).collect()).unwrap_or_default();
+        Ok(Status { model, battery, free_space, isos, shutterspeeds: names(&self.shutterspeeds), apertures: names(&self.apertures) })
+    }
+
+    /// Profile of the camera. Detects the camera first if `gphoto2.profile` is unset.
+    async fn profile(&mut self) -> Result<&'static Profile, GPhoto2Error> {
+        if self.profile.is_none() {
+            let model = match crate::CONFIG.gphoto2.profile {
+                Some(_) => None,
+                None => Some(model(self.port.as_deref()).await?),
+            };
+            self.select_profile(model.as_deref());
+        }
+        Ok(self.profile.unwrap())
+    }
+
+    /// Selects the profile set in `gphoto2.profile`, otherwise the one of `model`. Unknown models use canon.
+    fn select_profile(&mut self, model: Option<&str>) {
+        let profile = Profile::select(crate::CONFIG.gphoto2.profile, model);
+        if self.profile.map(|p| p.family) != Some(profile.family) {
+            info!("using the {family:?} profile for gphoto2 config values.", family = profile.family);
+        }
+        self.profile = Some(profile);
+    }
+
+    /// Probes the camera if it was not probed within `gphoto2.probe_interval`. Failures are logged.
+    async fn refresh(&mut self) {
+        let interval = crate::CONFIG.gphoto2.probe_interval;
+        match (self.probed, interval) {
+            (Some(probed), Some(interval)) if probed.elapsed() < interval => return,
+            (Some(_), None) => return,
+            _ => {},
+        }
+        self.probed = Some(Instant::now());
+        match self.probe().await {
+            Ok(status) => {
+                info!("camera is {status}.");
+                self.status = Some(status);
+            },
+            Err(e) => {
+                warn!("unable to probe the camera. {e}");
+                self.status = None;
+            },
+        }
+    }
+
+    /// Resets the port of the camera and detects it again after gphoto2 hung `self.hangs` times in a row.
+    /// Returns the error to report the failed capture with.
+    async fn recover(&mut self) -> GPhoto2Error {
+        let hangs = self.hangs;
+        warn!("gphoto2 hung {hangs} times in a row. resetting the camera.");
+        // the camera may come back with a different lens or settings.
+        self.apertures = None;
+        self.shutterspeeds = None;
+        self.compensations = None;
+        self.isos = None;
+        self.profile = None;
+        let port = self.port.clone();
+        // usb ports may change, the serial number finds the camera again.
+        if self.serial.is_some() { self.port = None; }
+        match reset(port.as_deref()).await {
+            Ok(models) => {
+                info!("camera detected again after the reset. {models}");
+                self.hangs = 0;
+                GPhoto2Error::Recovered(hangs)
+            },
+            Err(e) => {
+                error!("unable to recover the camera. {e}");
+                GPhoto2Error::Unrecoverable(hangs, Box::new(e))
+            },
+        }
+    }
+
+    /// `key=choice` to set the aperture to, `None` if it is left as it is.
+    async fn aperture(&mut self, aperture: &Aperture) -> Result<Option<String>, GPhoto2Error> {
+        let f = match aperture {
+            Aperture::Manual(f) => *f,
+            Aperture::Auto | Aperture::Implicit => return Ok(None),
+        };
+        for cached in [true, false] {
+            if !cached || self.apertures.is_none() {
+                self.apertures = Some(query_choices(self.port.as_deref(), APERTURE_KEYS, parse_aperture).await?
+                    .ok_or(GPhoto2Error::NoAperture)?);
+            }
+            match aperture_choice(self.apertures.as_ref().unwrap(), f) {
+                Err(_) if cached => debug!("f/{f} is not among the known apertures. querying the camera again."),
+                choice => return choice.map(Some),
+            }
+        }
+        unreachable!("the lookup after querying the camera returns.")
+    }
+
+    /// Times an exposure of `f` seconds with the nearest shutter speed of the camera.
+    /// Exposures longer than the longest shutter speed use bulb. Returns the exposure that will actually be made.
+    async fn shutter(&mut self, f: f64) -> Result<(Shutter, f64), GPhoto2Error> {
+        if self.shutterspeeds.is_none() {
+            let choices = query_choices(self.port.as_deref(), SHUTTERSPEED_KEYS, parse_shutterspeed).await?;
+            if choices.is_none() { warn!("camera offers none of {SHUTTERSPEED_KEYS:?}. all manual exposures use bulb."); }
+            self.shutterspeeds = Some(choices.unwrap_or(Choices { key: SHUTTERSPEED_KEYS[0], values: vec![], other: vec![] }));
+        }
+        Ok(time(self.shutterspeeds.as_ref().unwrap(), f))
+    }
+
+    /// `key=choice` to set the ISO to. A manual ISO has to be among the choices of the camera.
+    async fn iso(&mut self, iso: &Iso) -> Result<String, GPhoto2Error> {
+        if self.isos.is_none() {
+            self.isos = Some(query_choices(self.port.as_deref(), ISO_KEYS, parse_iso).await?
+                .unwrap_or(Choices { key: ISO_KEYS[0], values: vec![], other: vec![] }));
+        }
+        let isos = self.isos.as_ref().unwrap();
+        let key = isos.key;
+        match iso {
+            Iso::Auto => {
+                let auto = isos.other.iter().find(|c| c.eq_ignore_ascii_case("auto")).map_or("auto", |c| c.as_str());
+                Ok(format!("{key}={auto}"))
+            },
+            Iso::Manual(u) => match isos.values.iter().find(|(v, _)| *v == *u as f64) {
+                Some((_, choice)) => Ok(format!("{key}={choice}")),
+                // the camera does not tell, it has to accept it.
+                None if isos.values.is_empty() => Ok(format!("{key}={u}")),
+                None => Err(GPhoto2Error::Iso(*u, isos.values.iter().map(|(_, c)| c.as_str()).collect::<Vec<_>>().join(", "))),
+            },
+        }
+    }
+
+    /// `key=choice` of the exposure compensation nearest to `offset` EV.
+    async fn compensation(&mut self, offset: f64) -> Result<String, GPhoto2Error> {
+        if self.compensations.is_none() {
+            self.compensations = Some(query_choices(self.port.as_deref(), COMPENSATION_KEYS, |c| c.trim_start_matches('+').parse().ok()).await?
+                .ok_or(GPhoto2Error::NoCompensation)?);
+        }
+        let compensations = self.compensations.as_ref().unwrap();
+        let (v, choice) = compensations.values.iter()
+            .min_by(|(a, _), (b, _)| (a - offset).abs().total_cmp(&(b - offset).abs()))
+            .ok_or(GPhoto2Error::NoCompensation)?;
+        if (v - offset).abs() > 0.1 { warn!("exposure compensation of {offset} EV is not available. using the nearest, {choice}."); }
+        Ok(format!("{key}={choice}", key = compensations.key))
+    }
+
+    /// Captures a single frame with `settings`. `offset` is the EV offset within a bracket, `None` outside of one.
+    async fn capture_frame(&mut self, cancel_token: &Notify, mut settings: DNTime, offset: Option<f64>) -> Result<Frame, GPhoto2Error> {
+        let profile = self.profile().await?;
+        let aperture = self.aperture(&settings.aperture).await?;
+        let iso = self.iso(&settings.iso).await?;
+        let (shutter, compensation) = match (settings.exposure, offset) {
+            (Exposure::Manual(f), _) => {
+                let (shutter, actual) = self.shutter(f).await?;
+                // reported in the result.
+                settings.exposure = Exposure::Manual(actual);
+                (Some(shutter), None)
+            },
+            // also resets the compensation for the base frame.
+            (Exposure::Auto, Some(offset)) => (None, Some(self.compensation(offset).await?)),
+            (Exposure::Auto, None) => (None, None),
+        };
+        let mut downloads = do_capture(cancel_token, self.port.as_deref(), profile, &settings, aperture, iso, shutter, compensation).await?;
+
+        // a jpg is only the preview if there is a raw as well.
+        let jpg = match downloads.iter().position(|(_, t)| *t == FileType::Jpeg) {
+            Some(i) if downloads.len() > 1 => Some(downloads.remove(i).0),
+            _ => None,
+        };
+        if crate::CONFIG.preview.enable && jpg.is_none() {
+            warn!("camera did not store a jpg alongside the raw. check preview.imageformat.");
+        }
+        if downloads.len() > 1 {
+            warn!("gphoto2 downloaded {n} files. only the first is kept.", n = downloads.len());
+        }
+        let (file, file_type) = downloads.swap_remove(0);
+        Ok(Frame { file, file_type, jpg, settings })
+    }
+
+    /// Captures the frame, its subframes and its bracket.
+    async fn capture_all(&mut self, cmd: CaptureCommand) -> Result<Captured, GPhoto2Error> {
+        let CaptureCommand { cancel_token, time, is_night, settings } = cmd;
+        let start = Instant::now();
+        let uuid = Uuid::new_v4();
+        self.locate().await?;
+
+        let offsets = settings.bracket.as_ref().map(|b| b.offsets.clone());
+        let Frame { file, file_type, jpg, settings: base } = self.capture_frame(&cancel_token, settings.clone(), offsets.as_ref().map(|_| 0.0)).await?;
+
+        let mut subframes = vec![];
+        let n = settings.stack.map_or(1, |s| s.subframes);
+        for i in 1..n {
+            debug!("capturing subframe {} of {n}.", i + 1);
+            let frame = self.capture_frame(&cancel_token, settings.clone(), offsets.as_ref().map(|_| 0.0)).await?;
+            if frame.file_type != file_type { warn!("subframe {} is a {t:?}, the first one a {file_type:?}.", i + 1, t = frame.file_type); }
+            subframes.push(frame.file);
+        }
+        if !subframes.is_empty() {
+            info!("{n} subframes complete after {t:.1} seconds.", t = start.elapsed().as_secs_f64());
+        }
+
+        let mut bracket = vec![];
+        for offset in offsets.unwrap_or_default() {
+            debug!("capturing bracket frame at {offset:+} EV.");
+            let frame = self.capture_frame(&cancel_token, settings.offset(offset), Some(offset)).await?;
+            if frame.file_type != file_type { warn!("bracket frame at {offset:+} EV is a {t:?}, the base frame a {file_type:?}.", t = frame.file_type); }
+            bracket.push(Bracketed { offset, file: frame.file, settings: Some(frame.settings) });
+        }
+        if !bracket.is_empty() {
+            info!("bracket of {n} frames complete after {t:.1} seconds.", n = bracket.len() + 1, t = start.elapsed().as_secs_f64());
+        }
+
+        // only the base frame is previewed.
+        let preview = jpg.map(|jpg| CaptureResult { uuid: Uuid::new_v4(), time, is_night, file_type: FileType::Jpeg, file: jpg, settings: Some(base.clone()), bracket: vec![], subframes: vec![], kind: FrameKind::Light, raw: Some(uuid), camera: None });
+        let result = CaptureResult { uuid, time, is_night, file_type, file, settings: Some(base), bracket, subframes, kind: FrameKind::Light, raw: None, camera: None };
+        Ok(Captured { result, preview })
     }
 }
 
 #[async_trait]
 impl Capture for GPhoto2 {
-    async fn capture(&mut self, cmd: CaptureCommand) -> Result<CaptureResult, CaptureError> {
-        let CaptureCommand { cancel_token, time, is_night, settings } = cmd;
-        do_capture(cancel_token, time, is_night, settings).await
+    async fn capture(&mut self, cmd: CaptureCommand) -> Result<Captured, CaptureError> {
+        self.refresh().await;
+        match self.capture_all(cmd).await {
+            Ok(captured) => {
+                self.hangs = 0;
+                Ok(captured)
+            },
+            Err(e @ GPhoto2Error::TimedOut(_)) => {
+                self.hangs += 1;
+                let recover_after = crate::CONFIG.gphoto2.recover_after;
+                if recover_after > 0 && self.hangs >= recover_after {
+                    Err(self.recover().await.into_capture())
+                } else {
+                    Err(e.into_capture())
+                }
+            },
+            Err(e) => Err(e.into_capture()),
+        }
+    }
+
+    fn file_types(&self) -> Vec<FileType> {
+        FileType::ALL.iter().copied().filter(|t| *t != FileType::Dummy).collect()
+    }
+
+    async fn status(&mut self) -> Option<Status> {
+        self.refresh().await;
+        self.status.clone()
     }
 }
 
-async fn do_capture(cancel_token: Arc<Notify>, time: DateTime<Local>, is_night: bool, settings: DNTime) -> Result<CaptureResult, CaptureError> {
+/// Returns the downloaded files and their types, detected from their magic bytes or else their extensions.
+/// There is more than one if the camera stores e.g. RAW+JPEG.
+#[allow(clippy::too_many_arguments)]
+async fn do_capture(cancel_token: &Notify, port: Option<&str>, profile: &Profile, settings: &DNTime, aperture: Option<String>, iso: String, shutter: Option<Shutter>, compensation: Option<String>) -> Result<Vec<(Vec<u8>, FileType)>, GPhoto2Error> {
     debug!("capturing...");
     let start = Instant::now();
-    
-    let uuid = Uuid::new_v4();
 
-    let tmp_path = &crate::CONFIG.general.tmp_path;
-    let filename = format!("capture.cr2");
-    let filepath = PathBuf::from(tmp_path).join(&filename);
-
-    fs::create_dir_all(tmp_path).await
-        .map_err(|e| CaptureError::Module(Box::new(GPhoto2Error::IO(e))) )?;
+    let config = &crate::CONFIG.gphoto2;
+    // unique, so that neither leftovers of a failed capture nor other captures are taken for this one.
+    let dir = (download() == Download::File).then(|| config.download_path.join(Uuid::new_v4().to_string()));
 
     let mut args = vec![];
-    args.push(format!("--force-overwrite"));
-    args.push(format!("--filename")); args.push(filename.clone());
+    match dir {
+        // %C is the extension of the file on the camera.
+        Some(_) => { args.push(String::from("--filename")); args.push(String::from("capture.%C")); },
+        None => args.push(String::from("--stdout")),
+    }
 
-    args.append(&mut generate_args(&settings));
+    args.append(&mut generate_args(profile, settings, aperture, iso, shutter, compensation));
 
     //debug!("running command: \"gphoto2 {}\"", args.join(" "));
 
-    let mut child = Command::new("gphoto2")
-        .current_dir(&tmp_path)
-        .args(args)
-        .stdin(Stdio::null())
-        .stdout(Stdio::null())
-        .spawn()
-        .map_err(|e| CaptureError::Module(Box::new(GPhoto2Error::Process(e))) )?;
-
-    tokio::select! {
-        exit = child.wait() => {
-            let code = exit.map_err(|e| CaptureError::Module(Box::new(GPhoto2Error::Process(e))) )?
-                .code();
-            if code.is_none() || code.unwrap() != 0 {
-                error!("gphoto2 did exit with exit code: {}", if code.is_none() { format!("none") } else { code.unwrap().to_string() });
-                return Err(CaptureError::Module(Box::new(GPhoto2Error::GPhoto2)));
-            }
-        },
-        () = cancel_token.notified() => {
-            if let Some(id) = child.id() {
-                debug!("sending SIGTERM to gphoto2 {id}");
-                Command::new("kill")
-                    .arg(format!("-SIGTERM"))
-                    .arg(format!("{id}"))
-                    .stdin(Stdio::null())
-                    .stdout(Stdio::null())
-                    .output()
-                    .await
-                    .map_err(|e| CaptureError::Module(Box::new(GPhoto2Error::Process(e))) )?;
-                child.wait().await
-                    .map_err(|e| CaptureError::Module(Box::new(GPhoto2Error::Process(e))) )?;
-                return Err(CaptureError::Cancelled);
-            } else {
-                return Err(CaptureError::Cancelled);
-            }
+    let exposure = match settings.exposure {
+        Exposure::Manual(f) => f,
+        Exposure::Auto => AUTO_EXPOSURE,
+    };
+    let files = run_capture(cancel_token, port, args, exposure, dir.as_deref()).await;
+    if let Some(dir) = &dir {
+        if let Err(e) = fs::remove_dir_all(dir).await {
+            if e.kind() != std::io::ErrorKind::NotFound { warn!("unable to remove {dir:?}. {e}"); }
         }
     }
+    let files = files?;
 
-    let file = fs::read(&filepath).await
-        .map_err(|e| CaptureError::Module(Box::new(GPhoto2Error::IO(e))) )?;
+    if files.is_empty() {
+        return Err(GPhoto2Error::NoDownload);
+    }
+    for (file, _) in &files {
+        check_size(file.len() as u64, config.min_size, config.max_size)?;
+    }
 
-    fs::remove_file(&filepath).await
-        .map_err(|e| CaptureError::Module(Box::new(GPhoto2Error::IO(e))) )?;
+    let types = files.iter().map(|(f, t)| format!("{t:?} of {size} bytes", size = f.len())).collect::<Vec<_>>().join(", ");
+    info!("capture complete after {:.1} seconds. {types}.", start.elapsed().as_secs_f64());
 
-    info!("capture complete after {:.1} seconds.", start.elapsed().as_secs_f64());
-    
-    Ok(CaptureResult { uuid, time, is_night, file_type: FileType::Cr2, file })
+    Ok(files)
+}
+
+/// How this capture downloads, see `gphoto2.download`.
+fn download() -> Download {
+    match crate::CONFIG.gphoto2.download {
+        Download::Stdout if crate::CONFIG.preview.enable => Download::File,
+        download => download,
+    }
+}
+
+/// Runs gphoto2 with `args` and reads the downloads from its output, or from `dir` if it downloads into files.
+async fn run_capture(cancel_token: &Notify, port: Option<&str>, args: Vec<String>, exposure: f64, dir: Option<&Path>) -> Result<Vec<(Vec<u8>, FileType)>, GPhoto2Error> {
+    let mut command = Command::new("gphoto2");
+    if let Some(port) = port { command.arg("--port").arg(port); }
+    command.args(args);
+    match dir {
+        Some(dir) => {
+            fs::create_dir_all(dir).await
+                .map_err(GPhoto2Error::IO)?;
+            command.current_dir(dir).stdout(Stdio::null());
+        },
+        None => { command.stdout(Stdio::piped()); },
+    }
+    let output = process::run(command, cancel_token, &limits(exposure)).await?;
+    let code = output.status.code();
+    if code != Some(0) {
+        error!("gphoto2 did exit with exit code: {}", code.map_or(String::from("none"), |c| c.to_string()));
+        return Err(GPhoto2Error::GPhoto2);
+    }
+
+    let Some(dir) = dir else {
+        if output.stdout.is_empty() { return Ok(vec![]); }
+        let file_type = FileType::detect(&output.stdout)
+            .ok_or_else(|| GPhoto2Error::UnknownFileType(String::from("read from stdout")))?;
+        return Ok(vec![(output.stdout, file_type)]);
+    };
+
+    let mut files = vec![];
+    for filepath in downloads(dir).await? {
+        let size = fs::metadata(&filepath).await.map_err(GPhoto2Error::IO)?.len();
+        // the lower bound is checked once all files are read.
+        check_size(size, 0, crate::CONFIG.gphoto2.max_size)?;
+        let file = fs::read(&filepath).await
+            .map_err(GPhoto2Error::IO)?;
+
+        let file_type = match FileType::detect(&file) {
+            Some(t) => t,
+            None => {
+                let t = filepath.extension().and_then(|e| FileType::from_ext(&e.to_string_lossy()))
+                    .ok_or_else(|| GPhoto2Error::UnknownFileType(format!("{filepath:?}")))?;
+                warn!("file type of {filepath:?} cannot be detected. going by its extension.");
+                t
+            },
+        };
+        files.push((file, file_type));
+    }
+    Ok(files)
+}
+
+/// Refuses a download of `size` bytes outside of `min..=max`, see `gphoto2.min_size` and `gphoto2.max_size`.
+fn check_size(size: u64, min: usize, max: usize) -> Result<(), GPhoto2Error> {
+    if (min as u64..=max as u64).contains(&size) { Ok(()) }
+    else { Err(GPhoto2Error::Size(size as usize)) }
+}
+
+/// Files downloaded by gphoto2 into `dir`, whatever their extension. Sorted by name.
+async fn downloads(dir: &Path) -> Result<Vec<PathBuf>, GPhoto2Error> {
+    let mut downloads = vec![];
+    let mut entries = fs::read_dir(dir).await
+        .map_err(GPhoto2Error::IO)?;
+    while let Some(entry) = entries.next_entry().await.map_err(GPhoto2Error::IO)? {
+        let path = entry.path();
+        if path.file_stem().is_some_and(|s| s == "capture") && path.is_file() {
+            downloads.push(path);
+        }
+    }
+    downloads.sort();
+    Ok(downloads)
 }
 
-fn generate_args(settings: &DNTime) -> Vec<String> {
+/// `aperture` is the `key=choice` for `Aperture::Manual`, see `GPhoto2::aperture`. `iso` the one of the ISO, see `GPhoto2::iso`.
+/// `shutter` times `Exposure::Manual`, see `GPhoto2::shutter`.
+/// `compensation` is the `key=choice` of the exposure compensation of `Exposure::Auto`, see `GPhoto2::compensation`.
+fn generate_args(profile: &Profile, settings: &DNTime, aperture: Option<String>, iso: String, shutter: Option<Shutter>, compensation: Option<String>) -> Vec<String> {
     let mut args = vec![];
     let mut mode_args = vec![];
     let mut capture_args = vec![];
+    let program = |mode: &str| format!("{key}={mode}", key = profile.program_key);
 
-    let DNTime { frame: _, exposure, iso, aperture } = settings;
-
-    // TODO: Aperture
-    
-    match (exposure, aperture) {
-        (Exposure::Manual(f), _) => {
-            mode_args.push(format!("--set-config-value")); mode_args.push(format!("autoexposuremode=Manual"));
-            capture_args.push(String::from("--set-config")); capture_args.push(String::from("bulb=1"));
-            capture_args.push(format!("--wait-event={}ms", f * 1000.0));
-            capture_args.push(String::from("--set-config")); capture_args.push(String::from("bulb=0"));
-            capture_args.push(String::from("--wait-event-and-download=2s"));
+    match (shutter, &aperture) {
+        (Some(shutter), _) => {
+            if settings.aperture == Aperture::Auto {
+                debug!("aperture cannot be automatic with a manual exposure. keeping the aperture set on the camera.");
+            }
+            mode_args.push(String::from("--set-config-value")); mode_args.push(program(profile.manual));
+            match shutter {
+                Shutter::Native(speed) => {
+                    mode_args.push(String::from("--set-config-value")); mode_args.push(speed);
+                    capture_args.push(String::from("--capture-image-and-download"));
+                },
+                Shutter::Bulb(f, bulb) => {
+                    if let Some(bulb) = bulb {
+                        mode_args.push(String::from("--set-config-value")); mode_args.push(bulb);
+                    }
+                    match profile.bulb {
+                        Bulb::Toggle(key) => {
+                            capture_args.push(String::from("--set-config")); capture_args.push(format!("{key}=1"));
+                            capture_args.push(format!("--wait-event={}ms", f * 1000.0));
+                            capture_args.push(String::from("--set-config")); capture_args.push(format!("{key}=0"));
+                            capture_args.push(String::from("--wait-event-and-download=2s"));
+                        },
+                        Bulb::Timed => {
+                            // whole seconds only.
+                            capture_args.push(format!("--bulb={f:.0}"));
+                            capture_args.push(String::from("--capture-image-and-download"));
+                        },
+                    }
+                },
+            }
+        },
+        (None, None) if settings.aperture == Aperture::Auto => {
+            // program mode, the camera chooses exposure and aperture.
+            mode_args.push(String::from("--set-config-value")); mode_args.push(program(profile.program));
+            capture_args.push(String::from("--capture-image-and-download"));
         },
-        (Exposure::Auto, _) => {
-            mode_args.push(format!("--set-config-value")); mode_args.push(format!("autoexposuremode=AV"));
+        (None, _) => {
+            mode_args.push(String::from("--set-config-value")); mode_args.push(program(profile.aperture_priority));
             capture_args.push(String::from("--capture-image-and-download"));
         },
     }
 
     args.append(&mut mode_args);
 
-    args.push(format!("--set-config-value")); args.push(format!("imageformat=RAW", ));
-
-    match iso {
-        Iso::Auto => { args.push(format!("--set-config-value")); args.push(format!("iso=auto", )); },
-        Iso::Manual(u) => { args.push(format!("--set-config-value")); args.push(format!("iso={u}", )); },
+    // the mode has to be set first, the aperture is read-only in program mode.
+    if let Some(aperture) = aperture {
+        args.push(String::from("--set-config-value")); args.push(aperture);
+    }
+    if let Some(compensation) = compensation {
+        args.push(String::from("--set-config-value")); args.push(compensation);
     }
 
+    let preview = &crate::CONFIG.preview;
+    let imageformat = if preview.enable { preview.imageformat.as_deref().unwrap_or(profile.raw_jpeg) } else { profile.raw };
+    args.push(String::from("--set-config-value")); args.push(format!("{key}={imageformat}", key = profile.imageformat_key));
+
+    args.push(String::from("--set-config-value")); args.push(iso);
+
     args.append(&mut capture_args);
 
     args
-}
\ No newline at end of file
+}
+
+/// Choices of the first of `keys` the camera has, parsed with `parse`. `None` if it has none of them.
+async fn query_choices(port: Option<&str>, keys: &'static [&'static str], parse: fn(&str) -> Option<f64>) -> Result<Option<Choices>, GPhoto2Error> {
+    for key in keys {
+        if let Some(config) = get_config(port, key).await? {
+            let (values, other): (Vec<_>, Vec<_>) = config.choices.into_iter()
+                .map(|c| (parse(&c), c))
+                .partition(|(v, _)| v.is_some_and(f64::is_finite));
+            let values: Vec<(f64, String)> = values.into_iter().map(|(v, c)| (v.unwrap(), c)).collect();
+            let other = other.into_iter().map(|(_, c)| c).collect();
+            debug!("camera offers {values:?} and {other:?} as {key}.");
+            return Ok(Some(Choices { key, values, other }));
+        }
+    }
+    Ok(None)
+}
+
+/// Times an exposure of `f` seconds with the nearest of `speeds`, see `GPhoto2::shutter`.
+fn time(speeds: &Choices, f: f64) -> (Shutter, f64) {
+    let longest = speeds.values.iter().map(|(v, _)| *v).fold(0.0, f64::max);
+    if f > longest {
+        let bulb = speeds.other.iter().find(|c| c.eq_ignore_ascii_case("bulb")).map(|c| format!("{key}={c}", key = speeds.key));
+        return (Shutter::Bulb(f, bulb), f);
+    }
+    let (v, choice) = speeds.values.iter()
+        .min_by(|(a, _), (b, _)| (a.log2() - f.log2()).abs().total_cmp(&(b.log2() - f.log2()).abs()))
+        .unwrap();
+    if (v - f).abs() > f * 0.01 { debug!("exposure of {f}s is timed with the nearest shutter speed {choice}."); }
+    (Shutter::Native(format!("{key}={choice}", key = speeds.key)), *v)
+}
+
+/// `key=choice` of the aperture within 0.05 of f/`f`, see `GPhoto2::aperture`.
+fn aperture_choice(apertures: &Choices, f: f64) -> Result<String, GPhoto2Error> {
+    match apertures.values.iter().find(|(c, _)| (c - f).abs() < 0.05) {
+        Some((_, choice)) => Ok(format!("{key}={choice}", key = apertures.key)),
+        None => {
+            let available = apertures.values.iter().map(|(_, c)| c.as_str()).collect::<Vec<_>>().join(", ");
+            Err(GPhoto2Error::Aperture(f, available))
+        },
+    }
+}
+
+/// "f/5.6" or "5.6"
+fn parse_aperture(choice: &str) -> Option<f64> {
+    choice.trim_start_matches("f/").parse().ok().filter(|f: &f64| *f > 0.0)
+}
+
+/// "800". "Auto" and extended values like "Hi 1" are not numeric.
+fn parse_iso(choice: &str) -> Option<f64> {
+    choice.parse::<u32>().ok().filter(|u| *u > 0).map(f64::from)
+}
+
+/// "1/250", "0.3" or "30"
+fn parse_shutterspeed(choice: &str) -> Option<f64> {
+    let choice = choice.trim_end_matches('s');
+    let value = match choice.split_once('/') {
+        Some((n, d)) => n.parse::<f64>().ok()? / d.parse::<f64>().ok()?,
+        None => choice.parse().ok()?,
+    };
+    (value.is_finite() && value > 0.0).then_some(value)
+}
+
+/// Limits of a gphoto2 run that exposes for `exposure` seconds, see `gphoto2.timeout`.
+fn limits(exposure: f64) -> Limits {
+    let config = &crate::CONFIG.gphoto2;
+    Limits { timeout: Some(config.timeout + Duration::from_secs_f64(exposure)), grace_period: config.grace_period, max_output: Some(config.max_size as u64) }
+}
+
+/// Runs gphoto2 with `args` that do not capture on the camera at `port` and returns its output.
+async fn query(port: Option<&str>, args: &[&str]) -> Result<std::process::Output, GPhoto2Error> {
+    let mut command = Command::new("gphoto2");
+    if let Some(port) = port { command.arg("--port").arg(port); }
+    command.args(args)
+        .stdout(Stdio::piped());
+    // never cancelled, but limited by gphoto2.timeout.
+    Ok(process::run(command, &Notify::new(), &limits(0.0)).await?)
+}
+
+/// Resets the port of the camera and returns the models detected afterwards.
+async fn reset(port: Option<&str>) -> Result<String, GPhoto2Error> {
+    let output = query(port, &["--reset"]).await?;
+    if !output.status.success() {
+        warn!("gphoto2 --reset did exit with exit code: {}", output.status.code().map_or(String::from("none"), |c| c.to_string()));
+    }
+    Ok(detect().await?.into_iter().map(|(model, port)| format!("{model} at {port}")).collect::<Vec<_>>().join(", "))
+}
+
+/// Models and ports of the attached cameras. At least one.
+async fn detect() -> Result<Vec<(String, String)>, GPhoto2Error> {
+    let output = query(None, &["--auto-detect"]).await?;
+    // a header and a line of dashes precede the "model   port" lines.
+    let cameras: Vec<(String, String)> = String::from_utf8_lossy(&output.stdout).lines()
+        .skip_while(|l| !l.starts_with("---"))
+        .skip(1)
+        .filter_map(|l| l.trim().rsplit_once(char::is_whitespace).map(|(model, port)| (model.trim().to_owned(), port.to_owned())))
+        .filter(|(m, _)| !m.is_empty())
+        .collect();
+    if cameras.is_empty() {
+        return Err(GPhoto2Error::NotDetected);
+    }
+    Ok(cameras)
+}
+
+/// Model of the camera at `port`. The first detected camera if `port` is `None`.
+async fn model(port: Option<&str>) -> Result<String, GPhoto2Error> {
+    let mut cameras = detect().await?;
+    match port {
+        Some(port) => cameras.into_iter().find(|(_, p)| p == port).map(|(model, _)| model).ok_or_else(|| GPhoto2Error::NotAt(port.to_owned())),
+        None => {
+            if cameras.len() > 1 { warn!("gphoto2 detected {n} cameras. it uses the first, {first}.", n = cameras.len(), first = cameras[0].0); }
+            Ok(cameras.swap_remove(0).0)
+        },
+    }
+}
+
+/// A config value of the camera.
+struct Config {
+    current: Option<String>,
+    /// choices of radio and menu values.
+    choices: Vec<String>,
+}
+
+/// Current value and choices of a config value. `None` if the camera does not have it.
+async fn get_config(port: Option<&str>, key: &str) -> Result<Option<Config>, GPhoto2Error> {
+    let output = query(port, &["--get-config", key]).await?;
+    if !output.status.success() {
+        return Ok(None);
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    // "Current: 5.6"
+    let current = stdout.lines().find_map(|l| l.strip_prefix("Current: ")).map(str::to_owned);
+    // "Choice: 3 5.6"
+    let choices = stdout.lines()
+        .filter_map(|l| l.strip_prefix("Choice: "))
+        .filter_map(|l| l.split_once(' ').map(|(_, c)| c.to_owned()))
+        .collect();
+    Ok(Some(Config { current, choices }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn speeds(choices: &[&str]) -> Choices {
+        let (values, other): (Vec<_>, Vec<_>) = choices.iter().map(|c| (parse_shutterspeed(c), c.to_string())).partition(|(v, _)| v.is_some());
+        Choices { key: "shutterspeed", values: values.into_iter().map(|(v, c)| (v.unwrap(), c)).collect(), other: other.into_iter().map(|(_, c)| c).collect() }
+    }
+
+    #[test]
+    fn parse_shutterspeeds() {
+        assert_eq!(parse_shutterspeed("1/250"), Some(0.004));
+        assert_eq!(parse_shutterspeed("0.3"), Some(0.3));
+        assert_eq!(parse_shutterspeed("2.5s"), Some(2.5));
+        assert_eq!(parse_shutterspeed("30"), Some(30.0));
+        assert_eq!(parse_shutterspeed("bulb"), None);
+        assert_eq!(parse_shutterspeed("0"), None);
+        assert_eq!(parse_shutterspeed("1/0"), None);
+    }
+
+    #[test]
+    fn time_with_nearest_speed() {
+        let speeds = speeds(&["bulb", "30", "15", "1", "1/250", "1/4000"]);
+        match time(&speeds, 0.005) {
+            (Shutter::Native(s), v) => assert_eq!((s.as_str(), v), ("shutterspeed=1/250", 0.004)),
+            _ => panic!("expected a native shutter speed"),
+        }
+        match time(&speeds, 20.0) {
+            (Shutter::Native(s), v) => assert_eq!((s.as_str(), v), ("shutterspeed=15", 15.0)),
+            _ => panic!("expected a native shutter speed"),
+        }
+    }
+
+    #[test]
+    fn time_beyond_longest_speed_with_bulb() {
+        match time(&speeds(&["bulb", "30", "1"]), 60.0) {
+            (Shutter::Bulb(f, bulb), v) => assert_eq!((f, bulb.as_deref(), v), (60.0, Some("shutterspeed=bulb"), 60.0)),
+            _ => panic!("expected bulb"),
+        }
+        match time(&speeds(&[]), 0.5) {
+            (Shutter::Bulb(f, bulb), _) => assert_eq!((f, bulb), (0.5, None)),
+            _ => panic!("expected bulb"),
+        }
+    }
+
+    #[test]
+    fn parse_isos_and_apertures() {
+        assert_eq!(parse_iso("800"), Some(800.0));
+        assert_eq!(parse_iso("Auto"), None);
+        assert_eq!(parse_iso("Hi 1"), None);
+        assert_eq!(parse_aperture("f/5.6"), Some(5.6));
+        assert_eq!(parse_aperture("8"), Some(8.0));
+        assert_eq!(parse_aperture("implicit auto"), None);
+    }
+
+    #[test]
+    fn aperture_within_tolerance() {
+        let apertures = Choices {
+            key: "f-number",
+            values: ["f/2.8", "f/5.6", "f/8"].iter().map(|c| (parse_aperture(c).unwrap(), c.to_string())).collect(),
+            other: vec![String::from("implicit auto")],
+        };
+        assert_eq!(aperture_choice(&apertures, 5.6).unwrap(), "f-number=f/5.6");
+        assert_eq!(aperture_choice(&apertures, 2.83).unwrap(), "f-number=f/2.8");
+        assert_eq!(aperture_choice(&apertures, 7.96).unwrap(), "f-number=f/8");
+        match aperture_choice(&apertures, 4.0) {
+            Err(e @ GPhoto2Error::Aperture(..)) => assert_eq!(e.to_string(), "camera does not offer aperture f/4. available: f/2.8, f/5.6, f/8"),
+            other => panic!("expected GPhoto2Error::Aperture, got {other:?}"),
+        }
+        assert!(matches!(aperture_choice(&apertures, 5.66), Err(GPhoto2Error::Aperture(..))));
+    }
+
+    #[test]
+    fn check_download_sizes() {
+        assert!(check_size(1000, 1000, 5000).is_ok());
+        assert!(check_size(3000, 1000, 5000).is_ok());
+        assert!(check_size(5000, 1000, 5000).is_ok());
+        assert!(matches!(check_size(999, 1000, 5000), Err(GPhoto2Error::Size(999))));
+        assert!(matches!(check_size(0, 1000, 5000), Err(GPhoto2Error::Size(0))));
+        assert!(matches!(check_size(5001, 1000, 5000), Err(GPhoto2Error::Size(5001))));
+    }
+}
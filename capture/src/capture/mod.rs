@@ -1,16 +1,23 @@
 mod dummy;
 mod gphoto2;
+mod replay;
+mod command;
+mod process;
+mod profile;
+mod synthetic;
 
 use std::sync::Arc;
 
 use async_trait::async_trait;
 use chrono::{DateTime, Local};
-use common::capture::settings::dntime::DNTime;
+use common::capture::{settings::dntime::DNTime, FileType};
 use thiserror::Error;
 use tokio::sync::Notify;
 
 use dummy::Dummy;
-use crate::config::general::CaptureModule;
+use replay::Replay;
+use command::Command;
+use crate::config::{cameras, general::CaptureModule};
 
 #[derive(Debug)]
 pub struct CaptureCommand {
@@ -22,6 +29,45 @@ pub struct CaptureCommand {
 
 pub use common::capture::CaptureResult;
 
+/// A captured frame and the jpg the camera developed alongside it, see `preview.enable`.
+pub struct Captured {
+    pub result: CaptureResult,
+    /// `CaptureResult::raw` is the uuid of `result`.
+    pub preview: Option<CaptureResult>,
+}
+
+/// What the attached camera reported when it was last probed.
+#[derive(Debug, Clone)]
+pub struct Status {
+    pub model: String,
+    /// as the camera reports it, e.g. "75%".
+    pub battery: Option<String>,
+    /// in bytes, summed over all storages.
+    pub free_space: Option<u64>,
+    /// manual ISO the camera offers.
+    pub isos: Vec<u32>,
+    /// shutter speeds and apertures as the camera names them.
+    pub shutterspeeds: Vec<String>,
+    pub apertures: Vec<String>,
+}
+
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}, battery {}", self.model, self.battery.as_deref().unwrap_or("unknown"))?;
+        match self.free_space {
+            Some(b) => write!(f, ", {:.1}GiB free", b as f64 / (1024.0 * 1024.0 * 1024.0))?,
+            None => write!(f, ", free space unknown")?,
+        }
+        write!(f, ", ISO {:?}, shutter speeds {:?}, apertures {:?}", self.isos, self.shutterspeeds, self.apertures)
+    }
+}
+
+impl From<CaptureResult> for Captured {
+    fn from(result: CaptureResult) -> Captured {
+        Captured { result, preview: None }
+    }
+}
+
 use self::gphoto2::GPhoto2;
 
 
@@ -34,14 +80,56 @@ pub enum CaptureError {
     Module(Box<dyn std::error::Error>)
 }
 
-pub fn build() -> Box<dyn Capture> {
-    match crate::CONFIG.general.module {
+/// A camera of the node, see `cameras`.
+pub struct Camera {
+    /// `None` for the single camera of `general.module`.
+    pub id: Option<String>,
+    /// key of its settings on the processor. `None` uses the settings of the node.
+    pub settings: Option<String>,
+    pub module: CaptureModule,
+    pub capture: Box<dyn Capture>,
+}
+
+impl std::fmt::Display for Camera {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.id {
+            Some(id) => write!(f, "camera {id}"),
+            None => write!(f, "camera"),
+        }
+    }
+}
+
+/// Builds the cameras of `cameras`, or the single camera of `general.module` if there are none.
+pub fn build() -> Vec<Camera> {
+    let config = &crate::CONFIG;
+    if config.cameras.is_empty() {
+        let module = config.general.module.clone();
+        return vec![Camera { id: None, settings: None, capture: capture(&module, None), module }];
+    }
+    config.cameras.iter()
+        .map(|c| Camera { id: Some(c.id.clone()), settings: c.settings.clone(), module: c.module.clone(), capture: capture(&c.module, Some(c)) })
+        .collect()
+}
+
+fn capture(module: &CaptureModule, camera: Option<&cameras::Camera>) -> Box<dyn Capture> {
+    match module {
         CaptureModule::Dummy => Box::new(Dummy::new()),
-        CaptureModule::GPhoto2 => Box::new(GPhoto2::new()),
+        CaptureModule::GPhoto2 => Box::new(GPhoto2::new(camera.and_then(|c| c.port.clone()), camera.and_then(|c| c.serial.clone()))),
+        CaptureModule::Replay => Box::new(Replay::new()),
+        CaptureModule::Command => Box::new(Command::new(camera.map(|c| c.id.as_str()), camera.and_then(|c| c.file_type).unwrap_or(crate::CONFIG.command.file_type))),
     }
 }
 
 #[async_trait]
-pub trait Capture {
-    async fn capture(&mut self, cmd: CaptureCommand) -> Result<CaptureResult, CaptureError>;
+pub trait Capture: Send {
+    /// Captures a light frame. Calibration frames are captured with adjusted settings and retagged by the caller.
+    async fn capture(&mut self, cmd: CaptureCommand) -> Result<Captured, CaptureError>;
+
+    /// file types this module may produce. announced to the processor during the handshake.
+    fn file_types(&self) -> Vec<FileType>;
+
+    /// Probes the attached camera. `None` if the module has no camera or it could not be probed.
+    async fn status(&mut self) -> Option<Status> {
+        None
+    }
 }
\ No newline at end of file
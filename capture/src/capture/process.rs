@@ -0,0 +1,179 @@
+use std::future::pending;
+use std::process::{Output, Stdio};
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::{debug, warn, error};
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::process::{Child, Command};
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, timeout};
+
+/// time a process gets to exit after SIGKILL before it is given up on.
+const KILL_WAIT: Duration = Duration::from_secs(5);
+
+#[derive(Error, Debug)]
+pub enum ProcessError {
+    #[error("cancelled.")]
+    Cancelled,
+
+    #[error("{0} did not exit within {1:?} and was killed.")]
+    TimedOut(String, Duration),
+
+    #[error("unable to run {0}. {1}")]
+    Spawn(String, std::io::Error),
+
+    #[error("unable to wait for {0}. {1}")]
+    Wait(String, std::io::Error),
+
+    #[error("{0} wrote more than {1} bytes to stdout and was killed.")]
+    OutputTooLarge(String, u64),
+}
+
+/// How long a process may run and how it is stopped.
+pub struct Limits {
+    /// the process is terminated after running this long. unlimited if `None`.
+    pub timeout: Option<Duration>,
+    /// time between SIGTERM and SIGKILL.
+    pub grace_period: Duration,
+    /// the process is terminated once it writes more than this many bytes to stdout. unlimited if `None`.
+    pub max_output: Option<u64>,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits { timeout: None, grace_period: Duration::from_secs(5), max_output: None }
+    }
+}
+
+/// Runs `command` until it exits, `cancel_token` is notified or it exceeds `limits.timeout`, which terminate it.
+/// It runs in its own process group, so that children of e.g. a wrapper script are terminated with it.
+/// Piped stdout and stderr are collected into the output, they are empty otherwise. Stdout is read no further than `limits.max_output`.
+pub async fn run(mut command: Command, cancel_token: &Notify, limits: &Limits) -> Result<Output, ProcessError> {
+    let name = command.as_std().get_program().to_string_lossy().to_string();
+    let mut child = command
+        .stdin(Stdio::null())
+        .process_group(0)
+        .spawn()
+        .map_err(|e| ProcessError::Spawn(name.clone(), e))?;
+    let exceeded = Arc::new(Notify::new());
+    let stdout = collect(child.stdout.take(), limits.max_output, exceeded.clone());
+    let stderr = collect(child.stderr.take(), None, exceeded.clone());
+
+    let deadline = async {
+        match limits.timeout {
+            Some(t) => sleep(t).await,
+            None => pending().await,
+        }
+    };
+
+    tokio::select! {
+        status = child.wait() => {
+            let status = status.map_err(|e| ProcessError::Wait(name.clone(), e))?;
+            Ok(Output { status, stdout: stdout.await.unwrap_or_default(), stderr: stderr.await.unwrap_or_default() })
+        },
+        () = exceeded.notified() => {
+            let max = limits.max_output.unwrap_or_default();
+            warn!("{name} wrote more than {max} bytes to stdout. terminating it.");
+            terminate(&mut child, &name, limits.grace_period).await?;
+            Err(ProcessError::OutputTooLarge(name, max))
+        },
+        () = cancel_token.notified() => {
+            terminate(&mut child, &name, limits.grace_period).await?;
+            Err(ProcessError::Cancelled)
+        },
+        () = deadline => {
+            let t = limits.timeout.unwrap_or_default();
+            warn!("{name} did not exit within {t:?}. terminating it.");
+            terminate(&mut child, &name, limits.grace_period).await?;
+            Err(ProcessError::TimedOut(name, t))
+        },
+    }
+}
+
+/// Sends SIGTERM to the process group of `child` and SIGKILL if it did not exit after `grace_period`.
+/// A process that does not even exit after SIGKILL, e.g. stuck in a USB transfer, is left behind.
+async fn terminate(child: &mut Child, name: &str, grace_period: Duration) -> Result<(), ProcessError> {
+    let Some(id) = child.id() else { return Ok(()) };
+    for (signal, signal_name, wait) in [(libc::SIGTERM, "SIGTERM", grace_period), (libc::SIGKILL, "SIGKILL", KILL_WAIT)] {
+        debug!("sending {signal_name} to {name} {id}.");
+        // the negative id addresses the process group.
+        if unsafe { libc::kill(-(id as libc::pid_t), signal) } != 0 {
+            debug!("unable to send {signal_name} to {name} {id}. {e}", e = std::io::Error::last_os_error());
+        }
+        match timeout(wait, child.wait()).await {
+            Ok(status) => {
+                status.map_err(|e| ProcessError::Wait(name.to_owned(), e))?;
+                return Ok(());
+            },
+            Err(_) => warn!("{name} {id} did not exit within {wait:?} after {signal_name}."),
+        }
+    }
+    error!("giving up on {name} {id}. it is left behind.");
+    Ok(())
+}
+
+/// Reads `pipe` to its end, but stops one byte past `max` and notifies `exceeded`.
+fn collect<R: AsyncRead + Unpin + Send + 'static>(pipe: Option<R>, max: Option<u64>, exceeded: Arc<Notify>) -> JoinHandle<Vec<u8>> {
+    tokio::spawn(async move {
+        let mut b = vec![];
+        if let Some(pipe) = pipe {
+            let limit = max.map_or(u64::MAX, |m| m.saturating_add(1));
+            if let Err(e) = pipe.take(limit).read_to_end(&mut b).await { debug!("unable to read output. {e}"); }
+            if max.is_some_and(|m| b.len() as u64 > m) { exceeded.notify_one(); }
+        }
+        b
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    fn sh(script: &str) -> Command {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(script).stdout(Stdio::piped());
+        command
+    }
+
+    #[tokio::test]
+    async fn run_collects_output() {
+        let output = run(sh("printf abc"), &Notify::new(), &Limits { max_output: Some(3), ..Limits::default() }).await.unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"abc");
+    }
+
+    #[tokio::test]
+    async fn run_times_out() {
+        let limits = Limits { timeout: Some(Duration::from_millis(100)), ..Limits::default() };
+        let start = Instant::now();
+        assert!(matches!(run(sh("sleep 60"), &Notify::new(), &limits).await, Err(ProcessError::TimedOut(_, t)) if t == Duration::from_millis(100)));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn terminate_escalates_to_sigkill() {
+        let limits = Limits { timeout: Some(Duration::from_millis(100)), grace_period: Duration::from_millis(300), max_output: None };
+        let start = Instant::now();
+        assert!(matches!(run(sh("trap '' TERM; sleep 60"), &Notify::new(), &limits).await, Err(ProcessError::TimedOut(..))));
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(400), "exited after {elapsed:?} despite ignoring SIGTERM");
+        assert!(elapsed < KILL_WAIT, "SIGKILL was not sent after {elapsed:?}");
+    }
+
+    #[tokio::test]
+    async fn run_caps_output() {
+        let limits = Limits { max_output: Some(1000), ..Limits::default() };
+        assert!(matches!(run(sh("yes"), &Notify::new(), &limits).await, Err(ProcessError::OutputTooLarge(_, 1000))));
+    }
+
+    #[tokio::test]
+    async fn run_is_cancelled() {
+        let cancel_token = Notify::new();
+        cancel_token.notify_one();
+        assert!(matches!(run(sh("sleep 60"), &cancel_token, &Limits::default()).await, Err(ProcessError::Cancelled)));
+    }
+}
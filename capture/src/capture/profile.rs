@@ -0,0 +1,122 @@
+use log::warn;
+
+use crate::config::gphoto2::Family;
+
+/// How the cameras of a family name their gphoto2 config values.
+pub struct Profile {
+    pub family: Family,
+    /// config key of the exposure program and its values.
+    pub program_key: &'static str,
+    pub manual: &'static str,
+    pub aperture_priority: &'static str,
+    pub program: &'static str,
+    pub bulb: Bulb,
+    /// config key of the image format and its values.
+    pub imageformat_key: &'static str,
+    pub raw: &'static str,
+    /// a raw and a jpg alongside it, see `preview.enable`.
+    pub raw_jpeg: &'static str,
+}
+
+/// how a bulb exposure is held open.
+pub enum Bulb {
+    /// setting the config key to 1 opens the shutter, setting it to 0 closes it.
+    Toggle(&'static str),
+    /// gphoto2 times the exposure itself with `--bulb`.
+    Timed,
+}
+
+pub const CANON: Profile = Profile {
+    family: Family::Canon,
+    program_key: "autoexposuremode",
+    manual: "Manual",
+    aperture_priority: "AV",
+    program: "P",
+    bulb: Bulb::Toggle("bulb"),
+    imageformat_key: "imageformat",
+    raw: "RAW",
+    raw_jpeg: "RAW + Large Fine JPEG",
+};
+
+pub const NIKON: Profile = Profile {
+    family: Family::Nikon,
+    program_key: "expprogram",
+    manual: "M",
+    aperture_priority: "A",
+    program: "P",
+    bulb: Bulb::Timed,
+    imageformat_key: "imagequality",
+    raw: "NEF (Raw)",
+    raw_jpeg: "NEF+Fine",
+};
+
+pub const SONY: Profile = Profile {
+    family: Family::Sony,
+    program_key: "expprogram",
+    manual: "M",
+    aperture_priority: "A",
+    program: "P",
+    bulb: Bulb::Toggle("bulb"),
+    imageformat_key: "imagequality",
+    raw: "RAW",
+    raw_jpeg: "RAW+JPEG",
+};
+
+impl Profile {
+    pub fn of(family: Family) -> &'static Profile {
+        match family {
+            Family::Canon => &CANON,
+            Family::Nikon => &NIKON,
+            Family::Sony => &SONY,
+        }
+    }
+
+    /// Profile of the family of `model` as gphoto2 --auto-detect names it. `None` if the family is unknown.
+    pub fn detect(model: &str) -> Option<&'static Profile> {
+        let model = model.to_lowercase();
+        [Family::Canon, Family::Nikon, Family::Sony].into_iter()
+            .find(|f| model.contains(&format!("{f:?}").to_lowercase()))
+            .map(Profile::of)
+    }
+
+    /// Profile of the `configured` family, otherwise the one of `model`. Unknown models use canon.
+    pub fn select(configured: Option<Family>, model: Option<&str>) -> &'static Profile {
+        match (configured, model) {
+            (Some(family), _) => Profile::of(family),
+            (None, Some(model)) => Profile::detect(model).unwrap_or_else(|| {
+                warn!("camera family of {model} is unknown. using the canon profile, set gphoto2.profile otherwise.");
+                &CANON
+            }),
+            (None, None) => &CANON,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn family(p: Option<&Profile>) -> Option<Family> {
+        p.map(|p| p.family)
+    }
+
+    #[test]
+    fn detect_models() {
+        assert_eq!(family(Profile::detect("Canon EOS 6D")), Some(Family::Canon));
+        assert_eq!(family(Profile::detect("Canon EOS R5")), Some(Family::Canon));
+        assert_eq!(family(Profile::detect("Nikon DSC D850")), Some(Family::Nikon));
+        assert_eq!(family(Profile::detect("Nikon Z6_2")), Some(Family::Nikon));
+        assert_eq!(family(Profile::detect("Sony Alpha-A7 III (Control)")), Some(Family::Sony));
+        assert_eq!(family(Profile::detect("SONY ILCE-7M3")), Some(Family::Sony));
+        assert_eq!(family(Profile::detect("Fuji FUJIFILM X-T3")), None);
+        assert_eq!(family(Profile::detect("")), None);
+    }
+
+    #[test]
+    fn select_falls_back_to_canon() {
+        assert_eq!(Profile::select(None, Some("Fuji FUJIFILM X-T3")).family, Family::Canon);
+        assert_eq!(Profile::select(None, None).family, Family::Canon);
+        assert_eq!(Profile::select(None, Some("Nikon DSC D850")).family, Family::Nikon);
+        assert_eq!(Profile::select(Some(Family::Sony), Some("Nikon DSC D850")).family, Family::Sony);
+    }
+}
@@ -0,0 +1,217 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use common::capture::{settings::dntime::{DNTime, Exposure}, calibration::FrameKind, Bracketed, FileType};
+use log::{info, debug, warn};
+use thiserror::Error;
+use tokio::{fs, time::sleep};
+use uuid::Uuid;
+
+use super::{ Capture, CaptureCommand, CaptureResult, CaptureError, Captured };
+use crate::config::replay::Order;
+
+/// Captures the images of `replay.directory` one after another.
+pub struct Replay {
+    files: Vec<PathBuf>,
+    next: usize,
+}
+
+#[derive(Error, Debug)]
+enum ReplayError {
+    #[error("all {0} images of {1:?} were replayed.")]
+    Exhausted(usize, PathBuf),
+
+    #[error("unable to read {0:?}. {1}")]
+    IO(PathBuf, std::io::Error),
+
+    #[error("file type of {0:?} is unknown.")]
+    UnknownFileType(PathBuf),
+}
+
+impl Replay {
+    pub fn new() -> Self {
+        let config = &crate::CONFIG.replay;
+        let files = list(&config.directory, config.order);
+        if files.is_empty() { warn!("replay directory {dir:?} holds no images.", dir = config.directory); }
+        info!("replaying {n} images of {dir:?} ordered by {order:?}.", n = files.len(), dir = config.directory, order = config.order);
+        debug!("replay order {files:?}.");
+        Replay { files, next: 0 }
+    }
+
+    /// Next image and its type.
+    async fn frame(&mut self, settings: &DNTime) -> Result<(Vec<u8>, FileType), ReplayError> {
+        if self.next >= self.files.len() {
+            if !crate::CONFIG.replay.repeat || self.files.is_empty() {
+                return Err(ReplayError::Exhausted(self.files.len(), crate::CONFIG.replay.directory.clone()));
+            }
+            info!("all images were replayed. starting over.");
+            self.next = 0;
+        }
+        let path = &self.files[self.next];
+        self.next += 1;
+
+        expose(settings).await;
+        let file = fs::read(path).await
+            .map_err(|e| ReplayError::IO(path.clone(), e))?;
+        let file_type = file_type(path, &file).ok_or_else(|| ReplayError::UnknownFileType(path.clone()))?;
+        debug!("replaying {path:?} as {file_type:?}.");
+        Ok((file, file_type))
+    }
+
+    async fn replay(&mut self, cmd: &CaptureCommand) -> Result<CaptureResult, ReplayError> {
+        let (file, file_type) = self.frame(&cmd.settings).await?;
+        let mut subframes = vec![];
+        for _ in 1..cmd.settings.stack.map_or(1, |s| s.subframes) {
+            subframes.push(self.frame(&cmd.settings).await?.0);
+        }
+        let mut bracket = vec![];
+        for offset in cmd.settings.bracket.iter().flat_map(|b| b.offsets.iter().copied()) {
+            let settings = cmd.settings.offset(offset);
+            let (file, _) = self.frame(&settings).await?;
+            bracket.push(Bracketed { offset, file, settings: Some(settings) });
+        }
+        Ok(CaptureResult { uuid: Uuid::new_v4(), time: cmd.time, is_night: cmd.is_night, file_type, file, settings: Some(cmd.settings.clone()), bracket, subframes, kind: FrameKind::Light, raw: None, camera: None })
+    }
+}
+
+#[async_trait]
+impl Capture for Replay {
+    async fn capture(&mut self, cmd: CaptureCommand) -> Result<Captured, CaptureError> {
+        debug!("replay received a capture command {cmd:?}");
+        tokio::select! {
+            res = self.replay(&cmd) => res.map(Captured::from).map_err(|e| CaptureError::Module(Box::new(e))),
+            () = cmd.cancel_token.notified() => Err(CaptureError::Cancelled),
+        }
+    }
+
+    fn file_types(&self) -> Vec<FileType> {
+        let mut types = self.files.iter().filter_map(|f| extension(f)).collect::<Vec<_>>();
+        types.sort_by_key(|t| FileType::ALL.iter().position(|a| a == t));
+        types.dedup();
+        if types.is_empty() { FileType::ALL.to_vec() } else { types }
+    }
+}
+
+/// Images of `dir` in replay order. Files are images if their extension is a known file type.
+fn list(dir: &Path, order: Order) -> Vec<PathBuf> {
+    let mut files = match std::fs::read_dir(dir) {
+        Ok(entries) => entries.flatten()
+            .map(|e| e.path())
+            .filter(|p| p.is_file() && extension(p).is_some())
+            .collect::<Vec<_>>(),
+        Err(e) => {
+            warn!("unable to read replay directory {dir:?}. {e}");
+            vec![]
+        },
+    };
+    files.sort();
+    if order == Order::Exif {
+        // stable, files without exif time keep their order by name at the end.
+        let mut keyed = files.into_iter().map(|f| (exif_time(&f), f)).collect::<Vec<_>>();
+        let missing = keyed.iter().filter(|(t, _)| t.is_none()).count();
+        if missing > 0 { warn!("{missing} images have no exif time. they are replayed last."); }
+        keyed.sort_by(|(a, _), (b, _)| (a.is_none(), a).cmp(&(b.is_none(), b)));
+        files = keyed.into_iter().map(|(_, f)| f).collect();
+    }
+    files
+}
+
+/// Type of `file`, detected from its magic bytes or else the extension of `path`.
+fn file_type(path: &Path, file: &[u8]) -> Option<FileType> {
+    FileType::detect(file).or_else(|| extension(path))
+}
+
+fn extension(path: &Path) -> Option<FileType> {
+    path.extension().and_then(|e| FileType::from_ext(&e.to_string_lossy()))
+}
+
+/// `DateTimeOriginal` with its sub seconds, in a format that sorts chronologically.
+fn exif_time(path: &Path) -> Option<String> {
+    let file = std::fs::File::open(path).ok()?;
+    let exif = exif::Reader::new().read_from_container(&mut std::io::BufReader::new(file)).ok()?;
+    let time = exif.get_field(exif::Tag::DateTimeOriginal, exif::In::PRIMARY)?.display_value().to_string();
+    let subsec = exif.get_field(exif::Tag::SubSecTimeOriginal, exif::In::PRIMARY)
+        .map(|f| f.display_value().to_string().trim_matches('"').to_string())
+        .unwrap_or_default();
+    Some(format!("{time}.{subsec}"))
+}
+
+/// Takes as long as a camera would.
+async fn expose(settings: &DNTime) {
+    match settings.exposure {
+        Exposure::Auto => sleep(Duration::from_secs_f64(0.1)).await,
+        Exposure::Manual(f) => sleep(Duration::from_secs_f64(f)).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a jpeg that holds nothing but `DateTimeOriginal`.
+    fn jpeg(time: Option<&str>) -> Vec<u8> {
+        let mut tiff = b"II*\0".to_vec();
+        tiff.extend(8u32.to_le_bytes());
+        // IFD0 with a pointer to the exif IFD at 26, which holds DateTimeOriginal at 44.
+        tiff.extend(1u16.to_le_bytes());
+        tiff.extend([0x69, 0x87, 4, 0]);
+        tiff.extend(1u32.to_le_bytes());
+        tiff.extend(26u32.to_le_bytes());
+        tiff.extend(0u32.to_le_bytes());
+        tiff.extend(1u16.to_le_bytes());
+        tiff.extend([0x03, 0x90, 2, 0]);
+        tiff.extend(20u32.to_le_bytes());
+        tiff.extend(44u32.to_le_bytes());
+        tiff.extend(0u32.to_le_bytes());
+        tiff.extend(time.unwrap_or_default().as_bytes());
+        tiff.push(0);
+
+        let mut b = vec![0xff, 0xd8];
+        if time.is_some() {
+            b.extend([0xff, 0xe1]);
+            b.extend((2 + 6 + tiff.len() as u16).to_be_bytes());
+            b.extend(b"Exif\0\0");
+            b.extend(tiff);
+        }
+        b.extend([0xff, 0xd9]);
+        b
+    }
+
+    fn dir(files: &[(&str, Vec<u8>)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("replay-{uuid}", uuid = Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("sub.jpg")).unwrap();
+        for (name, b) in files {
+            std::fs::write(dir.join(name), b).unwrap();
+        }
+        dir
+    }
+
+    fn names(files: &[PathBuf]) -> Vec<&str> {
+        files.iter().map(|f| f.file_name().unwrap().to_str().unwrap()).collect()
+    }
+
+    #[test]
+    fn list_by_name_and_exif() {
+        let dir = dir(&[
+            ("a.jpg", jpeg(Some("2026:10:18 12:00:02"))),
+            ("b.JPG", jpeg(Some("2026:10:18 12:00:01"))),
+            ("c.jpg", jpeg(None)),
+            ("d.jpg", jpeg(Some("2026:10:18 11:59:59"))),
+            ("notes.txt", vec![]),
+        ]);
+        assert_eq!(names(&list(&dir, Order::Name)), vec!["a.jpg", "b.JPG", "c.jpg", "d.jpg"]);
+        assert_eq!(names(&list(&dir, Order::Exif)), vec!["d.jpg", "b.JPG", "a.jpg", "c.jpg"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(list(&dir, Order::Name).is_empty());
+    }
+
+    #[test]
+    fn file_type_from_magic_then_extension() {
+        assert_eq!(file_type(Path::new("a.cr2"), &jpeg(None)), Some(FileType::Jpeg));
+        assert_eq!(file_type(Path::new("a.png"), b"II*\0\x08\0\0\0CR\x02\0"), Some(FileType::Cr2));
+        assert_eq!(file_type(Path::new("a.nef"), b"unknown"), Some(FileType::Nef));
+        assert_eq!(file_type(Path::new("a.txt"), b"unknown"), None);
+        assert_eq!(file_type(Path::new("a"), b""), None);
+    }
+}
@@ -0,0 +1,193 @@
+use std::io::Cursor;
+
+use chrono::{DateTime, Local};
+use common::capture::settings::dntime::{DNTime, Exposure, Iso};
+use image::{DynamicImage, ImageBuffer, ImageOutputFormat, ImageResult, Rgb};
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
+use crate::config::dummy::{Dummy, Format};
+
+/// brightest and faintest magnitude of the star field.
+const MAGNITUDES: (f64, f64) = (-1.0, 9.0);
+const STARS: usize = 4000;
+/// pixel value of a magnitude 0 star at a gain of 1.
+const STAR_SCALE: f64 = 0.5;
+/// sky brightness the automatic exposure aims for.
+const MID_GREY: f64 = 0.18;
+/// resolution of the gamma lookup table.
+const GAMMA_STEPS: usize = 4096;
+
+/// 3x5 glyphs of the timestamp, rows top to bottom, most significant bit left.
+const GLYPHS: &[(char, [u8; 5])] = &[
+    ('0', [0b111, 0b101, 0b101, 0b101, 0b111]),
+    ('1', [0b010, 0b110, 0b010, 0b010, 0b111]),
+    ('2', [0b111, 0b001, 0b111, 0b100, 0b111]),
+    ('3', [0b111, 0b001, 0b111, 0b001, 0b111]),
+    ('4', [0b101, 0b101, 0b111, 0b001, 0b001]),
+    ('5', [0b111, 0b100, 0b111, 0b001, 0b111]),
+    ('6', [0b111, 0b100, 0b111, 0b101, 0b111]),
+    ('7', [0b111, 0b001, 0b001, 0b001, 0b001]),
+    ('8', [0b111, 0b101, 0b111, 0b101, 0b111]),
+    ('9', [0b111, 0b101, 0b111, 0b001, 0b111]),
+    ('-', [0b000, 0b000, 0b111, 0b000, 0b000]),
+    (':', [0b000, 0b010, 0b000, 0b010, 0b000]),
+];
+
+struct Star {
+    /// position relative to the image size.
+    x: f64,
+    y: f64,
+    /// pixel value at a gain of 1.
+    flux: f64,
+}
+
+/// A fixed star field, so stars stay in place from frame to frame.
+pub struct Sky {
+    stars: Vec<Star>,
+}
+
+impl Sky {
+    pub fn new(seed: u64) -> Sky {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let (m0, m1) = MAGNITUDES;
+        let stars = (0..STARS).map(|_| {
+            // faint stars are more common, roughly 2x per magnitude.
+            let u: f64 = rng.gen();
+            let m = m0 + (1.0 + u * (10f64.powf(0.3 * (m1 - m0)) - 1.0)).log10() / 0.3;
+            Star { x: rng.gen(), y: rng.gen(), flux: STAR_SCALE * 10f64.powf(-0.4 * m) }
+        }).collect();
+        Sky { stars }
+    }
+
+    /// Renders the sky at `altitude` of the sun as captured with `settings` and encodes it as `config.format`.
+    pub fn render(&self, rng: &mut StdRng, settings: &DNTime, altitude: f64, time: DateTime<Local>, config: &Dummy) -> ImageResult<Vec<u8>> {
+        let luminance = luminance(altitude);
+        let (exposure, iso) = expose(settings, luminance);
+        let gain = exposure * iso / 100.0;
+        let sigma = 0.003 * (iso / 100.0).sqrt();
+
+        let (width, height) = (config.width, config.height);
+        let (w, h) = (width as usize, height as usize);
+        let mut pixels = vec![[0.0f64; 3]; w * h];
+
+        // brighter and warmer towards the horizon at the bottom.
+        let top = if altitude > 0.0 { DAY } else { lerp(DAY, NIGHT, (-altitude / 12.0).clamp(0.0, 1.0)) };
+        let horizon = lerp(top, DUSK, (1.0 - (altitude + 2.0).abs() / 8.0).clamp(0.0, 1.0));
+        for y in 0..h {
+            let v = y as f64 / h as f64;
+            let color = lerp(top, horizon, v * v);
+            let value = luminance * gain * (0.6 + 1.2 * v * v);
+            for p in &mut pixels[y * w..(y + 1) * w] {
+                *p = color.map(|c| c * value);
+            }
+        }
+
+        for star in &self.stars {
+            let value = star.flux * gain;
+            if value < sigma { continue; }
+            let (x, y) = ((star.x * w as f64) as usize, (star.y * h as f64) as usize);
+            for (dx, dy, weight) in [(0, 0, 1.0), (-1, 0, 0.25), (1, 0, 0.25), (0, -1, 0.25), (0, 1, 0.25)] {
+                let (px, py) = (x as isize + dx, y as isize + dy);
+                if px < 0 || py < 0 || px >= w as isize || py >= h as isize { continue; }
+                for c in &mut pixels[py as usize * w + px as usize] { *c += value * weight; }
+            }
+        }
+
+        let gamma: Vec<u16> = (0..=GAMMA_STEPS).map(|i| ((i as f64 / GAMMA_STEPS as f64).powf(1.0 / 2.2) * u16::MAX as f64) as u16).collect();
+        let mut image = ImageBuffer::<Rgb<u16>, Vec<u16>>::new(width, height);
+        for (i, p) in image.pixels_mut().enumerate() {
+            *p = Rgb(pixels[i].map(|c| {
+                // triangular, close enough to gaussian.
+                let noise = (rng.gen::<f64>() + rng.gen::<f64>() - 1.0) * 2.0 * sigma;
+                gamma[((c + noise).clamp(0.0, 1.0) * GAMMA_STEPS as f64) as usize]
+            }));
+        }
+        stamp(&mut image, &time.format("%Y-%m-%d %H:%M:%S").to_string());
+
+        let mut b = vec![];
+        match config.format {
+            Format::Png => DynamicImage::ImageRgb16(image).write_to(&mut Cursor::new(&mut b), ImageOutputFormat::Png)?,
+            Format::Tiff => DynamicImage::ImageRgb16(image).write_to(&mut Cursor::new(&mut b), ImageOutputFormat::Tiff)?,
+            Format::Jpg => DynamicImage::ImageRgb8(DynamicImage::ImageRgb16(image).to_rgb8()).write_to(&mut Cursor::new(&mut b), ImageOutputFormat::Jpeg(90))?,
+            Format::Dummy => b = vec![0, 0, 0],
+        }
+        Ok(b)
+    }
+}
+
+const DAY: [f64; 3] = [0.45, 0.65, 1.0];
+const DUSK: [f64; 3] = [1.0, 0.55, 0.3];
+const NIGHT: [f64; 3] = [0.35, 0.4, 0.6];
+
+fn lerp(a: [f64; 3], b: [f64; 3], t: f64) -> [f64; 3] {
+    [a[0] + (b[0] - a[0]) * t, a[1] + (b[1] - a[1]) * t, a[2] + (b[2] - a[2]) * t]
+}
+
+/// Sky brightness at a gain of 1. Falls by about five orders of magnitude through twilight.
+fn luminance(altitude: f64) -> f64 {
+    if altitude >= 0.0 { 1000.0 * (0.3 + 0.7 * altitude.to_radians().sin()) }
+    else { (300.0 * 10f64.powf(altitude * 0.3)).max(0.001) }
+}
+
+/// Exposure in seconds and ISO. Automatic values are chosen to render the sky mid grey.
+fn expose(settings: &DNTime, luminance: f64) -> (f64, f64) {
+    let gain = MID_GREY / luminance;
+    match (settings.exposure, settings.iso) {
+        (Exposure::Manual(t), Iso::Manual(iso)) => (t, iso as f64),
+        (Exposure::Manual(t), Iso::Auto) => (t, (gain * 100.0 / t).clamp(100.0, 6400.0)),
+        (Exposure::Auto, Iso::Manual(iso)) => ((gain * 100.0 / iso as f64).clamp(1.0 / 4000.0, 30.0), iso as f64),
+        (Exposure::Auto, Iso::Auto) => {
+            let t = (gain).clamp(1.0 / 4000.0, 30.0);
+            (t, (gain * 100.0 / t).clamp(100.0, 6400.0))
+        },
+    }
+}
+
+/// Writes `text` in white on black into the top left corner.
+fn stamp(image: &mut ImageBuffer<Rgb<u16>, Vec<u16>>, text: &str) {
+    const SCALE: u32 = 2;
+    const MARGIN: u32 = 8;
+    let right = (MARGIN + (text.chars().count() as u32 * 4 + 1) * SCALE).min(image.width());
+    let bottom = (MARGIN + 7 * SCALE).min(image.height());
+    for (x, y) in (MARGIN - SCALE..right).flat_map(|x| (MARGIN - SCALE..bottom).map(move |y| (x, y))) {
+        image.put_pixel(x, y, Rgb([0; 3]));
+    }
+    for (i, c) in text.chars().enumerate() {
+        let Some((_, rows)) = GLYPHS.iter().find(|(g, _)| *g == c) else { continue };
+        for (row, bits) in rows.iter().enumerate() {
+            for col in 0..3 {
+                if bits & (0b100 >> col) == 0 { continue; }
+                for (sx, sy) in (0..SCALE).flat_map(|sx| (0..SCALE).map(move |sy| (sx, sy))) {
+                    let x = MARGIN + (i as u32 * 4 + col) * SCALE + sx;
+                    let y = MARGIN + row as u32 * SCALE + sy;
+                    if x < image.width() && y < image.height() {
+                        image.put_pixel(x, y, Rgb([u16::MAX; 3]));
+                    }
+                }
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use common::capture::settings::dntime::{Aperture, Frame};
+    use crate::config::dummy::Format;
+
+    fn render(seed: u64, altitude: f64) -> Vec<u8> {
+        let settings = DNTime { frame: Frame::None, exposure: Exposure::Manual(30.0), iso: Iso::Manual(800), aperture: Aperture::Implicit, bracket: None, stack: None };
+        let config = Dummy { format: Format::Png, width: 64, height: 32, failure_probability: 0.0, seed: Some(seed) };
+        let time = Local.with_ymd_and_hms(2026, 10, 18, 22, 0, 0).unwrap();
+        Sky::new(seed).render(&mut StdRng::seed_from_u64(seed), &settings, altitude, time, &config).unwrap()
+    }
+
+    #[test]
+    fn same_seed_renders_same_frame() {
+        assert_eq!(render(7, -20.0), render(7, -20.0));
+        assert_ne!(render(7, -20.0), render(8, -20.0));
+        assert_ne!(render(7, -20.0), render(7, -5.0));
+    }
+}
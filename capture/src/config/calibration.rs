@@ -0,0 +1,22 @@
+use serde::{ Deserialize, Deserializer, de::Unexpected };
+
+#[derive(Debug, Deserialize)]
+pub struct Calibration {
+    /// darks taken each morning when the sun rises above `dawn`. 0 disables them.
+    pub darks: u32,
+    /// bias frames taken along with the darks. 0 disables them.
+    pub bias: u32,
+
+    #[serde(deserialize_with = "deserialize_dawn")]
+    pub dawn: f64,
+
+    /// confirms the lens is covered at dawn, e.g. by a motorised cap or a closed dome.
+    /// scheduled darks and bias frames are skipped without.
+    pub covered: bool,
+}
+
+fn deserialize_dawn<'de, D>(d: D) -> Result<f64, D::Error> where D: Deserializer<'de> {
+    let value = f64::deserialize(d)?;
+    if value.is_finite() && (-90.0..=90.0).contains(&value) { Ok(value) }
+    else { Err(serde::de::Error::invalid_value(Unexpected::Float(value), &"to be -90.0 <= x <= 90.0. (calibration.dawn)")) }
+}
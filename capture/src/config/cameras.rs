@@ -0,0 +1,73 @@
+use std::collections::HashSet;
+
+use common::capture::{FileType, valid_id};
+use serde::{ Deserialize, Deserializer, de::Unexpected };
+
+use super::general::CaptureModule;
+
+/// A camera of the node. Empty `cameras` capture with the single camera of `general.module`.
+#[derive(Debug, Deserialize)]
+pub struct Camera {
+    /// tags its results. the processor files its frames under it.
+    #[serde(deserialize_with = "deserialize_id")]
+    pub id: String,
+
+    pub module: CaptureModule,
+
+    /// gphoto2 port, e.g. "usb:001,004". resolved from `serial` if unset.
+    #[serde(default)]
+    pub port: Option<String>,
+
+    /// serial number of the camera as gphoto2 --summary reports it. survives replugging, unlike the port.
+    #[serde(default)]
+    pub serial: Option<String>,
+
+    /// key of its settings on the processor. the settings of the node if unset.
+    #[serde(default, deserialize_with = "deserialize_settings")]
+    pub settings: Option<String>,
+
+    /// type of the file a command camera writes. `command.file_type` if unset.
+    #[serde(default, deserialize_with = "deserialize_file_type")]
+    pub file_type: Option<FileType>,
+}
+
+pub fn deserialize_cameras<'de, D>(d: D) -> Result<Vec<Camera>, D::Error> where D: Deserializer<'de> {
+    let cameras = Vec::<Camera>::deserialize(d)?;
+    let mut ids = HashSet::new();
+    for camera in &cameras {
+        if !ids.insert(camera.id.as_str()) {
+            return Err(serde::de::Error::invalid_value(Unexpected::Str(&camera.id), &"to be unique. (cameras.id)"));
+        }
+        if (camera.port.is_some() || camera.serial.is_some()) && camera.module != CaptureModule::GPhoto2 {
+            return Err(serde::de::Error::invalid_value(Unexpected::Str(&camera.id), &"to be a gphoto2 camera to have a port or serial. (cameras.port)"));
+        }
+        if camera.file_type.is_some() && camera.module != CaptureModule::Command {
+            return Err(serde::de::Error::invalid_value(Unexpected::Str(&camera.id), &"to be a command camera to have a file_type. (cameras.file_type)"));
+        }
+    }
+    let gphoto2 = cameras.iter().filter(|c| c.module == CaptureModule::GPhoto2);
+    if gphoto2.clone().count() > 1 && gphoto2.clone().any(|c| c.port.is_none() && c.serial.is_none()) {
+        return Err(serde::de::Error::invalid_value(Unexpected::Seq, &"every gphoto2 camera to have a port or serial if there are several. (cameras.port)"));
+    }
+    Ok(cameras)
+}
+
+fn deserialize_id<'de, D>(d: D) -> Result<String, D::Error> where D: Deserializer<'de> {
+    let s = String::deserialize(d)?;
+    if valid_id(&s) { Ok(s) }
+    else { Err(serde::de::Error::invalid_value(Unexpected::Str(&s), &"to be non empty and only letters, digits and underscores. (cameras.id)")) }
+}
+
+fn deserialize_settings<'de, D>(d: D) -> Result<Option<String>, D::Error> where D: Deserializer<'de> {
+    let s = String::deserialize(d)?;
+    if valid_id(&s) { Ok(Some(s)) }
+    else { Err(serde::de::Error::invalid_value(Unexpected::Str(&s), &"to be non empty and only letters, digits and underscores. (cameras.settings)")) }
+}
+
+fn deserialize_file_type<'de, D>(d: D) -> Result<Option<FileType>, D::Error> where D: Deserializer<'de> {
+    let s = String::deserialize(d)?;
+    match FileType::from_ext(&s) {
+        Some(t) if t != FileType::Dummy => Ok(Some(t)),
+        _ => Err(serde::de::Error::invalid_value(Unexpected::Str(&s), &"to be cr2, cr3, nef, arw, dng, jpg, png or tif. (cameras.file_type)")),
+    }
+}
@@ -0,0 +1,80 @@
+use std::{path::PathBuf, time::Duration};
+
+use common::capture::FileType;
+use regex::Regex;
+use serde::{ Deserialize, Deserializer, de::Unexpected };
+
+/// placeholders that may appear in `command.args`.
+pub const PLACEHOLDERS: &[&str] = &["exposure", "exposure_ms", "exposure_us", "iso", "gain", "aperture", "ev", "output"];
+
+#[derive(Debug, Deserialize)]
+pub struct Command {
+    pub program: PathBuf,
+
+    /// argument template. each entry is split at whitespace and dropped if one of its placeholders is automatic.
+    #[serde(deserialize_with = "deserialize_args")]
+    pub args: Vec<String>,
+
+    /// type of the file the command writes to `{output}`.
+    #[serde(deserialize_with = "deserialize_file_type")]
+    pub file_type: FileType,
+
+    /// exit codes that mean the capture succeeded.
+    pub success_codes: Vec<i32>,
+
+    /// has to match stdout or stderr for the capture to succeed. empty to skip.
+    #[serde(deserialize_with = "deserialize_success_pattern")]
+    pub success_pattern: Option<Regex>,
+
+    /// time a capture may take beyond its exposure before the command is considered hung.
+    #[serde(deserialize_with = "deserialize_timeout")]
+    pub timeout: Duration,
+
+    /// time between SIGTERM and SIGKILL.
+    #[serde(deserialize_with = "deserialize_grace_period")]
+    pub grace_period: Duration,
+}
+
+fn deserialize_args<'de, D>(d: D) -> Result<Vec<String>, D::Error> where D: Deserializer<'de> {
+    let args = Vec::<String>::deserialize(d)?;
+    for arg in &args {
+        for placeholder in arg.split('{').skip(1).filter_map(|p| p.split_once('}')).map(|(p, _)| p) {
+            if !PLACEHOLDERS.contains(&placeholder) {
+                return Err(serde::de::Error::invalid_value(Unexpected::Str(arg), &format!("placeholders to be one of {PLACEHOLDERS:?}. (command.args)").as_str()));
+            }
+        }
+    }
+    if !args.iter().any(|a| a.contains("{output}")) {
+        return Err(serde::de::Error::invalid_value(Unexpected::Seq, &"to contain {output}. (command.args)"));
+    }
+    Ok(args)
+}
+
+fn deserialize_file_type<'de, D>(d: D) -> Result<FileType, D::Error> where D: Deserializer<'de> {
+    let s = String::deserialize(d)?;
+    match FileType::from_ext(&s) {
+        Some(t) if t != FileType::Dummy => Ok(t),
+        _ => Err(serde::de::Error::invalid_value(Unexpected::Str(&s), &"to be cr2, cr3, nef, arw, dng, jpg, png or tif. (command.file_type)")),
+    }
+}
+
+fn deserialize_success_pattern<'de, D>(d: D) -> Result<Option<Regex>, D::Error> where D: Deserializer<'de> {
+    let s = String::deserialize(d)?;
+    if s.is_empty() { return Ok(None); }
+    match Regex::new(&s) {
+        Ok(r) => Ok(Some(r)),
+        Err(e) => Err(serde::de::Error::invalid_value(Unexpected::Str(&s), &format!("to be a valid regex. (command.success_pattern) {e}").as_str())),
+    }
+}
+
+fn deserialize_timeout<'de, D>(d: D) -> Result<Duration, D::Error> where D: Deserializer<'de> {
+    let value = f64::deserialize(d)?;
+    if value.is_finite() && value > 0.0 { Ok(Duration::from_secs_f64(value)) }
+    else { Err(serde::de::Error::invalid_value(Unexpected::Float(value), &"to be greater than zero. (command.timeout)")) }
+}
+
+fn deserialize_grace_period<'de, D>(d: D) -> Result<Duration, D::Error> where D: Deserializer<'de> {
+    let value = f64::deserialize(d)?;
+    if value.is_finite() && value >= 0.0 { Ok(Duration::from_secs_f64(value)) }
+    else { Err(serde::de::Error::invalid_value(Unexpected::Float(value), &"to be at least zero. (command.grace_period)")) }
+}
@@ -0,0 +1,41 @@
+use serde::{ Deserialize, Deserializer, de::Unexpected };
+
+#[derive(Debug, Deserialize)]
+pub struct Dummy {
+    pub format: Format,
+
+    #[serde(deserialize_with = "deserialize_dimension")]
+    pub width: u32,
+    #[serde(deserialize_with = "deserialize_dimension")]
+    pub height: u32,
+
+    /// chance of a capture to fail.
+    #[serde(deserialize_with = "deserialize_failure_probability")]
+    pub failure_probability: f64,
+
+    /// seeds failures and noise. random if unset.
+    #[serde(default)]
+    pub seed: Option<u64>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Png,
+    Tiff,
+    Jpg,
+    /// three zero bytes, as the dummy always did.
+    Dummy,
+}
+
+fn deserialize_dimension<'de, D>(d: D) -> Result<u32, D::Error> where D: Deserializer<'de> {
+    let value = u32::deserialize(d)?;
+    if (16..=8192).contains(&value) { Ok(value) }
+    else { Err(serde::de::Error::invalid_value(Unexpected::Unsigned(value as u64), &"to be 16 <= x <= 8192. (dummy.width, dummy.height)")) }
+}
+
+fn deserialize_failure_probability<'de, D>(d: D) -> Result<f64, D::Error> where D: Deserializer<'de> {
+    let value = f64::deserialize(d)?;
+    if (0.0..=1.0).contains(&value) { Ok(value) }
+    else { Err(serde::de::Error::invalid_value(Unexpected::Float(value), &"to be 0.0 <= x <= 1.0. (dummy.failure_probability)")) }
+}
@@ -13,6 +13,10 @@ pub struct General {
     #[serde(deserialize_with = "deserialize_processor_url")]
     pub processor_url: Url,
 
+    /// sent as bearer token. has to match the token of this node in the processor's auth.nodes.
+    #[serde(default, deserialize_with = "deserialize_token")]
+    pub token: Option<String>,
+
     pub tmp_path: PathBuf,
 
     #[serde(deserialize_with = "deserialize_queue")]
@@ -24,6 +28,19 @@ pub struct General {
 pub enum CaptureModule {
     Dummy,
     GPhoto2,
+    Replay,
+    Command,
+}
+
+impl std::fmt::Display for CaptureModule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CaptureModule::Dummy => write!(f, "dummy"),
+            CaptureModule::GPhoto2 => write!(f, "gphoto2"),
+            CaptureModule::Replay => write!(f, "replay"),
+            CaptureModule::Command => write!(f, "command"),
+        }
+    }
 }
 
 fn deserialize_name<'de, D>(d: D) -> Result<String, D::Error> where D: Deserializer<'de> {
@@ -39,9 +56,16 @@ fn deserialize_processor_url<'de, D>(d: D) -> Result<Url, D::Error> where D: Des
     }
 }
 
+fn deserialize_token<'de, D>(d: D) -> Result<Option<String>, D::Error> where D: Deserializer<'de> {
+    let s = String::deserialize(d)?;
+    if !s.is_empty() && s.bytes().all(|b| b.is_ascii_graphic()) { Ok(Some(s)) }
+    else { Err(serde::de::Error::invalid_value(Unexpected::Other("token"), &"to be non empty printable ascii without spaces. (general.token)")) }
+}
+
 fn deserialize_queue<'de, D>(d: D) -> Result<usize, D::Error> where D: Deserializer<'de> {
     let s = String::deserialize(d)?;
     match s.parse::<usize>() {
+        Ok(0) => Err(serde::de::Error::invalid_value(Unexpected::Unsigned(0), &"to be greater than zero. (general.queue)")),
         Ok(u) => Ok(u),
         Err(e) => Err(serde::de::Error::invalid_value(Unexpected::Str(&s), &format!("to be an usize. (general.queue) {e}").as_str())),
     }
@@ -0,0 +1,84 @@
+use std::{path::PathBuf, time::Duration};
+
+use serde::{ Deserialize, Deserializer, de::Unexpected };
+
+#[derive(Debug, Deserialize)]
+pub struct GPhoto2 {
+    /// time a capture may take beyond its exposure before gphoto2 is considered hung.
+    #[serde(deserialize_with = "deserialize_timeout")]
+    pub timeout: Duration,
+
+    /// time between SIGTERM and SIGKILL.
+    #[serde(deserialize_with = "deserialize_grace_period")]
+    pub grace_period: Duration,
+
+    /// consecutive hangs after which the camera is reset. 0 never resets it.
+    pub recover_after: u32,
+
+    /// camera family whose config names are used. detected from the model if unset.
+    #[serde(default)]
+    pub profile: Option<Family>,
+
+    pub download: Download,
+
+    /// per capture directories of `Download::File` are created below it. meant to be a tmpfs.
+    pub download_path: PathBuf,
+
+    /// bounds of the size of a download in bytes. others are rejected.
+    #[serde(deserialize_with = "deserialize_min_size")]
+    pub min_size: usize,
+    #[serde(deserialize_with = "deserialize_max_size")]
+    pub max_size: usize,
+
+    /// time between probes of the camera. `None` only probes at startup.
+    #[serde(deserialize_with = "deserialize_probe_interval")]
+    pub probe_interval: Option<Duration>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Family {
+    Canon,
+    Nikon,
+    Sony,
+}
+
+/// how images get from gphoto2 into memory.
+#[derive(Debug, PartialEq, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Download {
+    /// read from the output of gphoto2. a single image only.
+    Stdout,
+    /// written into a unique directory below `download_path` and read back.
+    File,
+}
+
+fn deserialize_timeout<'de, D>(d: D) -> Result<Duration, D::Error> where D: Deserializer<'de> {
+    let value = f64::deserialize(d)?;
+    if value.is_finite() && value > 0.0 { Ok(Duration::from_secs_f64(value)) }
+    else { Err(serde::de::Error::invalid_value(Unexpected::Float(value), &"to be greater than zero. (gphoto2.timeout)")) }
+}
+
+fn deserialize_grace_period<'de, D>(d: D) -> Result<Duration, D::Error> where D: Deserializer<'de> {
+    let value = f64::deserialize(d)?;
+    if value.is_finite() && value >= 0.0 { Ok(Duration::from_secs_f64(value)) }
+    else { Err(serde::de::Error::invalid_value(Unexpected::Float(value), &"to be at least zero. (gphoto2.grace_period)")) }
+}
+
+fn deserialize_probe_interval<'de, D>(d: D) -> Result<Option<Duration>, D::Error> where D: Deserializer<'de> {
+    let value = f64::deserialize(d)?;
+    if value.is_finite() && value > 0.0 { Ok(Some(Duration::from_secs_f64(value))) }
+    else if value == 0.0 { Ok(None) }
+    else { Err(serde::de::Error::invalid_value(Unexpected::Float(value), &"to be at least zero. (gphoto2.probe_interval)")) }
+}
+
+fn deserialize_min_size<'de, D>(d: D) -> Result<usize, D::Error> where D: Deserializer<'de> {
+    let value = usize::deserialize(d)?;
+    Ok(value.saturating_mul(1024))
+}
+
+fn deserialize_max_size<'de, D>(d: D) -> Result<usize, D::Error> where D: Deserializer<'de> {
+    let value = usize::deserialize(d)?;
+    if value > 0 { Ok(value.saturating_mul(1024 * 1024)) }
+    else { Err(serde::de::Error::invalid_value(Unexpected::Unsigned(value as u64), &"to be greater than zero. (gphoto2.max_size)")) }
+}
@@ -1,6 +1,7 @@
 use serde::{ Deserialize, Deserializer };
 
 #[derive(Debug, Deserialize)]
+#[allow(clippy::upper_case_acronyms)]
 pub struct GPS {
     #[serde(deserialize_with = "deserialize_latitude")]
     pub latitude: f64,
@@ -11,12 +12,12 @@ pub struct GPS {
 
 fn deserialize_latitude<'de, D>(d: D) -> Result<f64, D::Error> where D: Deserializer<'de> {
     let value = f64::deserialize(d)?;
-    if value.is_finite() && value >= -90.0 && value <= 90.0 { Ok(value) }
+    if value.is_finite() && (-90.0..=90.0).contains(&value) { Ok(value) }
     else { Err(serde::de::Error::invalid_value(serde::de::Unexpected::Float(value), &"to be -90.0 <= x <= 90.0")) }
 }
 
 fn deserialize_longitude<'de, D>(d: D) -> Result<f64, D::Error> where D: Deserializer<'de> {
     let value = f64::deserialize(d)?;
-    if value.is_finite() && value >= -180.0 && value <= 180.0 { Ok(value) }
+    if value.is_finite() && (-180.0..=180.0).contains(&value) { Ok(value) }
     else { Err(serde::de::Error::invalid_value(serde::de::Unexpected::Float(value), &"to be -180.0 <= x <= 180.0")) }
 }
\ No newline at end of file
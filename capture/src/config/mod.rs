@@ -2,14 +2,31 @@ pub mod general;
 pub mod logging;
 pub mod gps;
 pub mod tracking;
+pub mod spool;
+pub mod tls;
+pub mod calibration;
+pub mod preview;
+pub mod replay;
+pub mod command;
+pub mod dummy;
+pub mod gphoto2;
+pub mod cameras;
 
 use serde::Deserialize;
-use config_rs;
 
 use general::General;
 use logging::Logging;
 use gps::GPS;
 use tracking::Tracking;
+use spool::Spool;
+use tls::Tls;
+use calibration::Calibration;
+use preview::Preview;
+use replay::Replay;
+use command::Command;
+use dummy::Dummy;
+use gphoto2::GPhoto2;
+use cameras::Camera;
 
 const CONFIGS: &[&str] = &["capture.toml", "nonexistant.toml"];
 
@@ -19,6 +36,16 @@ pub struct Config {
     pub logging: Logging,
     pub gps: GPS,
     pub tracking: Tracking,
+    pub spool: Spool,
+    pub tls: Tls,
+    pub calibration: Calibration,
+    pub preview: Preview,
+    pub replay: Replay,
+    pub command: Command,
+    pub dummy: Dummy,
+    pub gphoto2: GPhoto2,
+    #[serde(deserialize_with = "cameras::deserialize_cameras")]
+    pub cameras: Vec<Camera>,
 }
 
 impl Config {
@@ -28,6 +55,15 @@ impl Config {
             .add_source(config_rs::File::from_str(include_str!("defaults/logging.toml"), config_rs::FileFormat::Toml))
             .add_source(config_rs::File::from_str(include_str!("defaults/gps.toml"), config_rs::FileFormat::Toml))
             .add_source(config_rs::File::from_str(include_str!("defaults/tracking.toml"), config_rs::FileFormat::Toml))
+            .add_source(config_rs::File::from_str(include_str!("defaults/spool.toml"), config_rs::FileFormat::Toml))
+            .add_source(config_rs::File::from_str(include_str!("defaults/tls.toml"), config_rs::FileFormat::Toml))
+            .add_source(config_rs::File::from_str(include_str!("defaults/calibration.toml"), config_rs::FileFormat::Toml))
+            .add_source(config_rs::File::from_str(include_str!("defaults/preview.toml"), config_rs::FileFormat::Toml))
+            .add_source(config_rs::File::from_str(include_str!("defaults/replay.toml"), config_rs::FileFormat::Toml))
+            .add_source(config_rs::File::from_str(include_str!("defaults/command.toml"), config_rs::FileFormat::Toml))
+            .add_source(config_rs::File::from_str(include_str!("defaults/dummy.toml"), config_rs::FileFormat::Toml))
+            .add_source(config_rs::File::from_str(include_str!("defaults/gphoto2.toml"), config_rs::FileFormat::Toml))
+            .add_source(config_rs::File::from_str(include_str!("defaults/cameras.toml"), config_rs::FileFormat::Toml))
             ;
         for s in CONFIGS {
             config_rs_builder = config_rs_builder.add_source(config_rs::File::with_name(s).required(false));
@@ -0,0 +1,22 @@
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct Preview {
+    /// capture RAW+JPEG and upload the camera jpg ahead of the raw.
+    pub enable: bool,
+
+    /// image format choice of the camera that stores a raw and a jpg. the one of the camera profile if unset.
+    #[serde(default)]
+    pub imageformat: Option<String>,
+
+    pub raw: RawUpload,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RawUpload {
+    /// upload the raw right after its preview.
+    After,
+    /// keep the raw in the spool until the processor requests it.
+    Demand,
+}
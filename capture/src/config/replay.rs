@@ -0,0 +1,23 @@
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct Replay {
+    /// images replayed by the `replay` module.
+    pub directory: PathBuf,
+
+    pub order: Order,
+
+    /// start over once all images were replayed.
+    pub repeat: bool,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Order {
+    /// by file name.
+    Name,
+    /// by the time the image was taken. images without exif time follow by file name.
+    Exif,
+}
@@ -0,0 +1,35 @@
+use serde::{ Deserialize, Deserializer, de::Unexpected };
+
+#[derive(Debug, Deserialize)]
+pub struct Spool {
+    #[serde(deserialize_with = "deserialize_max_items")]
+    pub max_items: usize,
+
+    #[serde(deserialize_with = "deserialize_max_size")]
+    pub max_size: u64,
+
+    pub policy: DropPolicy,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DropPolicy {
+    /// delete the oldest spooled uploads to make room.
+    Oldest,
+    /// drop the upload that does not fit anymore.
+    Newest,
+    /// stop capturing until the spool has room again.
+    Stop,
+}
+
+fn deserialize_max_items<'de, D>(d: D) -> Result<usize, D::Error> where D: Deserializer<'de> {
+    let value = usize::deserialize(d)?;
+    if value > 0 { Ok(value) }
+    else { Err(serde::de::Error::invalid_value(Unexpected::Unsigned(value as u64), &"to be greater than zero. (spool.max_items)")) }
+}
+
+fn deserialize_max_size<'de, D>(d: D) -> Result<u64, D::Error> where D: Deserializer<'de> {
+    let value = u64::deserialize(d)?;
+    if value > 0 { Ok(value.saturating_mul(1024 * 1024)) }
+    else { Err(serde::de::Error::invalid_value(Unexpected::Unsigned(value), &"to be greater than zero. (spool.max_size)")) }
+}
@@ -0,0 +1,16 @@
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct Tls {
+    /// pem encoded CA the processor certificate is verified against instead of the public roots.
+    #[serde(default)]
+    pub ca: Option<PathBuf>,
+
+    /// pem encoded client certificate chain and private key, if the processor asks for one.
+    #[serde(default)]
+    pub cert: Option<PathBuf>,
+    #[serde(default)]
+    pub key: Option<PathBuf>,
+}
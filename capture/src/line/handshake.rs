@@ -0,0 +1,79 @@
+use std::time::Duration;
+
+use futures::{ SinkExt, StreamExt };
+use log::{debug, info, warn};
+use thiserror::Error;
+use tokio::time::timeout;
+use tokio_tungstenite::tungstenite::{self, Message, protocol::CloseFrame};
+
+use common::handshake::{Hello, Welcome, MIN_PROTOCOL_VERSION};
+
+use super::Ws;
+
+const WELCOME_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Error, Debug)]
+pub enum HandshakeError {
+    #[error("unable to send hello. {0}")]
+    Send(tungstenite::Error),
+
+    #[error("no welcome received within {0:?}.")]
+    Timeout(Duration),
+
+    #[error("websocket threw an error. {0}")]
+    Read(tungstenite::Error),
+
+    #[error("remote closed the connection. {0:?}")]
+    Closed(Option<CloseFrame<'static>>),
+
+    #[error("cannot deserialize welcome. {0}")]
+    Parse(bincode::Error),
+
+    #[error("expected a binary welcome frame.")]
+    Unexpected,
+
+    #[error("rejected by processor. {0}")]
+    Rejected(String),
+
+    #[error("processor selected protocol version {0}, but at least {MIN_PROTOCOL_VERSION} is required.")]
+    Incompatible(u32),
+}
+
+/// Sends `hello` and waits for the processors `Welcome`.
+/// Returns the negotiated protocol version.
+pub async fn handshake(ws: &mut Ws, hello: &Hello) -> Result<u32, HandshakeError> {
+    debug!("sending {hello:?}");
+    ws.send(Message::Binary(bincode::serialize(hello).unwrap())).await
+        .map_err(HandshakeError::Send)?;
+
+    let welcome = timeout(WELCOME_TIMEOUT, async {
+        loop {
+            match ws.next().await {
+                Some(Ok(Message::Binary(b))) => return bincode::deserialize::<Welcome>(&b).map_err(HandshakeError::Parse),
+                Some(Ok(Message::Ping(_) | Message::Pong(_))) => { },
+                Some(Ok(Message::Close(c))) => return Err(HandshakeError::Closed(c.map(|c| c.into_owned()))),
+                Some(Ok(Message::Text(_) | Message::Frame(_))) => return Err(HandshakeError::Unexpected),
+                Some(Err(e)) => return Err(HandshakeError::Read(e)),
+                None => return Err(HandshakeError::Closed(None)),
+            }
+        }
+    }).await
+        .map_err(|_| HandshakeError::Timeout(WELCOME_TIMEOUT))??;
+
+    match welcome {
+        Welcome::Accepted { protocol_version, software_version, file_types } => {
+            if protocol_version < MIN_PROTOCOL_VERSION || protocol_version > hello.protocol_version {
+                return Err(HandshakeError::Incompatible(protocol_version));
+            }
+            info!("processor {software_version} accepted. using protocol version {protocol_version}.");
+            let unsupported: Vec<&String> = hello.capabilities.file_types.iter()
+                .filter(|t| !file_types.contains(t))
+                .collect();
+            if !unsupported.is_empty() {
+                warn!("processor does not accept file types {unsupported:?}. it accepts {file_types:?}.");
+            }
+            Ok(protocol_version)
+        },
+        Welcome::Rejected { reason } => Err(HandshakeError::Rejected(reason)),
+    }
+}
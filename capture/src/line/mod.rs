@@ -1,4 +1,9 @@
+mod handshake;
+mod spool;
+mod tls;
+
 use std::borrow::Cow;
+use std::collections::HashSet;
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -6,33 +11,57 @@ use futures::{ SinkExt, StreamExt };
 use log::{debug, error, info, warn};
 use tokio::sync::Notify;
 use tokio::sync::mpsc::{Sender, Receiver};
-use tokio::time::sleep;
+use tokio::time::{sleep, sleep_until, Instant};
 use tokio::{runtime::Runtime, net::TcpStream, task::JoinHandle, sync::mpsc};
 use tokio_tungstenite::tungstenite::protocol::CloseFrame;
 use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
-use tokio_tungstenite::{MaybeTlsStream, WebSocketStream, tungstenite::Message};
+use tokio_tungstenite::{Connector, MaybeTlsStream, WebSocketStream, tungstenite::Message};
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::{HeaderValue, header::AUTHORIZATION};
 
 use common::capture::{Message as CMsg, CaptureResult};
+
+use crate::config::spool::DropPolicy;
+use crate::config::preview::RawUpload;
+use common::capture::settings::Settings;
 use common::processor::{Message as PMsg, CancelBehaviour};
+use common::handshake::{Hello, Capabilities, close_reason, ACK_PROTOCOL_VERSION, CHUNK_PROTOCOL_VERSION, PREVIEW_PROTOCOL_VERSION, CAMERAS_PROTOCOL_VERSION, LEGACY_PROTOCOL_VERSION};
+
+use handshake::handshake;
+use spool::{Spool, Entry, Class};
+use uuid::Uuid;
 
 const CONNECTION_FAILURE_RETRY_SLEEP: &[f64] = &[1.0, 1.0, 1.0, 10.0, 30.0, 60.0];
+const NACK_RETRY_DELAY: Duration = Duration::from_secs(10);
+const CHUNK_SIZE: usize = 1024 * 1024;
+
+type Ws = WebSocketStream<MaybeTlsStream<TcpStream>>;
 
 pub struct Line {
     _rt: Runtime,
     _handle: JoinHandle<()>,
 
     request_settings_tx: Sender<()>,
-    upload_tx: Sender<CaptureResult>,
+    spool: Arc<Spool>,
 
     settings_changed_notify: Arc<Notify>
 }
 
 impl Line {
-    pub fn new() -> Line {
+    pub fn new(capabilities: Capabilities) -> Line {
+        let hello = Hello::new(crate::CONFIG.general.name.clone(), capabilities);
+
         let (request_settings_tx, request_settings_rx) = mpsc::channel(1);
-        let (upload_tx, upload_rx) = mpsc::channel(crate::CONFIG.general.queue);
+        let spool = match Spool::open(crate::CONFIG.general.tmp_path.join("spool")) {
+            Ok(s) => Arc::new(s),
+            Err(e) => panic!("unable to open spool. {e}"),
+        };
 
         let settings_changed_notify = Arc::new(Notify::new());
+        let connector = tls::connector();
+        if crate::CONFIG.general.token.is_some() && crate::CONFIG.general.processor_url.scheme() != "wss" {
+            warn!("general.token is sent unencrypted, since general.processor_url is not wss://.");
+        }
 
         let rt = tokio::runtime::Builder::new_multi_thread()
             .thread_name("ws-rt")
@@ -43,23 +72,27 @@ impl Line {
 
         let handle: JoinHandle<()> = {
             let settings_changed_notify = settings_changed_notify.clone();
+            let spool = spool.clone();
 
             rt.spawn( async move {
-                worker(settings_changed_notify, upload_rx, request_settings_rx).await;
+                worker(hello, connector, settings_changed_notify, spool, request_settings_rx).await;
             })
         };
 
 
-        Line { _rt: rt, _handle: handle, request_settings_tx, upload_tx, settings_changed_notify }
+        Line { _rt: rt, _handle: handle, request_settings_tx, spool, settings_changed_notify }
     }
 
     pub fn subscribe_settings(& self) -> Arc<Notify> {
         self.settings_changed_notify.clone()
     }
 
+    /// Blocks until the settings of the node and those of every key in `cameras.settings` were received.
     pub async fn init_settings(&mut self) {
         tokio::select! {
-            () = self.settings_changed_notify.notified() => { },
+            () = async {
+                while !settings_complete() { self.settings_changed_notify.notified().await; }
+            } => { },
             _ = async {
                 self.request_settings_tx.send(()).await.unwrap();
                 loop {
@@ -70,51 +103,126 @@ impl Line {
         }
     }
 
+    /// Blocks while the spool is full and `spool.policy` is `stop`.
+    pub async fn reserve(&mut self) {
+        if crate::CONFIG.spool.policy == DropPolicy::Stop && self.spool.is_full() {
+            warn!("spool is full. capturing stopped until uploads drain.");
+            self.spool.room().await;
+            info!("spool has room again. capturing resumed.");
+        }
+    }
+
     pub async fn upload(&mut self, upload: CaptureResult) {
-        let max = crate::CONFIG.general.queue;
-        let cap = self.upload_tx.capacity();
-        let len = max - cap;
-        if len > 0 { debug!("queue has {len} items; max is {max}"); }
-        if cap > 0 {
-            self.upload_tx.send(upload).await.unwrap();
-        } else {
-            error!("upload channel full. message dropped.")
+        if let Err(e) = self.spool.push(&upload, Class::Normal).await {
+            error!("unable to spool {uuid}. message dropped. {e}", uuid = upload.uuid);
         }
     }
+
+    /// Uploads `preview` ahead of everything else. `upload` follows or is held back until requested, see `preview.raw`.
+    pub async fn upload_with_preview(&mut self, upload: CaptureResult, preview: CaptureResult) {
+        if let Err(e) = self.spool.push(&preview, Class::Priority).await {
+            error!("unable to spool preview {uuid}. message dropped. {e}", uuid = preview.uuid);
+        }
+        let class = match crate::CONFIG.preview.raw {
+            RawUpload::After => Class::Normal,
+            RawUpload::Demand => Class::Held,
+        };
+        if let Err(e) = self.spool.push(&upload, class).await {
+            error!("unable to spool {uuid}. message dropped. {e}", uuid = upload.uuid);
+        }
+    }
+}
+
+/// chunked upload currently being sent.
+struct Transfer {
+    entry: Entry,
+    /// position to continue at. `None` until the processor answered `UploadBegin`.
+    offset: Option<u64>,
 }
 
-async fn worker(settings_changed_notify: Arc<Notify>, mut upload_rx: Receiver<CaptureResult>, mut request_settings_rx: Receiver<()>) {
-    let (mut failure_cnt, mut open, mut ws) = connect_ws(0).await;
+async fn worker(hello: Hello, connector: Option<Connector>, settings_changed_notify: Arc<Notify>, spool: Arc<Spool>, mut request_settings_rx: Receiver<()>) {
+    let (mut failure_cnt, mut open, mut ws, mut protocol_version) = connect_ws(0, &hello, &connector).await;
+
+    // spooled uploads sent on this connection but not yet acknowledged.
+    let mut in_flight: HashSet<Uuid> = HashSet::new();
+    let mut active: Option<Transfer> = None;
+    let mut retry_at = Instant::now();
 
     loop {
+        match ws {
+            Some(_) => spool.set_busy(in_flight.iter().copied().chain(active.as_ref().map(|t| t.entry.uuid))),
+            None => spool.set_busy([]),
+        }
         if let Some(some_ws) = &mut ws {
+            // a priority upload overtakes a normal one. the processor keeps what it received and resumes it later.
+            if let Some(t) = active.as_ref().filter(|t| t.entry.class == Class::Normal) {
+                if let Some(p) = spool.next(&in_flight).filter(|e| e.class == Class::Priority) {
+                    debug!("pausing {uuid} for {priority}.", uuid = t.entry.uuid, priority = p.uuid);
+                    active = None;
+                }
+            }
+            let next = if active.is_none() && in_flight.len() < crate::CONFIG.general.queue { spool.next(&in_flight) } else { None };
+            let chunk_due = matches!(active, Some(Transfer { offset: Some(_), .. }));
             tokio::select! {
                 msg = some_ws.next() => {
                     match msg {
                         Some(Ok(Message::Binary(b))) => {
-                            match bincode::deserialize::<PMsg>(&b) {
+                            match common::processor::decode(&b, protocol_version) {
                                 Ok(msg) => match msg {
                                     PMsg::SetSettings{ settings, cancel_behaviour } => {
                                         info!("received new settings.");
-                                        let (changed, init) = {
-                                            let mut m = crate::SETTINGS.lock().unwrap();
-                                            if let Some(s) = m.as_ref() {
-                                                if *s != settings {
-                                                    *m = Some(settings);
-                                                    (true, false)
-                                                } else { (false, false) }
-                                                
-                                            } else {
-                                                *m = Some(settings);
-                                                (true, true)
+                                        let (mut changed, mut init) = (false, false);
+                                        if protocol_version < CAMERAS_PROTOCOL_VERSION {
+                                            // the processor cannot answer for keys. cameras with their own settings use those of the node.
+                                            for key in settings_keys() {
+                                                let (c, i) = replace_keyed(key, settings.clone());
+                                                (changed, init) = (changed || c, init || i);
                                             }
-                                        };
-                                        match (cancel_behaviour, changed, init) {
-                                            (_, _, true) | (CancelBehaviour::Allways, _, false) | (CancelBehaviour::IfUnequal, true, false) => {
-                                                debug!("sending cancelation token due to behaviour={cancel_behaviour:?} changed={changed}, init={init}");
-                                                settings_changed_notify.notify_waiters()
+                                        }
+                                        let (c, i) = replace(&mut crate::SETTINGS.lock().unwrap(), settings);
+                                        notify(&settings_changed_notify, cancel_behaviour, changed || c, init || i);
+                                    },
+                                    PMsg::SetSettingsOf { key, settings, cancel_behaviour } => {
+                                        if settings_keys().contains(&key) {
+                                            info!("received new settings of {key}.");
+                                            let (changed, init) = replace_keyed(key, settings);
+                                            notify(&settings_changed_notify, cancel_behaviour, changed, init);
+                                        } else {
+                                            warn!("received settings of {key}, which no camera uses.");
+                                        }
+                                    },
+                                    PMsg::Ack { uuid } => {
+                                        in_flight.remove(&uuid);
+                                        if active.as_ref().is_some_and(|t| t.entry.uuid == uuid) { active = None; }
+                                        acknowledged(&spool, uuid).await;
+                                    },
+                                    PMsg::Nack { uuid, reason, retry } => {
+                                        in_flight.remove(&uuid);
+                                        if active.as_ref().is_some_and(|t| t.entry.uuid == uuid) { active = None; }
+                                        if let Some(at) = refused(&spool, uuid, &reason, retry).await { retry_at = at; }
+                                    },
+                                    PMsg::Calibrate(calibration) => {
+                                        if calibration.kind.covered() && !calibration.covered {
+                                            warn!("refusing {calibration:?}. the lens was not confirmed to be covered.");
+                                        } else {
+                                            info!("received request for {frames} {kind} frames.", frames = calibration.frames, kind = calibration.kind.name());
+                                            crate::CALIBRATIONS.lock().unwrap().push_back(calibration);
+                                        }
+                                    },
+                                    PMsg::RequestRaw { uuid } => {
+                                        match spool.release(&uuid).await {
+                                            Ok(true) => info!("processor requested raw {uuid}. uploading it."),
+                                            Ok(false) => warn!("processor requested raw {uuid}, but it is not held back."),
+                                            Err(e) => error!("unable to release raw {uuid}. {e}"),
+                                        }
+                                    },
+                                    PMsg::Resume { uuid, offset } => {
+                                        match active.as_mut() {
+                                            Some(t) if t.entry.uuid == uuid => {
+                                                if offset > 0 { info!("resuming {uuid} at {offset} of {size} bytes.", size = t.entry.size); }
+                                                t.offset = Some(offset.min(t.entry.size));
                                             },
-                                            _ => { },
+                                            _ => warn!("received resume for inactive upload {uuid}."),
                                         }
                                     },
                                 },
@@ -123,7 +231,7 @@ async fn worker(settings_changed_notify: Arc<Notify>, mut upload_rx: Receiver<Ca
                                     open = false;
                                     if let Err(e) = some_ws.send(Message::Close(Some(CloseFrame {
                                             code: CloseCode::Unsupported,
-                                            reason: Cow::Owned(format!("cannot deserialize message. {e}"))
+                                            reason: Cow::Owned(close_reason(&format!("cannot deserialize message. {e}")))
                                         }))).await {
                                         error!("unable to send close frame. {e}");
                                         ws = None
@@ -137,7 +245,7 @@ async fn worker(settings_changed_notify: Arc<Notify>, mut upload_rx: Receiver<Ca
                                 open = false;
                                 if let Err(e) = some_ws.send(Message::Close(Some(CloseFrame {
                                         code: CloseCode::Unsupported,
-                                        reason: Cow::Owned(format!("text unsupported"))
+                                        reason: Cow::Owned(String::from("text unsupported"))
                                     }))).await {
                                     error!("unable to send close frame. {e}");
                                     ws = None
@@ -162,37 +270,193 @@ async fn worker(settings_changed_notify: Arc<Notify>, mut upload_rx: Receiver<Ca
                 },
                 rq = request_settings_rx.recv(), if open => {
                     rq.unwrap();
-                    match some_ws.send(Message::Binary(bincode::serialize(&CMsg::RequestSettings).unwrap())).await {
-                        Ok(()) => { debug!("sent RequestSettings"); },
-                        Err(e) => {
-                            error!("unable to send message. message dropped. dropping connection. {e}");
-                            ws = None;
-                        },
+                    let mut requests = vec![CMsg::RequestSettings];
+                    if protocol_version >= CAMERAS_PROTOCOL_VERSION {
+                        requests.extend(settings_keys().into_iter().map(|key| CMsg::RequestSettingsOf { key }));
                     }
-                }
-                item = upload_rx.recv(), if open => {
-                    let item = item.unwrap();
-                    debug!("sending {item:?}");
-                    match bincode::serialize(&CMsg::Upload(item)) {
-                        Ok(b) => match some_ws.send(Message::Binary(b)).await {
-                            Ok(()) => { },
+                    for msg in requests {
+                        match some_ws.send(Message::Binary(bincode::serialize(&msg).unwrap())).await {
+                            Ok(()) => { debug!("sent {msg:?}"); },
                             Err(e) => {
                                 error!("unable to send message. message dropped. dropping connection. {e}");
                                 ws = None;
-
+                                break;
                             },
                         }
-                        Err(e) => error!("unable to serialize message. message dropped. {e}"),
+                    }
+                }
+                () = spool.pushed(), if open && next.is_none() && !chunk_due => { },
+                () = sleep_until(retry_at), if open && next.is_some() && protocol_version >= CHUNK_PROTOCOL_VERSION => {
+                    let entry = next.unwrap();
+                    let msg = CMsg::UploadBegin { uuid: entry.uuid, size: entry.size, checksum: entry.checksum };
+                    debug!("sending {msg:?}");
+                    match some_ws.send(Message::Binary(bincode::serialize(&msg).unwrap())).await {
+                        Ok(()) => active = Some(Transfer { entry, offset: None }),
+                        Err(e) => {
+                            error!("unable to send {msg:?}. dropping connection. {e}");
+                            ws = None;
+                        },
+                    }
+                }
+                () = std::future::ready(()), if open && chunk_due => {
+                    let t = active.as_mut().unwrap();
+                    let (uuid, offset) = (t.entry.uuid, t.offset.unwrap());
+                    let data = match spool.read_chunk(&t.entry, offset, CHUNK_SIZE).await {
+                        Ok(data) => data,
+                        Err(e) => {
+                            error!("unable to read {uuid} from spool. upload dropped. {e}");
+                            spool.remove(&uuid).await;
+                            active = None;
+                            continue;
+                        },
+                    };
+                    let end = offset + data.len() as u64;
+                    match some_ws.send(Message::Binary(bincode::serialize(&CMsg::UploadChunk { uuid, offset, data }).unwrap())).await {
+                        Ok(()) => {
+                            if end >= t.entry.size {
+                                debug!("sent all {end} bytes of {uuid}.");
+                                in_flight.insert(uuid);
+                                active = None;
+                            } else {
+                                t.offset = Some(end);
+                            }
+                        },
+                        Err(e) => {
+                            error!("unable to send chunk of {uuid}. dropping connection. {e}");
+                            ws = None;
+                        },
+                    }
+                }
+                () = sleep_until(retry_at), if open && next.is_some() && protocol_version < CHUNK_PROTOCOL_VERSION => {
+                    let entry = next.unwrap();
+                    let uuid = entry.uuid;
+                    let frame = match spool.read(&entry).await.map(|item| { debug!("sending {item:?}"); bincode::serialize(&CMsg::Upload(item)) }) {
+                        Ok(Ok(frame)) => frame,
+                        Ok(Err(e)) => {
+                            error!("unable to serialize {uuid}. upload dropped. {e}");
+                            spool.remove(&uuid).await;
+                            continue;
+                        },
+                        Err(e) => {
+                            error!("unable to read {uuid} from spool. upload dropped. {e}");
+                            spool.remove(&uuid).await;
+                            continue;
+                        },
+                    };
+                    match some_ws.send(Message::Binary(frame)).await {
+                        Ok(()) => {
+                            if protocol_version < ACK_PROTOCOL_VERSION {
+                                // the processor will never acknowledge. send and forget.
+                                spool.remove(&uuid).await;
+                            } else {
+                                in_flight.insert(uuid);
+                            }
+                        },
+                        Err(e) => {
+                            error!("unable to send {uuid}. dropping connection. {e}");
+                            ws = None;
+                        },
                     }
                 }
             }
         } else {
-            (failure_cnt, open, ws) = connect_ws(failure_cnt).await;
+            (failure_cnt, open, ws, protocol_version) = connect_ws(failure_cnt, &hello, &connector).await;
+            if ws.is_some() {
+                if !in_flight.is_empty() { info!("sending {len} unacknowledged uploads again.", len = in_flight.len()); }
+                in_flight.clear();
+                active = None;
+                retry_at = Instant::now();
+                let backlog = spool.backlog();
+                if backlog > 0 { info!("draining {backlog} spooled uploads."); }
+                if protocol_version < CAMERAS_PROTOCOL_VERSION && !crate::CONFIG.cameras.is_empty() {
+                    warn!("processor speaks protocol version {protocol_version}. it files the frames of all cameras together and cameras with their own settings use those of the node.");
+                }
+                if protocol_version < PREVIEW_PROTOCOL_VERSION {
+                    let held = spool.held();
+                    if !held.is_empty() { warn!("processor cannot request raws. uploading {len} held back raws.", len = held.len()); }
+                    for uuid in held {
+                        if let Err(e) = spool.release(&uuid).await { error!("unable to release raw {uuid}. {e}"); }
+                    }
+                }
+            }
         }
     }
 }
 
-async fn connect_ws(failure_cnt: usize) -> (usize, bool, Option<WebSocketStream<MaybeTlsStream<TcpStream>>>) {
+/// Removes the upload `uuid` from the spool once the processor stored it.
+async fn acknowledged(spool: &Spool, uuid: Uuid) {
+    if spool.remove(&uuid).await {
+        debug!("{uuid} acknowledged. {len} uploads spooled.", len = spool.backlog());
+    } else {
+        warn!("received ack for unknown upload {uuid}.");
+    }
+}
+
+/// Handles the processor refusing the upload `uuid`. Returns when to send it again if `retry` is set.
+/// Otherwise it is held back in the spool, kept for a look by hand instead of being retried forever.
+async fn refused(spool: &Spool, uuid: Uuid, reason: &str, retry: bool) -> Option<Instant> {
+    if retry {
+        warn!("processor could not store {uuid}. retry in {NACK_RETRY_DELAY:?}. {reason}");
+        return Some(Instant::now() + NACK_RETRY_DELAY);
+    }
+    match spool.hold(&uuid).await {
+        Ok(true) => error!("processor refused {uuid}. holding it back. {reason}"),
+        Ok(false) => warn!("received nack for unknown upload {uuid}. {reason}"),
+        Err(e) => error!("processor refused {uuid}, which cannot be held back. {reason} {e}"),
+    }
+    None
+}
+
+/// Settings keys of the cameras, see `cameras.settings`.
+pub fn settings_keys() -> Vec<String> {
+    let mut keys: Vec<String> = crate::CONFIG.cameras.iter().filter_map(|c| c.settings.clone()).collect();
+    keys.sort();
+    keys.dedup();
+    keys
+}
+
+/// Whether the settings of the node and of every key were received.
+fn settings_complete() -> bool {
+    let keyed = crate::CAMERA_SETTINGS.lock().unwrap();
+    crate::SETTINGS.lock().unwrap().is_some() && settings_keys().iter().all(|k| keyed.contains_key(k))
+}
+
+/// Replaces `current` with `settings`. Returns whether they changed and whether they are the first.
+fn replace(current: &mut Option<Settings>, settings: Settings) -> (bool, bool) {
+    match current {
+        Some(s) if *s == settings => (false, false),
+        Some(_) => {
+            *current = Some(settings);
+            (true, false)
+        },
+        None => {
+            *current = Some(settings);
+            (true, true)
+        },
+    }
+}
+
+/// `replace` for the settings of `key`.
+fn replace_keyed(key: String, settings: Settings) -> (bool, bool) {
+    let mut keyed = crate::CAMERA_SETTINGS.lock().unwrap();
+    let mut current = keyed.remove(&key);
+    let replaced = replace(&mut current, settings);
+    keyed.insert(key, current.unwrap());
+    replaced
+}
+
+/// Cancels running captures according to `cancel_behaviour`. The first settings always do.
+fn notify(settings_changed_notify: &Notify, cancel_behaviour: CancelBehaviour, changed: bool, init: bool) {
+    match (cancel_behaviour, changed, init) {
+        (_, _, true) | (CancelBehaviour::Allways, _, false) | (CancelBehaviour::IfUnequal, true, false) => {
+            debug!("sending cancelation token due to behaviour={cancel_behaviour:?} changed={changed}, init={init}");
+            settings_changed_notify.notify_waiters()
+        },
+        _ => { },
+    }
+}
+
+async fn connect_ws(failure_cnt: usize, hello: &Hello, connector: &Option<Connector>) -> (usize, bool, Option<Ws>, u32) {
     let url = {
         let mut url = crate::CONFIG.general.processor_url.clone();
         let name = &crate::CONFIG.general.name;
@@ -201,17 +465,92 @@ async fn connect_ws(failure_cnt: usize) -> (usize, bool, Option<WebSocketStream<
         url.set_query(Some(format!("name={name}&latitude={latitude}&longitude={longitude}").as_str()));
         url
     };
-    match tokio_tungstenite::connect_async(&url).await {
-        Ok((ws, _)) => {
+    let mut request = (&url).into_client_request().unwrap();
+    if let Some(token) = &crate::CONFIG.general.token {
+        request.headers_mut().insert(AUTHORIZATION, HeaderValue::from_str(&format!("Bearer {token}")).unwrap());
+    }
+    match tokio_tungstenite::connect_async_tls_with_config(request, None, connector.clone()).await {
+        Ok((mut ws, _)) => {
             info!("Connected to {url}");
-            (0, true, Some(ws))
+            match handshake(&mut ws, hello).await {
+                Ok(protocol_version) => (0, true, Some(ws), protocol_version),
+                Err(e) => {
+                    let failure_cnt = failure_cnt + 1;
+                    let delay = retry_delay(failure_cnt);
+                    error!("handshake with {url} failed {failure_cnt} times so far. retry in {delay}s. {e}");
+                    if let Err(e) = ws.close(Some(CloseFrame {
+                            code: CloseCode::Policy,
+                            reason: Cow::Owned(close_reason(&format!("handshake failed. {e}")))
+                        })).await {
+                        debug!("unable to send close frame. {e}");
+                    }
+                    sleep(Duration::from_secs_f64(delay)).await;
+                    (failure_cnt, false, None, LEGACY_PROTOCOL_VERSION)
+                },
+            }
         },
         Err(e) => {
             let failure_cnt = failure_cnt + 1;
-            let delay = *CONNECTION_FAILURE_RETRY_SLEEP.get(failure_cnt - 1).unwrap_or(CONNECTION_FAILURE_RETRY_SLEEP.last().unwrap());
+            let delay = retry_delay(failure_cnt);
             warn!("Failed to connect to {url} {failure_cnt} times so far. retry in {delay}s. {e}");
             sleep(Duration::from_secs_f64(delay)).await;
-            (failure_cnt, false, None)
+            (failure_cnt, false, None, LEGACY_PROTOCOL_VERSION)
         },
     }
-}
\ No newline at end of file
+}
+
+fn retry_delay(failure_cnt: usize) -> f64 {
+    *CONNECTION_FAILURE_RETRY_SLEEP.get(failure_cnt - 1).unwrap_or(CONNECTION_FAILURE_RETRY_SLEEP.last().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a spool in a fresh directory with one normal upload of each of `uuids`.
+    fn spool(uuids: &[Uuid]) -> (Spool, std::path::PathBuf) {
+        let dir = std::env::temp_dir().join(format!("line-{uuid}", uuid = Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (seq, uuid) in uuids.iter().enumerate() {
+            std::fs::write(dir.join(spool::file_name(seq as u64, *uuid, 0, Class::Normal)), b"upload").unwrap();
+        }
+        (Spool::open(dir.clone()).unwrap(), dir)
+    }
+
+    #[tokio::test]
+    async fn ack_removes_upload() {
+        let uuid = Uuid::new_v4();
+        let (spool, dir) = spool(&[uuid]);
+        acknowledged(&spool, uuid).await;
+        assert_eq!(spool.backlog(), 0);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        // a second ack of the same upload is harmless.
+        acknowledged(&spool, uuid).await;
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn nack_with_retry_keeps_upload() {
+        let uuid = Uuid::new_v4();
+        let (spool, dir) = spool(&[uuid]);
+        let before = Instant::now();
+        let at = refused(&spool, uuid, "disk full.", true).await.unwrap();
+        assert!(at >= before + NACK_RETRY_DELAY);
+        assert_eq!(spool.next(&HashSet::new()).map(|e| (e.uuid, e.class)), Some((uuid, Class::Normal)));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn nack_without_retry_holds_upload_back() {
+        let (refused_uuid, other) = (Uuid::new_v4(), Uuid::new_v4());
+        let (spool, dir) = spool(&[refused_uuid, other]);
+        assert_eq!(refused(&spool, refused_uuid, "invalid camera id.", false).await, None);
+        assert_eq!(spool.held(), vec![refused_uuid]);
+        assert_eq!(spool.next(&HashSet::new()).map(|e| e.uuid), Some(other));
+        assert_eq!(spool.next(&HashSet::from([other])).map(|e| e.uuid), None);
+        // unknown uploads are ignored.
+        assert_eq!(refused(&spool, Uuid::new_v4(), "invalid camera id.", false).await, None);
+        assert_eq!(spool.backlog(), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
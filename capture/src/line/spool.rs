@@ -0,0 +1,454 @@
+use std::collections::{HashSet, VecDeque};
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use log::{debug, error, info, warn};
+use size_format::SizeFormatterBinary;
+use thiserror::Error;
+use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio::sync::Notify;
+use uuid::Uuid;
+
+use common::capture::CaptureResult;
+
+use crate::config::spool::DropPolicy;
+
+const EXT: &str = "upload";
+const PRIORITY_EXT: &str = "priority";
+const HELD_EXT: &str = "held";
+const TMP_EXT: &str = "partial";
+
+/// Uploads waiting for an acknowledgement, persisted under `general.tmp_path/spool`.
+/// Each upload is one file named `{seq}-{uuid}-{checksum}.upload`; `seq` preserves the capture order across restarts
+/// and `checksum` is the crc32 of the file, so it does not have to be read again before a chunked transfer.
+/// Priority uploads end in `.priority` instead, uploads held back until they are released in `.held`.
+pub struct Spool {
+    dir: PathBuf,
+    state: Mutex<State>,
+    /// notified whenever an upload was added.
+    pushed: Notify,
+    /// notified whenever an upload was removed.
+    drained: Notify,
+}
+
+struct State {
+    next_seq: u64,
+    entries: VecDeque<Entry>,
+    /// size of all entries and of the uploads being written.
+    bytes: u64,
+    /// uploads being written. they count against `spool.max_items` before they are entries.
+    reserved: usize,
+    /// uploads currently being sent or awaiting their acknowledgement. never dropped.
+    busy: HashSet<Uuid>,
+}
+
+#[derive(Clone)]
+pub struct Entry {
+    pub seq: u64,
+    pub uuid: Uuid,
+    pub size: u64,
+    pub checksum: u32,
+    pub path: PathBuf,
+    pub class: Class,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Class {
+    Normal,
+    /// sent ahead of all normal uploads.
+    Priority,
+    /// not sent until released.
+    Held,
+}
+
+impl Class {
+    fn ext(self) -> &'static str {
+        match self {
+            Class::Normal => EXT,
+            Class::Priority => PRIORITY_EXT,
+            Class::Held => HELD_EXT,
+        }
+    }
+
+    fn from_ext(ext: &str) -> Option<Class> {
+        [Class::Normal, Class::Priority, Class::Held].into_iter().find(|c| c.ext() == ext)
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum SpoolError {
+    #[error("IO Error. {0}")]
+    IO(std::io::Error),
+
+    #[error("unable to serialize upload. {0}")]
+    Serialize(bincode::Error),
+
+    #[error("unable to deserialize spooled upload. {0}")]
+    Deserialize(bincode::Error),
+
+    #[error("spool is full. upload dropped.")]
+    Full,
+
+    #[error("upload of {0}B exceeds spool.max_size. upload dropped.")]
+    TooLarge(u64),
+}
+
+impl Spool {
+    /// Opens the spool in `dir` and picks up uploads left over from previous runs.
+    pub fn open(dir: PathBuf) -> Result<Spool, SpoolError> {
+        std::fs::create_dir_all(&dir).map_err(SpoolError::IO)?;
+
+        let mut entries = vec![];
+        for f in std::fs::read_dir(&dir).map_err(SpoolError::IO)? {
+            let f = f.map_err(SpoolError::IO)?;
+            let path = f.path();
+            match path.extension().and_then(|e| e.to_str()) {
+                Some(TMP_EXT) => {
+                    debug!("removing incomplete {path:?} from spool.");
+                    std::fs::remove_file(&path).map_err(SpoolError::IO)?;
+                },
+                Some(ext) => match (Class::from_ext(ext), parse_name(&path)) {
+                    (Some(class), Some((seq, uuid, checksum))) => {
+                        let size = f.metadata().map_err(SpoolError::IO)?.len();
+                        entries.push(Entry { seq, uuid, size, checksum, path, class });
+                    },
+                    _ => warn!("ignoring unexpected file {path:?} in spool."),
+                },
+                None => warn!("ignoring unexpected file {path:?} in spool."),
+            }
+        }
+        entries.sort_by_key(|e| e.seq);
+
+        let next_seq = entries.last().map_or(0, |e| e.seq + 1);
+        let bytes = entries.iter().map(|e| e.size).sum();
+        if !entries.is_empty() {
+            info!("resuming with {len} spooled uploads of {size}B.", len = entries.len(), size = SizeFormatterBinary::new(bytes));
+        }
+
+        Ok(Spool {
+            dir,
+            state: Mutex::new(State { next_seq, entries: entries.into(), bytes, reserved: 0, busy: HashSet::new() }),
+            pushed: Notify::new(),
+            drained: Notify::new(),
+        })
+    }
+
+    /// Number of spooled uploads.
+    pub fn backlog(&self) -> usize {
+        self.state.lock().unwrap().entries.len()
+    }
+
+    pub fn is_full(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        state.entries.len() + state.reserved >= crate::CONFIG.spool.max_items || state.bytes >= crate::CONFIG.spool.max_size
+    }
+
+    /// Persists `upload` and enforces `spool.policy` if the spool is over budget.
+    /// The room for `upload` is reserved before it is written, so concurrent pushes cannot exceed the budget together.
+    pub async fn push(&self, upload: &CaptureResult, class: Class) -> Result<(), SpoolError> {
+        let b = bincode::serialize(upload).map_err(SpoolError::Serialize)?;
+        let size = b.len() as u64;
+        let checksum = crc32fast::hash(&b);
+        let (max_items, max_size) = (crate::CONFIG.spool.max_items, crate::CONFIG.spool.max_size);
+
+        if size > max_size { return Err(SpoolError::TooLarge(size)); }
+
+        let (seq, evicted) = {
+            let mut state = self.state.lock().unwrap();
+            let evicted = state.evict(size, max_items, max_size, crate::CONFIG.spool.policy)?;
+            let evicted: Vec<Entry> = evicted.iter().filter_map(|uuid| {
+                let i = state.entries.iter().position(|e| e.uuid == *uuid)?;
+                state.entries.remove(i)
+            }).collect();
+            state.bytes -= evicted.iter().map(|e| e.size).sum::<u64>();
+            state.bytes += size;
+            state.reserved += 1;
+            let seq = state.next_seq;
+            state.next_seq += 1;
+            (seq, evicted)
+        };
+
+        for e in evicted {
+            warn!("spool is full. dropping oldest upload {uuid}.", uuid = e.uuid);
+            if let Err(e) = fs::remove_file(&e.path).await {
+                error!("unable to remove dropped upload from spool. {e}");
+            }
+        }
+
+        let path = self.dir.join(file_name(seq, upload.uuid, checksum, class));
+        let tmp_path = path.with_extension(TMP_EXT);
+        let written = match fs::write(&tmp_path, &b).await {
+            Ok(()) => fs::rename(&tmp_path, &path).await,
+            Err(e) => Err(e),
+        };
+
+        {
+            let mut state = self.state.lock().unwrap();
+            state.reserved -= 1;
+            if written.is_err() {
+                state.bytes -= size;
+            } else {
+                state.entries.push_back(Entry { seq, uuid: upload.uuid, size, checksum, path, class });
+                debug!("spool holds {len} uploads of {total}B.", len = state.entries.len(), total = SizeFormatterBinary::new(state.bytes));
+            }
+        }
+        if let Err(e) = written {
+            self.drained.notify_waiters();
+            return Err(SpoolError::IO(e));
+        }
+        self.pushed.notify_one();
+        Ok(())
+    }
+
+    /// Oldest priority upload that is not in `skip`, else the oldest normal one.
+    pub fn next(&self, skip: &HashSet<Uuid>) -> Option<Entry> {
+        let state = self.state.lock().unwrap();
+        let mut sendable = state.entries.iter().filter(|e| !skip.contains(&e.uuid));
+        sendable.clone().find(|e| e.class == Class::Priority)
+            .or_else(|| sendable.find(|e| e.class == Class::Normal))
+            .cloned()
+    }
+
+    /// Turns the held upload with `uuid` into a normal one. Returns false if no such upload is held.
+    pub async fn release(&self, uuid: &Uuid) -> Result<bool, SpoolError> {
+        let path = {
+            let state = self.state.lock().unwrap();
+            match state.entries.iter().find(|e| e.uuid == *uuid && e.class == Class::Held) {
+                Some(e) => e.path.clone(),
+                None => return Ok(false),
+            }
+        };
+        let released = path.with_extension(EXT);
+        fs::rename(&path, &released).await.map_err(SpoolError::IO)?;
+        {
+            let mut state = self.state.lock().unwrap();
+            if let Some(e) = state.entries.iter_mut().find(|e| e.uuid == *uuid) {
+                e.class = Class::Normal;
+                e.path = released;
+            }
+        }
+        self.pushed.notify_one();
+        Ok(true)
+    }
+
+    /// Holds the upload with `uuid` back until it is released. Returns false if it was not spooled.
+    pub async fn hold(&self, uuid: &Uuid) -> Result<bool, SpoolError> {
+        let path = {
+            let state = self.state.lock().unwrap();
+            match state.entries.iter().find(|e| e.uuid == *uuid) {
+                Some(e) => e.path.clone(),
+                None => return Ok(false),
+            }
+        };
+        let held = path.with_extension(HELD_EXT);
+        fs::rename(&path, &held).await.map_err(SpoolError::IO)?;
+        {
+            let mut state = self.state.lock().unwrap();
+            if let Some(e) = state.entries.iter_mut().find(|e| e.uuid == *uuid) {
+                e.class = Class::Held;
+                e.path = held;
+            }
+        }
+        Ok(true)
+    }
+
+    /// Marks the uploads that are being sent or await their acknowledgement, so they are not dropped for new ones.
+    pub fn set_busy(&self, busy: impl IntoIterator<Item = Uuid>) {
+        self.state.lock().unwrap().busy = busy.into_iter().collect();
+    }
+
+    /// Uuids of all held uploads.
+    pub fn held(&self) -> Vec<Uuid> {
+        self.state.lock().unwrap().entries.iter()
+            .filter(|e| e.class == Class::Held)
+            .map(|e| e.uuid)
+            .collect()
+    }
+
+    pub async fn read(&self, entry: &Entry) -> Result<CaptureResult, SpoolError> {
+        let b = fs::read(&entry.path).await.map_err(SpoolError::IO)?;
+        common::capture::decode_result(&b).map_err(SpoolError::Deserialize)
+    }
+
+    /// Reads up to `len` bytes of the serialized upload starting at `offset`.
+    pub async fn read_chunk(&self, entry: &Entry, offset: u64, len: usize) -> Result<Vec<u8>, SpoolError> {
+        let mut f = fs::File::open(&entry.path).await.map_err(SpoolError::IO)?;
+        f.seek(SeekFrom::Start(offset)).await.map_err(SpoolError::IO)?;
+        let mut b = vec![0; len.min(entry.size.saturating_sub(offset) as usize)];
+        f.read_exact(&mut b).await.map_err(SpoolError::IO)?;
+        Ok(b)
+    }
+
+    /// Deletes the upload with `uuid`. Returns false if it was not spooled.
+    pub async fn remove(&self, uuid: &Uuid) -> bool {
+        let entry = {
+            let mut state = self.state.lock().unwrap();
+            match state.entries.iter().position(|e| e.uuid == *uuid) {
+                Some(i) => {
+                    let e = state.entries.remove(i).unwrap();
+                    state.bytes -= e.size;
+                    e
+                },
+                None => return false,
+            }
+        };
+        if let Err(e) = fs::remove_file(&entry.path).await {
+            error!("unable to remove {uuid} from spool. {e}");
+        }
+        self.drained.notify_waiters();
+        true
+    }
+
+    /// Resolves once an upload was pushed. Pushes in between calls are not lost.
+    pub async fn pushed(&self) {
+        self.pushed.notified().await
+    }
+
+    /// Resolves once the spool has room for another upload.
+    pub async fn room(&self) {
+        loop {
+            let drained = self.drained.notified();
+            if !self.is_full() { return; }
+            drained.await;
+        }
+    }
+}
+
+impl State {
+    /// Uploads to drop so another one of `size` bytes fits, or `Full` if it does not fit under `policy`.
+    /// Held uploads and those on their way are never dropped.
+    fn evict(&self, size: u64, max_items: usize, max_size: u64, policy: DropPolicy) -> Result<Vec<Uuid>, SpoolError> {
+        let (mut items, mut bytes) = (self.entries.len() + self.reserved + 1, self.bytes + size);
+        let mut evicted = vec![];
+        if items <= max_items && bytes <= max_size { return Ok(evicted); }
+        match policy {
+            DropPolicy::Oldest => {
+                for e in self.entries.iter().filter(|e| e.class != Class::Held && !self.busy.contains(&e.uuid)) {
+                    if items <= max_items && bytes <= max_size { break; }
+                    items -= 1;
+                    bytes -= e.size;
+                    evicted.push(e.uuid);
+                }
+                if items > max_items || bytes > max_size { return Err(SpoolError::Full); }
+                Ok(evicted)
+            },
+            DropPolicy::Newest | DropPolicy::Stop => Err(SpoolError::Full),
+        }
+    }
+}
+
+pub(super) fn file_name(seq: u64, uuid: Uuid, checksum: u32, class: Class) -> String {
+    format!("{seq:020}-{uuid}-{checksum:08x}.{ext}", uuid = uuid.as_hyphenated(), ext = class.ext())
+}
+
+fn parse_name(path: &Path) -> Option<(u64, Uuid, u32)> {
+    let stem = path.file_stem()?.to_str()?;
+    let (seq, rest) = stem.split_once('-')?;
+    let (uuid, checksum) = rest.rsplit_once('-')?;
+    Some((seq.parse().ok()?, Uuid::parse_str(uuid).ok()?, u32::from_str_radix(checksum, 16).ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(seq: u64, size: u64, class: Class) -> Entry {
+        let uuid = Uuid::new_v4();
+        Entry { seq, uuid, size, checksum: 0, path: PathBuf::from(file_name(seq, uuid, 0, class)), class }
+    }
+
+    fn state(entries: Vec<Entry>) -> State {
+        let bytes = entries.iter().map(|e| e.size).sum();
+        State { next_seq: entries.len() as u64, entries: entries.into(), bytes, reserved: 0, busy: HashSet::new() }
+    }
+
+    fn spool(dir: PathBuf, state: State) -> Spool {
+        Spool { dir, state: Mutex::new(state), pushed: Notify::new(), drained: Notify::new() }
+    }
+
+    #[test]
+    fn file_name_round_trip() {
+        let uuid = Uuid::new_v4();
+        for class in [Class::Normal, Class::Priority, Class::Held] {
+            let path = PathBuf::from("spool").join(file_name(42, uuid, 0xdeadbeef, class));
+            assert_eq!(parse_name(&path), Some((42, uuid, 0xdeadbeef)));
+            assert_eq!(path.extension().and_then(|e| e.to_str()).and_then(Class::from_ext), Some(class));
+        }
+        assert_eq!(parse_name(Path::new("spool/42-nouuid-deadbeef.upload")), None);
+        assert_eq!(parse_name(Path::new("spool/x-1.upload")), None);
+    }
+
+    #[test]
+    fn evict_oldest_skips_held_and_busy() {
+        let (held, busy, old, young) = (entry(0, 10, Class::Held), entry(1, 10, Class::Normal), entry(2, 10, Class::Normal), entry(3, 10, Class::Priority));
+        let mut s = state(vec![held.clone(), busy.clone(), old.clone(), young.clone()]);
+        s.busy.insert(busy.uuid);
+
+        assert_eq!(s.evict(10, 5, 100, DropPolicy::Oldest).unwrap(), Vec::<Uuid>::new());
+        assert_eq!(s.evict(10, 4, 100, DropPolicy::Oldest).unwrap(), vec![old.uuid]);
+        assert_eq!(s.evict(25, 10, 50, DropPolicy::Oldest).unwrap(), vec![old.uuid, young.uuid]);
+        assert!(matches!(s.evict(10, 2, 100, DropPolicy::Oldest), Err(SpoolError::Full)));
+        assert!(matches!(s.evict(35, 10, 50, DropPolicy::Oldest), Err(SpoolError::Full)));
+    }
+
+    #[test]
+    fn evict_counts_reserved_uploads() {
+        let mut s = state(vec![entry(0, 10, Class::Held)]);
+        assert_eq!(s.evict(10, 2, 100, DropPolicy::Oldest).unwrap(), Vec::<Uuid>::new());
+        s.reserved = 1;
+        s.bytes += 10;
+        assert!(matches!(s.evict(10, 2, 100, DropPolicy::Oldest), Err(SpoolError::Full)));
+        assert!(matches!(s.evict(10, 10, 25, DropPolicy::Oldest), Err(SpoolError::Full)));
+    }
+
+    #[test]
+    fn evict_other_policies_drop_the_newest() {
+        let s = state(vec![entry(0, 10, Class::Normal)]);
+        assert_eq!(s.evict(10, 2, 100, DropPolicy::Newest).unwrap(), Vec::<Uuid>::new());
+        assert!(matches!(s.evict(10, 1, 100, DropPolicy::Newest), Err(SpoolError::Full)));
+        assert!(matches!(s.evict(10, 1, 100, DropPolicy::Stop), Err(SpoolError::Full)));
+    }
+
+    #[test]
+    fn next_prefers_priority() {
+        let (normal, priority, held, later) = (entry(0, 1, Class::Normal), entry(1, 1, Class::Priority), entry(2, 1, Class::Held), entry(3, 1, Class::Priority));
+        let spool = spool(PathBuf::new(), state(vec![normal.clone(), priority.clone(), held, later.clone()]));
+
+        let mut skip = HashSet::new();
+        let mut order = vec![];
+        while let Some(e) = spool.next(&skip) {
+            skip.insert(e.uuid);
+            order.push(e.uuid);
+        }
+        assert_eq!(order, vec![priority.uuid, later.uuid, normal.uuid]);
+    }
+
+    #[test]
+    fn open_resumes_in_capture_order() {
+        let dir = std::env::temp_dir().join(format!("spool-{uuid}", uuid = Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let uuids: Vec<Uuid> = (0..3).map(|_| Uuid::new_v4()).collect();
+        for (seq, uuid) in [(10, uuids[2]), (2, uuids[0]), (7, uuids[1])] {
+            std::fs::write(dir.join(file_name(seq, uuid, 1, Class::Normal)), b"abc").unwrap();
+        }
+        let partial = dir.join(file_name(11, Uuid::new_v4(), 1, Class::Normal)).with_extension(TMP_EXT);
+        std::fs::write(&partial, b"ab").unwrap();
+        std::fs::write(dir.join("notes.txt"), b"").unwrap();
+
+        let spool = Spool::open(dir.clone()).unwrap();
+        assert_eq!(spool.backlog(), 3);
+        assert!(!partial.exists());
+        let mut skip = HashSet::new();
+        while let Some(e) = spool.next(&skip) {
+            assert_eq!((e.size, e.checksum), (3, 1));
+            skip.insert(e.uuid);
+        }
+        let state = spool.state.lock().unwrap();
+        assert_eq!(state.entries.iter().map(|e| e.uuid).collect::<Vec<_>>(), uuids);
+        assert_eq!((state.next_seq, state.bytes), (11, 9));
+        drop(state);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
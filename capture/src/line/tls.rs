@@ -0,0 +1,45 @@
+use std::sync::Arc;
+
+use log::{ info, warn };
+use rustls::{ ClientConfig, OwnedTrustAnchor, RootCertStore };
+use tokio_tungstenite::Connector;
+
+use common::tls::{ load_certs, load_key, load_roots };
+
+/// Builds the connector for `wss://` from the `tls` section.
+/// `None` if nothing is configured, in which case the public roots are used and no client certificate is sent.
+/// Panics on unusable certificates, just like on an invalid config.
+pub fn connector() -> Option<Connector> {
+    let tls = &crate::CONFIG.tls;
+    if tls.ca.is_none() && tls.cert.is_none() && tls.key.is_none() { return None; }
+    if crate::CONFIG.general.processor_url.scheme() != "wss" {
+        warn!("tls is configured, but general.processor_url is not wss://. tls settings are ignored.");
+    }
+
+    let roots = match &tls.ca {
+        Some(path) => load_roots(path).unwrap_or_else(|e| panic!("unable to load tls.ca. {e}")),
+        None => {
+            let mut roots = RootCertStore::empty();
+            roots.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|ta| {
+                OwnedTrustAnchor::from_subject_spki_name_constraints(ta.subject, ta.spki, ta.name_constraints)
+            }));
+            roots
+        },
+    };
+
+    let builder = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots);
+    let config = match (&tls.cert, &tls.key) {
+        (Some(cert), Some(key)) => {
+            let certs = load_certs(cert).unwrap_or_else(|e| panic!("unable to load tls.cert. {e}"));
+            let key = load_key(key).unwrap_or_else(|e| panic!("unable to load tls.key. {e}"));
+            info!("using client certificate {cert:?}.");
+            builder.with_single_cert(certs, key).unwrap_or_else(|e| panic!("unable to use tls.cert and tls.key. {e}"))
+        },
+        (None, None) => builder.with_no_client_auth(),
+        _ => panic!("tls.cert and tls.key have to be set together."),
+    };
+
+    Some(Connector::Rustls(Arc::new(config)))
+}
@@ -18,23 +18,22 @@ pub fn init() {
 fn generate_config() -> Result<log4rs::Config> {
     let level = crate::CONFIG.logging.level;
     let path = crate::CONFIG.logging.path.clone();
-    let size = crate::CONFIG.logging.size.checked_mul(1024 * 1024).unwrap_or(u64::MAX);
+    let size = crate::CONFIG.logging.size.saturating_mul(1024 * 1024);
     let count = crate::CONFIG.logging.count;
     let pattern = "{d(%Y-%m-%d %H:%M:%S)} {h({l}):5} {t} {T} - {m}{n}";
 
     let console_appender = ConsoleAppender::builder()
         .target(Target::Stdout)
-        .encoder(Box::new(PatternEncoder::new(&pattern)))
+        .encoder(Box::new(PatternEncoder::new(pattern)))
         .build();
 
     let rolling_file_appender = RollingFileAppender::builder()
-        .encoder(Box::new(PatternEncoder::new(&pattern)))
+        .encoder(Box::new(PatternEncoder::new(pattern)))
         .build(
             Path::new(&path)
                 .join("hematite-capture.log")
                 .to_str()
-                .unwrap()
-                .to_string(),
+                .unwrap(),
             Box::new(CompoundPolicy::new(
                 Box::new(SizeTrigger::new(size)),
                 Box::new(
@@ -43,9 +42,7 @@ fn generate_config() -> Result<log4rs::Config> {
                             Path::new(&path)
                                 .join("{}.log")
                                 .to_str()
-                                .unwrap()
-                                .to_string()
-                                .as_str(),
+                                .unwrap(),
                             count,
                         )
                         ?,
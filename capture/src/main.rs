@@ -8,23 +8,28 @@ mod capture;
 
 use chrono::{Local, DateTime};
 
-use std::{sync::Mutex, time::Duration};
+use std::{collections::{HashMap, VecDeque}, sync::Mutex, time::Duration};
+use futures::future::join_all;
 use tokio::time::sleep;
 use log::{ error, warn, info, debug };
 
 use lazy_static::lazy_static;
 
-use common::{ self, capture::settings::Settings };
+use common::{ self, capture::{settings::{Settings, dntime::DNTime}, calibration::{Calibration, FrameKind}}, handshake::Capabilities };
 use config::Config;
 
 use line::Line;
 use tracking::Tracking;
 
-use crate::capture::{CaptureCommand, CaptureError};
+use crate::capture::{Camera, CaptureCommand, CaptureError, Captured};
 
 lazy_static!{
     static ref CONFIG: Config = Config::new();
     static ref SETTINGS: Mutex<Option<Settings>> = Mutex::new(None);
+    /// settings of the cameras by their `cameras.settings` key.
+    static ref CAMERA_SETTINGS: Mutex<HashMap<String, Settings>> = Mutex::new(HashMap::new());
+    /// calibration frames to capture before the next light frame.
+    static ref CALIBRATIONS: Mutex<VecDeque<Calibration>> = Mutex::new(VecDeque::new());
 }
 
 #[tokio::main(flavor = "current_thread")]
@@ -32,12 +37,23 @@ async fn main() {
     // initializing logger
     logging::init();
 
-    // inittialize module
-    let mut camera = capture::build();
+    // inittialize modules
+    let mut cameras = capture::build();
 
     // initializing async websocket
-    let mut line = Line::new();
-    // get settings from remote. will block until SETTINGS is set to Some().
+    let mut file_types: Vec<String> = cameras.iter().flat_map(|c| c.capture.file_types()).map(|t| t.ext()).collect();
+    file_types.sort();
+    file_types.dedup();
+    let mut modules: Vec<String> = cameras.iter().map(|c| c.module.to_string()).collect();
+    modules.sort();
+    modules.dedup();
+    let mut line = Line::new(Capabilities {
+        file_types,
+        module: modules.join(","),
+        tracking: CONFIG.tracking.enable,
+        settings_keys: line::settings_keys(),
+    });
+    // get settings from remote. will block until SETTINGS is set to Some() and the settings of every camera arrived.
     // SETTINGS will allways be Some after this point.
     line.init_settings().await;
     assert!(SETTINGS.lock().unwrap().is_some(), "SETTNGS were None");
@@ -51,67 +67,182 @@ async fn main() {
         t.start_homing().await;
     } 
 
-    let mut is_night = {
-        let timestamp_millis_now = Local::now().timestamp_millis();
-        let is_night = sun::is_night(timestamp_millis_now);
-        info!("using {} settings. sun is at {} while horizon is at {}",
-            if is_night { "nighttime" } else { "daytime "},
-            sun::altitude(timestamp_millis_now),
-            SETTINGS.lock().unwrap().as_ref().unwrap().horizon
-        );
-        is_night
-    };
+    // phase of the settings of each camera. logged whenever it changes.
+    let mut phases = vec![String::new(); cameras.len()];
+    let mut isos = vec![Isos::default(); cameras.len()];
+
+    let calibration = &CONFIG.calibration;
+    if (calibration.darks > 0 || calibration.bias > 0) && !calibration.covered {
+        warn!("scheduled darks and bias frames are skipped. calibration.covered does not confirm the lens is covered at dawn.");
+    }
 
     let mut now = Local::now();
+    let mut altitude = sun::altitude(now.timestamp_millis());
 
     loop {
-        update_is_night(now, &mut is_night);
+        // only blocks if spool.policy is stop and the processor is behind
+        line.reserve().await;
+
+        // new settings or a new probe of the camera may change the ISO on either side.
+        for (camera, isos) in cameras.iter_mut().zip(isos.iter_mut()) {
+            isos.offered = camera.capture.status().await.map(|s| s.isos).unwrap_or_default();
+            check_isos(camera, isos);
+        }
 
+        let commands: Vec<(bool, DNTime)> = cameras.iter().zip(phases.iter_mut()).zip(&isos)
+            .map(|((camera, phase), isos)| {
+                let (is_night, settings) = update_phase(camera, now, phase);
+                (is_night, snap_iso(camera, settings, &isos.offered))
+            })
+            .collect();
+
+        schedule_calibration(now, &mut altitude);
+        let pending: Vec<Calibration> = CALIBRATIONS.lock().unwrap().drain(..).collect();
+        for calibration in pending {
+            for (camera, (is_night, settings)) in cameras.iter_mut().zip(&commands) {
+                calibrate(camera, &mut line, calibration, settings, *is_night).await;
+            }
+        }
+
+        // tracks until homing below, across all subframes and bracket frames of the capture.
         if let Some(t) = tracking.as_mut() {
             t.track().await;
         }
 
-        match camera.capture(CaptureCommand {
+        // all cameras expose at once. their uploads follow in the order of the cameras.
+        let commands: Vec<CaptureCommand> = commands.into_iter().map(|(is_night, settings)| CaptureCommand {
             cancel_token: line.subscribe_settings(),
             time: now,
             is_night,
-            settings: if is_night { SETTINGS.lock().unwrap().as_ref().unwrap().nighttime }
-                else { SETTINGS.lock().unwrap().as_ref().unwrap().daytime }
-        }).await {
-            Ok(c) => {
-                info!("capture complete!");
-                line.upload(c).await;
-                debug!("sent.");
-            },
-            Err(CaptureError::Cancelled) => info!("capture was cancelled."),
-            Err(CaptureError::Module(e)) => error!("capture encountured an error. {e}"),
+            settings,
+        }).collect();
+        let results = join_all(cameras.iter_mut().zip(commands).map(|(camera, cmd)| camera.capture.capture(cmd))).await;
+        for (camera, result) in cameras.iter().zip(results) {
+            match result {
+                Ok(Captured { mut result, preview }) => {
+                    info!("{camera} completed its capture!");
+                    result.camera = camera.id.clone();
+                    match preview {
+                        Some(mut preview) => {
+                            preview.camera = camera.id.clone();
+                            line.upload_with_preview(result, preview).await
+                        },
+                        None => line.upload(result).await,
+                    }
+                    debug!("sent.");
+                },
+                Err(CaptureError::Cancelled) => info!("capture of {camera} was cancelled."),
+                Err(CaptureError::Module(e)) => error!("capture of {camera} encountured an error. {e}"),
+            }
         }
 
         if let Some(t) = tracking.as_mut() {
             t.start_homing().await;
         }
-        now = delay(is_night, now).await;
+        now = delay(now).await;
+    }
+}
+
+/// Settings of `camera`. Those of the node if it has no key of its own.
+fn settings(camera: &Camera) -> Settings {
+    let keyed = camera.settings.as_ref().and_then(|key| CAMERA_SETTINGS.lock().unwrap().get(key).cloned());
+    keyed.unwrap_or_else(|| SETTINGS.lock().unwrap().clone().unwrap())
+}
+
+/// ISO a camera offers, empty while unknown, and the ISO of the settings and the camera last checked.
+#[derive(Clone, Default)]
+struct Isos {
+    offered: Vec<u32>,
+    checked: Option<(Vec<u32>, Vec<u32>)>,
+}
+
+/// Logs the manual ISO of the settings that the camera does not offer, unless the same were checked before.
+fn check_isos(camera: &Camera, isos: &mut Isos) {
+    if isos.offered.is_empty() { return; }
+    let used = settings(camera).isos();
+    if isos.checked.as_ref().is_some_and(|(u, o)| *u == used && *o == isos.offered) { return; }
+    let missing: Vec<u32> = used.iter().copied().filter(|iso| !isos.offered.contains(iso)).collect();
+    if !missing.is_empty() {
+        warn!("settings use ISO {missing:?}, which {camera} does not offer. the nearest it offers is used instead. available: {offered:?}", offered = isos.offered);
+    }
+    isos.checked = Some((used, isos.offered.clone()));
+}
+
+/// Snaps the ISO of `settings` to the nearest one `offered`, including ramped ISO.
+fn snap_iso(camera: &Camera, settings: DNTime, offered: &[u32]) -> DNTime {
+    let snapped = settings.snap_iso(offered);
+    if snapped.iso != settings.iso {
+        debug!("{camera} does not offer {iso:?}. using {snapped:?}.", iso = settings.iso, snapped = snapped.iso);
+    }
+    snapped
+}
+
+/// Picks the phase of the sun at `time` and logs when it changed.
+/// Returns whether it is night according to `horizon` and the settings of the phase.
+fn update_phase(camera: &Camera, time: DateTime<Local>, last_phase: &mut String) -> (bool, DNTime) {
+    debug!("updating phase of {camera}");
+    let altitude = sun::altitude(time.timestamp_millis());
+    let settings = settings(camera);
+    let (phase, dntime) = settings.phase(altitude);
+    if phase != last_phase {
+        info!("{camera} now uses {phase} settings. sun is at {altitude} while horizon is at {horizon}", horizon = settings.horizon);
+        *last_phase = phase.to_owned();
+    }
+    (altitude < settings.horizon, dntime)
+}
+
+/// Queues the scheduled calibration frames once the sun rises above `calibration.dawn`.
+fn schedule_calibration(time: DateTime<Local>, last_altitude: &mut f64) {
+    let altitude = sun::altitude(time.timestamp_millis());
+    let c = &CONFIG.calibration;
+    if *last_altitude < c.dawn && altitude >= c.dawn && c.covered {
+        info!("sun rose above {dawn}. scheduling calibration frames.", dawn = c.dawn);
+        let mut calibrations = CALIBRATIONS.lock().unwrap();
+        for (kind, frames) in [(FrameKind::Dark, c.darks), (FrameKind::Bias, c.bias)] {
+            if frames > 0 { calibrations.push_back(Calibration { kind, frames, covered: true }); }
+        }
     }
+    *last_altitude = altitude;
 }
 
-fn update_is_night(time: DateTime<Local>, last_is_night: &mut bool) -> bool {
-    debug!("updating is_night");
-    let is_night = sun::is_night(time.timestamp_millis());
-    if is_night != *last_is_night {
-        info!("now using {} settings. sun is at {} while horizon is at {}",
-            if is_night { "nighttime" } else { "daytime "},
-            sun::altitude(time.timestamp_millis()),
-            SETTINGS.lock().unwrap().as_ref().unwrap().horizon
-            );
-        *last_is_night = is_night;
-        true
-    } else { false }
+/// Captures the frames of `calibration` with the settings derived from those of the light frames.
+async fn calibrate(camera: &mut Camera, line: &mut Line, calibration: Calibration, light: &DNTime, is_night: bool) {
+    let kind = calibration.kind.name();
+    let settings = match calibration.settings(light) {
+        Some(s) => s,
+        None => {
+            warn!("skipping {kind} frames of {camera}. the current settings have no manual exposure.");
+            return;
+        },
+    };
+    info!("capturing {frames} {kind} frames with {camera}.", frames = calibration.frames);
+    for i in 1..=calibration.frames {
+        line.reserve().await;
+        match camera.capture.capture(CaptureCommand {
+            cancel_token: line.subscribe_settings(),
+            time: Local::now(),
+            is_night,
+            settings: settings.clone(),
+        }).await {
+            Ok(Captured { result: mut c, .. }) => {
+                c.kind = calibration.kind;
+                c.camera = camera.id.clone();
+                line.upload(c).await;
+            },
+            Err(CaptureError::Cancelled) => {
+                info!("calibration was cancelled.");
+                return;
+            },
+            Err(CaptureError::Module(e)) => error!("{kind} frame {i} of {frames} of {camera} encountured an error. {e}", frames = calibration.frames),
+        }
+    }
 }
 
-async fn delay(is_night: bool, last: DateTime<Local>) -> DateTime<Local> {
+/// Waits for the next frame. The frame of the node's settings paces all cameras.
+async fn delay(last: DateTime<Local>) -> DateTime<Local> {
     let frame = {
-        if is_night { SETTINGS.lock().unwrap().as_ref().unwrap().nighttime.frame }
-        else { SETTINGS.lock().unwrap().as_ref().unwrap().daytime.frame }
+        let altitude = sun::altitude(last.timestamp_millis());
+        SETTINGS.lock().unwrap().as_ref().unwrap().phase(altitude).1.frame
     };
     let now = Local::now();
 
@@ -0,0 +1,66 @@
+use serde::{ Serialize, Deserialize };
+
+use super::settings::dntime::{ DNTime, Exposure };
+
+/// Exposure of bias frames in seconds. the shortest the camera offers is used.
+pub const BIAS_EXPOSURE: f64 = 1.0 / 8000.0;
+
+/// What a `CaptureResult` holds. Everything but `Light` is filed into the calibration library of the processor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FrameKind {
+    Light,
+    /// lens covered, exposure and ISO of the light frames.
+    Dark,
+    /// evenly lit, aperture and ISO of the light frames.
+    Flat,
+    /// lens covered, shortest exposure at the ISO of the light frames.
+    Bias,
+}
+
+impl FrameKind {
+    pub const ALL: &'static [FrameKind] = &[FrameKind::Light, FrameKind::Dark, FrameKind::Flat, FrameKind::Bias];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FrameKind::Light => "light",
+            FrameKind::Dark => "dark",
+            FrameKind::Flat => "flat",
+            FrameKind::Bias => "bias",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<FrameKind> {
+        FrameKind::ALL.iter().copied().find(|k| k.name().eq_ignore_ascii_case(name))
+    }
+
+    /// Whether the lens has to be covered.
+    pub fn covered(&self) -> bool {
+        matches!(self, FrameKind::Dark | FrameKind::Bias)
+    }
+}
+
+/// Request to capture `frames` calibration frames of `kind` with the settings of the current light frames.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Calibration {
+    pub kind: FrameKind,
+    pub frames: u32,
+    /// the lens was confirmed to be covered. darks and bias are refused without.
+    pub covered: bool,
+}
+
+impl Calibration {
+    /// Settings to capture the calibration frames of the light frames taken with `light`.
+    /// Exposure bracket and stack are not applied. `None` for darks of automatic exposures.
+    pub fn settings(&self, light: &DNTime) -> Option<DNTime> {
+        let exposure = match self.kind {
+            FrameKind::Light => light.exposure,
+            FrameKind::Dark => match light.exposure {
+                Exposure::Manual(f) => Exposure::Manual(f),
+                Exposure::Auto => return None,
+            },
+            FrameKind::Flat => Exposure::Auto,
+            FrameKind::Bias => Exposure::Manual(BIAS_EXPOSURE),
+        };
+        Some(DNTime { exposure, bracket: None, stack: None, ..light.clone() })
+    }
+}
@@ -1,20 +1,151 @@
 use serde::{Serialize, Deserialize};
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Variants are only ever appended, they are serialized by index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum FileType {
     Dummy,
     Cr2,
+    Cr3,
+    Nef,
+    Arw,
+    Dng,
+    Jpeg,
+    Png,
+    Tiff,
 }
 
+/// TIFF tag of the camera make.
+const TAG_MAKE: u16 = 0x010f;
+/// TIFF tag only DNGs have.
+const TAG_DNG_VERSION: u16 = 0xc612;
+
 impl FileType {
+    pub const ALL: &'static [FileType] = &[FileType::Dummy, FileType::Cr2, FileType::Cr3, FileType::Nef, FileType::Arw, FileType::Dng, FileType::Jpeg, FileType::Png, FileType::Tiff];
+
     pub fn ext(&self) -> String {
         match self {
-            FileType::Dummy => format!("dummy"),
-            FileType::Cr2 => format!("cr2"),
+            FileType::Dummy => String::from("dummy"),
+            FileType::Cr2 => String::from("cr2"),
+            FileType::Cr3 => String::from("cr3"),
+            FileType::Nef => String::from("nef"),
+            FileType::Arw => String::from("arw"),
+            FileType::Dng => String::from("dng"),
+            FileType::Jpeg => String::from("jpg"),
+            FileType::Png => String::from("png"),
+            FileType::Tiff => String::from("tif"),
         }
     }
 
     pub fn dotext(&self) -> String {
         format!(".{ext}", ext = self.ext())
     }
-}
\ No newline at end of file
+
+    pub fn from_ext(ext: &str) -> Option<FileType> {
+        if ext.eq_ignore_ascii_case("jpeg") { return Some(FileType::Jpeg); }
+        if ext.eq_ignore_ascii_case("tiff") { return Some(FileType::Tiff); }
+        FileType::ALL.iter().copied().find(|t| t.ext().eq_ignore_ascii_case(ext))
+    }
+
+    /// Detects the type of a file from its magic bytes.
+    /// NEF, ARW and DNG are plain TIFFs, they are told apart by the DNG version and the make in the first IFD.
+    /// TIFFs without a make are images, those of an unknown make are not detected.
+    pub fn detect(file: &[u8]) -> Option<FileType> {
+        if file.starts_with(&[0xff, 0xd8, 0xff]) {
+            return Some(FileType::Jpeg);
+        }
+        if file.starts_with(b"\x89PNG\r\n\x1a\n") {
+            return Some(FileType::Png);
+        }
+        if file.get(4..8) == Some(b"ftyp") && file.get(8..12) == Some(b"crx ") {
+            return Some(FileType::Cr3);
+        }
+        let big_endian = match file.get(0..4)? {
+            b"II*\0" => false,
+            b"MM\0*" => true,
+            _ => return None,
+        };
+        if file.get(8..11) == Some(b"CR\x02") {
+            return Some(FileType::Cr2);
+        }
+
+        let u16_at = |o: usize| file.get(o..o + 2).map(|b| if big_endian { u16::from_be_bytes([b[0], b[1]]) } else { u16::from_le_bytes([b[0], b[1]]) });
+        let u32_at = |o: usize| file.get(o..o + 4).map(|b| if big_endian { u32::from_be_bytes([b[0], b[1], b[2], b[3]]) } else { u32::from_le_bytes([b[0], b[1], b[2], b[3]]) });
+
+        let ifd = u32_at(4)? as usize;
+        let mut make = None;
+        for i in 0..u16_at(ifd)? as usize {
+            let entry = ifd + 2 + i * 12;
+            match u16_at(entry)? {
+                TAG_DNG_VERSION => return Some(FileType::Dng),
+                TAG_MAKE => {
+                    // ascii, stored in the entry itself if it fits into 4 bytes.
+                    let count = u32_at(entry + 4)? as usize;
+                    let offset = if count <= 4 { entry + 8 } else { u32_at(entry + 8)? as usize };
+                    make = file.get(offset..offset + count).map(|m| String::from_utf8_lossy(m).to_ascii_uppercase());
+                },
+                _ => {},
+            }
+        }
+        match make {
+            None => Some(FileType::Tiff),
+            Some(m) if m.starts_with("NIKON") => Some(FileType::Nef),
+            Some(m) if m.starts_with("SONY") => Some(FileType::Arw),
+            Some(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// little endian TIFF with a single IFD of one ascii entry of `tag`, stored behind the IFD.
+    fn tiff(tag: u16, value: &[u8]) -> Vec<u8> {
+        let mut file = b"II*\0".to_vec();
+        file.extend(8u32.to_le_bytes());
+        file.extend(1u16.to_le_bytes());
+        file.extend(tag.to_le_bytes());
+        file.extend(2u16.to_le_bytes());
+        file.extend((value.len() as u32).to_le_bytes());
+        file.extend(26u32.to_le_bytes());
+        file.extend(0u32.to_le_bytes());
+        file.extend(value);
+        file
+    }
+
+    #[test]
+    fn detect_magic_bytes() {
+        assert_eq!(FileType::detect(&[0xff, 0xd8, 0xff, 0xe0]), Some(FileType::Jpeg));
+        assert_eq!(FileType::detect(b"\x89PNG\r\n\x1a\n...."), Some(FileType::Png));
+        assert_eq!(FileType::detect(b"\0\0\0\x18ftypcrx \0\0\0\x01"), Some(FileType::Cr3));
+        assert_eq!(FileType::detect(b"II*\0\x10\0\0\0CR\x02\0"), Some(FileType::Cr2));
+        assert_eq!(FileType::detect(b"not an image"), None);
+        assert_eq!(FileType::detect(&[]), None);
+    }
+
+    #[test]
+    fn detect_tiff_by_make() {
+        assert_eq!(FileType::detect(&tiff(TAG_MAKE, b"NIKON CORPORATION\0")), Some(FileType::Nef));
+        assert_eq!(FileType::detect(&tiff(TAG_MAKE, b"SONY\0")), Some(FileType::Arw));
+        assert_eq!(FileType::detect(&tiff(TAG_DNG_VERSION, b"\x01\x04\0\0")), Some(FileType::Dng));
+        assert_eq!(FileType::detect(&tiff(TAG_MAKE, b"Unknown\0")), None);
+        // a TIFF without a make is an image.
+        assert_eq!(FileType::detect(&tiff(0x0100, b"\0\0\0\0")), Some(FileType::Tiff));
+    }
+
+    #[test]
+    fn detect_truncated_tiff() {
+        let file = tiff(TAG_MAKE, b"NIKON CORPORATION\0");
+        assert_eq!(FileType::detect(&file[..12]), None);
+    }
+
+    #[test]
+    fn from_ext() {
+        assert_eq!(FileType::from_ext("JPEG"), Some(FileType::Jpeg));
+        assert_eq!(FileType::from_ext("cr3"), Some(FileType::Cr3));
+        assert_eq!(FileType::from_ext("exe"), None);
+        for t in FileType::ALL {
+            assert_eq!(FileType::from_ext(&t.ext()), Some(*t));
+        }
+    }
+}
@@ -1,18 +1,48 @@
 pub mod settings;
+pub mod calibration;
 mod filetype;
 
 use size_format::SizeFormatterBinary;
 use uuid::Uuid;
 use chrono::{ DateTime, Local };
 
-use serde::{ Serialize, Deserialize };
+use serde::{ Serialize, Deserialize, de::DeserializeOwned };
 
 pub use filetype::FileType;
+use calibration::FrameKind;
+use settings::dntime::{ DNTime, DNTimeV1, DNTimeV6 };
+use crate::handshake::{ RAMP_PROTOCOL_VERSION, BRACKET_PROTOCOL_VERSION, STACK_PROTOCOL_VERSION, CALIBRATION_PROTOCOL_VERSION, PREVIEW_PROTOCOL_VERSION, CAMERAS_PROTOCOL_VERSION };
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Serialize, Deserialize)]
 pub enum Message {
     RequestSettings,
-    Upload(CaptureResult)
+    Upload(CaptureResult),
+    /// announces a chunked upload of a bincode serialized `CaptureResult` of `size` bytes, in the layout of the negotiated protocol version.
+    /// answered with `processor::Message::Resume` or, if already stored, `Ack`.
+    UploadBegin { uuid: Uuid, size: u64, checksum: u32 },
+    UploadChunk { uuid: Uuid, offset: u64, data: Vec<u8> },
+    /// requests the settings stored under `key` instead of the node's name. answered with `processor::Message::SetSettingsOf`.
+    RequestSettingsOf { key: String },
+}
+
+impl std::fmt::Debug for Message {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Message::RequestSettings => write!(f, "RequestSettings"),
+            Message::Upload(c) => f.debug_tuple("Upload").field(c).finish(),
+            Message::UploadBegin { uuid, size, checksum } => f.debug_struct("UploadBegin")
+                .field("uuid", uuid)
+                .field("size", &format!("{}B", SizeFormatterBinary::new(*size)))
+                .field("checksum", &format!("{checksum:08x}"))
+                .finish(),
+            Message::UploadChunk { uuid, offset, data } => f.debug_struct("UploadChunk")
+                .field("uuid", uuid)
+                .field("offset", offset)
+                .field("data", &format!("Vec<u8> of length {}B", SizeFormatterBinary::new(data.len() as u64)))
+                .finish(),
+            Message::RequestSettingsOf { key } => f.debug_struct("RequestSettingsOf").field("key", key).finish(),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -24,6 +54,43 @@ pub struct CaptureResult {
 
     pub file_type: FileType,
     pub file: Vec<u8>,
+
+    /// settings the frame was captured with, including ramped exposure and ISO. `None` for results of older nodes.
+    pub settings: Option<DNTime>,
+
+    /// frames at the offsets of the exposure bracket, in the order of the offsets. `file` is the frame at the base exposure.
+    pub bracket: Vec<Bracketed>,
+
+    /// further subframes of a stack, captured with `settings` after `file`.
+    pub subframes: Vec<Vec<u8>>,
+
+    pub kind: FrameKind,
+
+    /// set on a jpg the camera developed alongside a raw. uploaded ahead of the raw with this uuid as a preview.
+    pub raw: Option<Uuid>,
+
+    /// id of the camera that captured the frame, see `cameras`. `None` for nodes with a single camera.
+    pub camera: Option<String>,
+}
+
+/// Frame of an exposure bracket. Has the `file_type` of its `CaptureResult`.
+#[derive(Serialize, Deserialize)]
+pub struct Bracketed {
+    /// offset in EV from the base exposure.
+    pub offset: f64,
+    pub file: Vec<u8>,
+    /// settings the frame was captured with.
+    pub settings: Option<DNTime>,
+}
+
+impl std::fmt::Debug for Bracketed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Bracketed")
+            .field("offset", &self.offset)
+            .field("file", &format!("Vec<u8> of length {}B", SizeFormatterBinary::new(self.file.len() as u64)))
+            .field("settings", &self.settings)
+            .finish()
+    }
 }
 
 impl std::fmt::Debug for CaptureResult {
@@ -34,6 +101,333 @@ impl std::fmt::Debug for CaptureResult {
             .field("is_night", &self.is_night)
             .field("file_type", &self.file_type)
             .field("file", &format!("Vec<u8> of length {}B", SizeFormatterBinary::new(self.file.len() as u64)))
+            .field("settings", &self.settings)
+            .field("bracket", &self.bracket)
+            .field("subframes", &format!("{} subframes of {}B", self.subframes.len(), SizeFormatterBinary::new(self.subframes.iter().map(|s| s.len() as u64).sum())))
+            .field("kind", &self.kind)
+            .field("raw", &self.raw)
+            .field("camera", &self.camera)
             .finish()
     }
-}
\ No newline at end of file
+}
+
+/// Whether `s` may be used as the id of a camera or a settings key. Both end up in paths on the processor.
+pub fn valid_id(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+/// `CaptureResult` as captured in protocol version 9.
+#[derive(Deserialize)]
+struct CaptureResultV9 {
+    uuid: Uuid,
+    time: DateTime<Local>,
+    is_night: bool,
+    file_type: FileType,
+    file: Vec<u8>,
+    settings: Option<DNTime>,
+    bracket: Vec<Bracketed>,
+    subframes: Vec<Vec<u8>>,
+    kind: FrameKind,
+    raw: Option<Uuid>,
+}
+
+impl From<CaptureResultV9> for CaptureResult {
+    fn from(c: CaptureResultV9) -> CaptureResult {
+        CaptureResult { uuid: c.uuid, time: c.time, is_night: c.is_night, file_type: c.file_type, file: c.file, settings: c.settings, bracket: c.bracket, subframes: c.subframes, kind: c.kind, raw: c.raw, camera: None }
+    }
+}
+
+/// `CaptureResult` as captured in protocol version 8.
+#[derive(Deserialize)]
+struct CaptureResultV8 {
+    uuid: Uuid,
+    time: DateTime<Local>,
+    is_night: bool,
+    file_type: FileType,
+    file: Vec<u8>,
+    settings: Option<DNTime>,
+    bracket: Vec<Bracketed>,
+    subframes: Vec<Vec<u8>>,
+    kind: FrameKind,
+}
+
+impl From<CaptureResultV8> for CaptureResult {
+    fn from(c: CaptureResultV8) -> CaptureResult {
+        CaptureResult { uuid: c.uuid, time: c.time, is_night: c.is_night, file_type: c.file_type, file: c.file, settings: c.settings, bracket: c.bracket, subframes: c.subframes, kind: c.kind, raw: None, camera: None }
+    }
+}
+
+/// `CaptureResult` as captured in protocol version 7.
+#[derive(Deserialize)]
+struct CaptureResultV7 {
+    uuid: Uuid,
+    time: DateTime<Local>,
+    is_night: bool,
+    file_type: FileType,
+    file: Vec<u8>,
+    settings: Option<DNTime>,
+    bracket: Vec<Bracketed>,
+    subframes: Vec<Vec<u8>>,
+}
+
+impl From<CaptureResultV7> for CaptureResult {
+    fn from(c: CaptureResultV7) -> CaptureResult {
+        CaptureResult { uuid: c.uuid, time: c.time, is_night: c.is_night, file_type: c.file_type, file: c.file, settings: c.settings, bracket: c.bracket, subframes: c.subframes, kind: FrameKind::Light, raw: None, camera: None }
+    }
+}
+
+/// `CaptureResult` as captured in protocol version 6.
+#[derive(Deserialize)]
+struct CaptureResultV6 {
+    uuid: Uuid,
+    time: DateTime<Local>,
+    is_night: bool,
+    file_type: FileType,
+    file: Vec<u8>,
+    settings: Option<DNTimeV6>,
+    bracket: Vec<BracketedV6>,
+}
+
+#[derive(Deserialize)]
+struct BracketedV6 {
+    offset: f64,
+    file: Vec<u8>,
+    settings: Option<DNTimeV6>,
+}
+
+impl From<CaptureResultV6> for CaptureResult {
+    fn from(c: CaptureResultV6) -> CaptureResult {
+        let bracket = c.bracket.into_iter().map(|b| Bracketed { offset: b.offset, file: b.file, settings: b.settings.map(DNTime::from) }).collect();
+        CaptureResult { uuid: c.uuid, time: c.time, is_night: c.is_night, file_type: c.file_type, file: c.file, settings: c.settings.map(DNTime::from), bracket, subframes: vec![], kind: FrameKind::Light, raw: None, camera: None }
+    }
+}
+
+/// `CaptureResult` as captured in protocol version 5.
+#[derive(Deserialize)]
+struct CaptureResultV5 {
+    uuid: Uuid,
+    time: DateTime<Local>,
+    is_night: bool,
+    file_type: FileType,
+    file: Vec<u8>,
+    settings: Option<DNTimeV1>,
+}
+
+impl From<CaptureResultV5> for CaptureResult {
+    fn from(c: CaptureResultV5) -> CaptureResult {
+        CaptureResult { uuid: c.uuid, time: c.time, is_night: c.is_night, file_type: c.file_type, file: c.file, settings: c.settings.map(DNTime::from), bracket: vec![], subframes: vec![], kind: FrameKind::Light, raw: None, camera: None }
+    }
+}
+
+/// `CaptureResult` as captured before protocol version 5.
+#[derive(Deserialize)]
+struct CaptureResultV1 {
+    uuid: Uuid,
+    time: DateTime<Local>,
+    is_night: bool,
+    file_type: FileType,
+    file: Vec<u8>,
+}
+
+impl From<CaptureResultV1> for CaptureResult {
+    fn from(c: CaptureResultV1) -> CaptureResult {
+        CaptureResult { uuid: c.uuid, time: c.time, is_night: c.is_night, file_type: c.file_type, file: c.file, settings: None, bracket: vec![], subframes: vec![], kind: FrameKind::Light, raw: None, camera: None }
+    }
+}
+
+/// `Message` with an older `CaptureResult` layout. Variants have to stay in the order of `Message`.
+#[derive(Deserialize)]
+enum Versioned<R> {
+    RequestSettings,
+    Upload(R),
+}
+
+/// Deserializes a message of a node speaking `protocol_version`. Uploads have the layout of that version.
+pub fn decode(b: &[u8], protocol_version: u32) -> bincode::Result<Message> {
+    let versioned = if protocol_version < RAMP_PROTOCOL_VERSION {
+        decode_upload::<CaptureResultV1>(b)
+    } else if protocol_version < BRACKET_PROTOCOL_VERSION {
+        decode_upload::<CaptureResultV5>(b)
+    } else if protocol_version < STACK_PROTOCOL_VERSION {
+        decode_upload::<CaptureResultV6>(b)
+    } else if protocol_version < CALIBRATION_PROTOCOL_VERSION {
+        decode_upload::<CaptureResultV7>(b)
+    } else if protocol_version < PREVIEW_PROTOCOL_VERSION {
+        decode_upload::<CaptureResultV8>(b)
+    } else if protocol_version < CAMERAS_PROTOCOL_VERSION {
+        decode_upload::<CaptureResultV9>(b)
+    } else {
+        return bincode::deserialize(b);
+    };
+    // messages after `Upload` did not change.
+    versioned.or_else(|_| bincode::deserialize(b))
+}
+
+fn decode_upload<R: DeserializeOwned + Into<CaptureResult>>(b: &[u8]) -> bincode::Result<Message> {
+    match bincode::deserialize::<Versioned<R>>(b)? {
+        Versioned::Upload(c) => Ok(Message::Upload(c.into())),
+        Versioned::RequestSettings => Ok(Message::RequestSettings),
+    }
+}
+
+/// Deserializes a bare `CaptureResult` sent in chunks by a peer on `protocol_version`.
+pub fn decode_chunked(b: &[u8], protocol_version: u32) -> bincode::Result<CaptureResult> {
+    if protocol_version < RAMP_PROTOCOL_VERSION {
+        decode_layout::<CaptureResultV1>(b)
+    } else if protocol_version < BRACKET_PROTOCOL_VERSION {
+        decode_layout::<CaptureResultV5>(b)
+    } else if protocol_version < STACK_PROTOCOL_VERSION {
+        decode_layout::<CaptureResultV6>(b)
+    } else if protocol_version < CALIBRATION_PROTOCOL_VERSION {
+        decode_layout::<CaptureResultV7>(b)
+    } else if protocol_version < PREVIEW_PROTOCOL_VERSION {
+        decode_layout::<CaptureResultV8>(b)
+    } else if protocol_version < CAMERAS_PROTOCOL_VERSION {
+        decode_layout::<CaptureResultV9>(b)
+    } else {
+        bincode::deserialize(b)
+    }
+}
+
+fn decode_layout<R: DeserializeOwned + Into<CaptureResult>>(b: &[u8]) -> bincode::Result<CaptureResult> {
+    bincode::deserialize::<R>(b).map(Into::into)
+}
+
+/// Deserializes a `CaptureResult` of any layout, as those left in a spool by older nodes.
+/// Each layout is tried from newest to oldest, so prefer `decode_chunked` where the layout is known.
+pub fn decode_result(b: &[u8]) -> bincode::Result<CaptureResult> {
+    bincode::deserialize(b)
+        .or_else(|e| bincode::deserialize::<CaptureResultV9>(b).map(CaptureResult::from).map_err(|_| e))
+        .or_else(|e| bincode::deserialize::<CaptureResultV8>(b).map(CaptureResult::from).map_err(|_| e))
+        .or_else(|e| bincode::deserialize::<CaptureResultV7>(b).map(CaptureResult::from).map_err(|_| e))
+        .or_else(|e| bincode::deserialize::<CaptureResultV6>(b).map(CaptureResult::from).map_err(|_| e))
+        .or_else(|e| bincode::deserialize::<CaptureResultV5>(b).map(CaptureResult::from).map_err(|_| e))
+        .or_else(|e| bincode::deserialize::<CaptureResultV1>(b).map(CaptureResult::from).map_err(|_| e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handshake::PROTOCOL_VERSION;
+    use settings::bracket::Bracket;
+    use settings::dntime::{ Aperture, Exposure, Frame, Iso };
+
+    /// index of `Message::Upload`, which leads an upload frame.
+    const UPLOAD: u32 = 1;
+
+    fn upload(b: &[u8], protocol_version: u32) -> CaptureResult {
+        match decode(b, protocol_version).unwrap() {
+            Message::Upload(c) => c,
+            msg => panic!("decoded {msg:?}"),
+        }
+    }
+
+    #[test]
+    fn decode_current() {
+        let uuid = Uuid::new_v4();
+        let result = CaptureResult {
+            uuid, time: Local::now(), is_night: true, file_type: FileType::Cr2, file: vec![1, 2, 3],
+            settings: None, bracket: vec![], subframes: vec![vec![4]], kind: FrameKind::Dark, raw: Some(uuid), camera: Some(String::from("east")),
+        };
+        let c = upload(&bincode::serialize(&Message::Upload(result)).unwrap(), PROTOCOL_VERSION);
+        assert_eq!((c.uuid, c.file_type, c.file, c.subframes, c.kind, c.raw, c.camera), (uuid, FileType::Cr2, vec![1, 2, 3], vec![vec![4]], FrameKind::Dark, Some(uuid), Some(String::from("east"))));
+        assert!(matches!(decode(&bincode::serialize(&Message::RequestSettings).unwrap(), PROTOCOL_VERSION).unwrap(), Message::RequestSettings));
+    }
+
+    #[test]
+    fn decode_v1() {
+        let (uuid, time) = (Uuid::new_v4(), Local::now());
+        let fields = (uuid, time, true, FileType::Jpeg, vec![1u8, 2, 3]);
+        let c = upload(&bincode::serialize(&(UPLOAD, &fields)).unwrap(), RAMP_PROTOCOL_VERSION - 1);
+        assert_eq!((c.uuid, c.time, c.is_night, c.file_type, c.file), (uuid, time, true, FileType::Jpeg, vec![1, 2, 3]));
+        assert!(c.settings.is_none() && c.bracket.is_empty() && c.subframes.is_empty());
+        assert_eq!((c.kind, c.raw, c.camera), (FrameKind::Light, None, None));
+
+        // as left in a spool.
+        let c = decode_result(&bincode::serialize(&fields).unwrap()).unwrap();
+        assert_eq!(c.uuid, uuid);
+        // messages after `Upload` keep their layout.
+        let b = bincode::serialize(&Message::UploadBegin { uuid, size: 3, checksum: 0 }).unwrap();
+        assert!(matches!(decode(&b, RAMP_PROTOCOL_VERSION - 1).unwrap(), Message::UploadBegin { size: 3, .. }));
+    }
+
+    #[test]
+    fn decode_v5() {
+        let (uuid, time) = (Uuid::new_v4(), Local::now());
+        let settings = DNTimeV1 { frame: Frame::None, exposure: Exposure::Manual(2.0), iso: Iso::Manual(800), aperture: Aperture::Implicit };
+        let fields = (uuid, time, false, FileType::Cr2, vec![1u8], Some(&settings));
+        let c = upload(&bincode::serialize(&(UPLOAD, &fields)).unwrap(), RAMP_PROTOCOL_VERSION);
+        assert_eq!((c.uuid, c.file_type, c.settings), (uuid, FileType::Cr2, Some(DNTime::from(settings.clone()))));
+        assert!(c.bracket.is_empty());
+        assert_eq!(decode_result(&bincode::serialize(&fields).unwrap()).unwrap().uuid, uuid);
+    }
+
+    #[test]
+    fn decode_v6() {
+        let (uuid, time) = (Uuid::new_v4(), Local::now());
+        let settings = DNTimeV6 { frame: Frame::None, exposure: Exposure::Manual(2.0), iso: Iso::Manual(800), aperture: Aperture::Implicit, bracket: Some(Bracket { offsets: vec![-2.0] }) };
+        let bracket = vec![(-2.0, vec![2u8], Some(&settings))];
+        let fields = (uuid, time, false, FileType::Cr2, vec![1u8], Some(&settings), &bracket);
+        let c = upload(&bincode::serialize(&(UPLOAD, &fields)).unwrap(), BRACKET_PROTOCOL_VERSION);
+        assert_eq!((c.uuid, c.settings), (uuid, Some(DNTime::from(settings.clone()))));
+        assert_eq!((c.bracket.len(), c.bracket[0].offset, &c.bracket[0].file), (1, -2.0, &vec![2]));
+        assert!(c.subframes.is_empty());
+        assert_eq!(decode_result(&bincode::serialize(&fields).unwrap()).unwrap().bracket.len(), 1);
+    }
+
+    #[test]
+    fn decode_v7() {
+        let (uuid, time) = (Uuid::new_v4(), Local::now());
+        let fields = (uuid, time, true, FileType::Nef, vec![1u8], None::<DNTime>, Vec::<Bracketed>::new(), vec![vec![2u8], vec![3u8]]);
+        let c = upload(&bincode::serialize(&(UPLOAD, &fields)).unwrap(), STACK_PROTOCOL_VERSION);
+        assert_eq!((c.uuid, c.subframes, c.kind), (uuid, vec![vec![2], vec![3]], FrameKind::Light));
+        assert_eq!(decode_result(&bincode::serialize(&fields).unwrap()).unwrap().subframes.len(), 2);
+    }
+
+    #[test]
+    fn decode_v8() {
+        let (uuid, time) = (Uuid::new_v4(), Local::now());
+        let fields = (uuid, time, true, FileType::Arw, vec![1u8], None::<DNTime>, Vec::<Bracketed>::new(), Vec::<Vec<u8>>::new(), FrameKind::Flat);
+        let c = upload(&bincode::serialize(&(UPLOAD, &fields)).unwrap(), CALIBRATION_PROTOCOL_VERSION);
+        assert_eq!((c.uuid, c.kind, c.raw), (uuid, FrameKind::Flat, None));
+        assert_eq!(decode_result(&bincode::serialize(&fields).unwrap()).unwrap().kind, FrameKind::Flat);
+    }
+
+    #[test]
+    fn decode_v9() {
+        let (uuid, raw, time) = (Uuid::new_v4(), Uuid::new_v4(), Local::now());
+        let fields = (uuid, time, true, FileType::Jpeg, vec![1u8], None::<DNTime>, Vec::<Bracketed>::new(), Vec::<Vec<u8>>::new(), FrameKind::Light, Some(raw));
+        let c = upload(&bincode::serialize(&(UPLOAD, &fields)).unwrap(), PREVIEW_PROTOCOL_VERSION);
+        assert_eq!((c.uuid, c.raw, c.camera), (uuid, Some(raw), None));
+        assert_eq!(decode_result(&bincode::serialize(&fields).unwrap()).unwrap().raw, Some(raw));
+    }
+
+    #[test]
+    fn decode_chunked_layouts() {
+        let (uuid, raw, time) = (Uuid::new_v4(), Uuid::new_v4(), Local::now());
+        let fields = (uuid, time, true, FileType::Jpeg, vec![1u8], None::<DNTime>, Vec::<Bracketed>::new(), Vec::<Vec<u8>>::new(), FrameKind::Light, Some(raw));
+        let c = decode_chunked(&bincode::serialize(&fields).unwrap(), PREVIEW_PROTOCOL_VERSION).unwrap();
+        assert_eq!((c.uuid, c.raw, c.camera), (uuid, Some(raw), None));
+
+        let fields = (uuid, time, false, FileType::Cr2, vec![1u8, 2]);
+        let c = decode_chunked(&bincode::serialize(&fields).unwrap(), RAMP_PROTOCOL_VERSION - 1).unwrap();
+        assert_eq!((c.uuid, c.file_type, c.file), (uuid, FileType::Cr2, vec![1, 2]));
+
+        let result = CaptureResult {
+            uuid, time, is_night: false, file_type: FileType::Nef, file: vec![3],
+            settings: None, bracket: vec![], subframes: vec![], kind: FrameKind::Light, raw: None, camera: Some(String::from("west")),
+        };
+        let c = decode_chunked(&bincode::serialize(&result).unwrap(), PROTOCOL_VERSION).unwrap();
+        assert_eq!((c.uuid, c.camera), (uuid, Some(String::from("west"))));
+    }
+
+    #[test]
+    fn valid_ids() {
+        assert!(valid_id("east"));
+        assert!(valid_id("cam_2"));
+        assert!(!valid_id(""));
+        assert!(!valid_id("../east"));
+        assert!(!valid_id("a/b"));
+        assert!(!valid_id("a b"));
+    }
+}
@@ -0,0 +1,28 @@
+use serde::{ Deserialize, Deserializer, Serialize };
+
+/// Exposure offsets in EV taken back to back after the frame at the base exposure.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Bracket {
+    pub offsets: Vec<f64>,
+}
+
+/// Largest offset in EV a bracket may use.
+pub const MAX_OFFSET: f64 = 5.0;
+
+pub(super) fn deserialize_bracket<'de, D>(d: D) -> Result<Option<Bracket>, D::Error> where D: Deserializer<'de> {
+    let value = Option::<Bracket>::deserialize(d)?;
+    if let Some(b) = &value {
+        if b.offsets.is_empty() {
+            return Err(serde::de::Error::invalid_length(0, &"at least one offset"));
+        }
+        for (i, o) in b.offsets.iter().enumerate() {
+            if !(o.is_finite() && *o != 0.0 && o.abs() <= MAX_OFFSET) {
+                return Err(serde::de::Error::invalid_value(serde::de::Unexpected::Float(*o), &format!("offsets to be -{MAX_OFFSET} <= x <= {MAX_OFFSET} and not 0. the base exposure is always taken").as_str()));
+            }
+            if b.offsets[..i].contains(o) {
+                return Err(serde::de::Error::invalid_value(serde::de::Unexpected::Float(*o), &"offsets to be unique"));
+            }
+        }
+    }
+    Ok(value)
+}
@@ -1,6 +1,9 @@
 use serde::{ Deserialize, Deserializer, Serialize };
 
-#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+use super::bracket::{ self, Bracket };
+use super::stack::{ self, Stack };
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct DNTime {
     #[serde(deserialize_with = "deserialize_frame")]
     pub frame: Frame,
@@ -10,6 +13,79 @@ pub struct DNTime {
     pub iso: Iso,
     #[serde(deserialize_with = "deserialize_apature")]
     pub aperture: Aperture,
+    /// further frames at exposure offsets. taken back to back and merged by the processor.
+    #[serde(default, deserialize_with = "bracket::deserialize_bracket")]
+    pub bracket: Option<Bracket>,
+    /// subframes of `exposure` each the frame is stacked from. bracket frames are single frames.
+    #[serde(default, deserialize_with = "stack::deserialize_stack")]
+    pub stack: Option<Stack>,
+}
+
+impl DNTime {
+    /// Settings of the bracket frame `offset` EV from these. Automatic exposures are compensated by the camera.
+    pub fn offset(&self, offset: f64) -> DNTime {
+        let exposure = match self.exposure {
+            Exposure::Manual(f) => Exposure::Manual(f * offset.exp2()),
+            Exposure::Auto => Exposure::Auto,
+        };
+        DNTime { exposure, bracket: None, stack: None, ..self.clone() }
+    }
+
+    /// These settings with a manual ISO the camera does not offer replaced by the nearest one of `offered`.
+    /// A manual exposure time compensates for the difference. Unchanged if `offered` is unknown, i.e. empty.
+    pub fn snap_iso(&self, offered: &[u32]) -> DNTime {
+        let Iso::Manual(u) = self.iso else { return self.clone() };
+        let distance = |iso: u32| (iso as f64 / u as f64).log2().abs();
+        let Some(snapped) = offered.iter().copied().filter(|iso| *iso > 0).min_by(|a, b| distance(*a).total_cmp(&distance(*b))) else { return self.clone() };
+        let exposure = match self.exposure {
+            Exposure::Manual(f) => Exposure::Manual(f * u as f64 / snapped as f64),
+            Exposure::Auto => Exposure::Auto,
+        };
+        DNTime { exposure, iso: Iso::Manual(snapped), ..self.clone() }
+    }
+}
+
+/// `DNTime` as sent before protocol version 6.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DNTimeV1 {
+    pub frame: Frame,
+    pub exposure: Exposure,
+    pub iso: Iso,
+    pub aperture: Aperture,
+}
+
+impl From<DNTime> for DNTimeV1 {
+    fn from(d: DNTime) -> DNTimeV1 {
+        DNTimeV1 { frame: d.frame, exposure: d.exposure, iso: d.iso, aperture: d.aperture }
+    }
+}
+
+impl From<DNTimeV1> for DNTime {
+    fn from(d: DNTimeV1) -> DNTime {
+        DNTime { frame: d.frame, exposure: d.exposure, iso: d.iso, aperture: d.aperture, bracket: None, stack: None }
+    }
+}
+
+/// `DNTime` as sent in protocol version 6.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DNTimeV6 {
+    pub frame: Frame,
+    pub exposure: Exposure,
+    pub iso: Iso,
+    pub aperture: Aperture,
+    pub bracket: Option<Bracket>,
+}
+
+impl From<DNTime> for DNTimeV6 {
+    fn from(d: DNTime) -> DNTimeV6 {
+        DNTimeV6 { frame: d.frame, exposure: d.exposure, iso: d.iso, aperture: d.aperture, bracket: d.bracket }
+    }
+}
+
+impl From<DNTimeV6> for DNTime {
+    fn from(d: DNTimeV6) -> DNTime {
+        DNTime { frame: d.frame, exposure: d.exposure, iso: d.iso, aperture: d.aperture, bracket: d.bracket, stack: None }
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
@@ -48,7 +124,13 @@ pub enum Iso {
     Manual(u32),
 }
 
-const VALID_ISO: &[u32] = &[100, 200, 400, 800, 1600];
+/// ISO accepted in settings, the full and third stops. capture nodes snap it to the ISO their camera offers.
+pub const VALID_ISO: &[u32] = &[
+    25, 32, 40, 50, 64, 80, 100, 125, 160, 200, 250, 320, 400, 500, 640, 800, 1000, 1250, 1600, 2000, 2500, 3200, 4000, 5000,
+    6400, 8000, 10000, 12800, 16000, 20000, 25600, 32000, 40000, 51200, 64000, 80000, 102400, 128000, 160000, 204800, 256000, 320000, 409600,
+];
+/// full ISO stops. cameras offer all of them within their range.
+pub const ISO_STOPS: &[u32] = &[25, 50, 100, 200, 400, 800, 1600, 3200, 6400, 12800, 25600, 51200, 102400, 204800, 409600];
 fn deserialize_iso<'de, D>(d: D) -> Result<Iso, D::Error> where D: Deserializer<'de> {
     let value = Iso::deserialize(d)?;
     match value {
@@ -73,4 +155,38 @@ fn deserialize_apature<'de, D>(d: D) -> Result<Aperture, D::Error> where D: Dese
         Aperture::Manual(f) if f.is_finite() => Ok(value),
         Aperture::Manual(f) => Err(serde::de::Error::invalid_value(serde::de::Unexpected::Float(f), &"to be valid"))
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dntime(exposure: Exposure, iso: Iso) -> DNTime {
+        DNTime { frame: Frame::None, exposure, iso, aperture: Aperture::Implicit, bracket: None, stack: None }
+    }
+
+    #[test]
+    fn snap_iso_to_nearest_offered() {
+        let snapped = dntime(Exposure::Manual(1.0), Iso::Manual(800)).snap_iso(&[0, 100, 200, 400]);
+        assert_eq!(snapped, dntime(Exposure::Manual(2.0), Iso::Manual(400)));
+        // nearest in stops, not in ISO.
+        let snapped = dntime(Exposure::Manual(1.0), Iso::Manual(250)).snap_iso(&[100, 320, 400]);
+        assert_eq!(snapped.iso, Iso::Manual(320));
+    }
+
+    #[test]
+    fn snap_iso_keeps_offered_and_automatic() {
+        let d = dntime(Exposure::Manual(1.0), Iso::Manual(400));
+        assert_eq!(d.snap_iso(&[100, 400, 1600]), d);
+        assert_eq!(d.snap_iso(&[]), d);
+        let auto = dntime(Exposure::Manual(1.0), Iso::Auto);
+        assert_eq!(auto.snap_iso(&[100]), auto);
+        let snapped = dntime(Exposure::Auto, Iso::Manual(800)).snap_iso(&[400]);
+        assert_eq!(snapped, dntime(Exposure::Auto, Iso::Manual(400)));
+    }
+
+    #[test]
+    fn valid_iso_contains_stops() {
+        assert!(ISO_STOPS.iter().all(|s| VALID_ISO.contains(s)));
+        assert!(VALID_ISO.windows(2).all(|w| w[0] < w[1]));
+    }
+}
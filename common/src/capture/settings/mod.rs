@@ -1,19 +1,214 @@
 pub mod dntime;
+pub mod phase;
+pub mod ramp;
+pub mod bracket;
+pub mod stack;
 
-use dntime::DNTime;
+use dntime::{ DNTime, DNTimeV1, DNTimeV6, Exposure, Iso };
+use phase::{ Phase, PhaseV1, PhaseV6 };
+use ramp::Ramp;
 use serde::{ Serialize, Deserialize, Deserializer };
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Settings {
+    /// sun altitude below which frames are considered night.
     #[serde(deserialize_with = "deserialize_horizon")]
     pub horizon: f64,
 
     pub daytime: DNTime,
     pub nighttime: DNTime,
+
+    /// ordered by descending altitude. replaces `daytime` and `nighttime` if not empty.
+    #[serde(default, deserialize_with = "phase::deserialize_phases")]
+    pub phases: Vec<Phase>,
+
+    /// overrides exposure and ISO of the phase while the sun is between its anchors.
+    #[serde(default, deserialize_with = "ramp::deserialize_ramp")]
+    pub ramp: Option<Ramp>,
+}
+
+impl Settings {
+    /// Name and settings of the phase the sun is in at `altitude`.
+    /// Without phases this is `daytime` or `nighttime` depending on `horizon`.
+    /// Below the last phase, the last phase is used. Between the anchors of `ramp`, the phase is called "ramp".
+    pub fn phase(&self, altitude: f64) -> (&str, DNTime) {
+        let (name, mut dntime) = match self.phases.iter().find(|p| altitude >= p.above).or(self.phases.last()) {
+            Some(p) => (p.name.as_str(), p.settings.clone()),
+            None if altitude < self.horizon => ("nighttime", self.nighttime.clone()),
+            None => ("daytime", self.daytime.clone()),
+        };
+        match self.ramp.as_ref().and_then(|r| r.at(altitude)) {
+            Some((exposure, iso)) => {
+                dntime.exposure = Exposure::Manual(exposure);
+                dntime.iso = Iso::Manual(iso);
+                ("ramp", dntime)
+            },
+            None => (name, dntime),
+        }
+    }
+
+    /// Whether any of the settings takes an exposure bracket.
+    pub fn has_bracket(&self) -> bool {
+        self.dntimes().any(|d| d.bracket.is_some())
+    }
+
+    /// Whether any of the settings stacks subframes.
+    pub fn has_stack(&self) -> bool {
+        self.dntimes().any(|d| d.stack.is_some())
+    }
+
+    /// Manual ISO of all settings, including the anchors of `ramp`.
+    pub fn isos(&self) -> Vec<u32> {
+        let mut isos: Vec<u32> = self.dntimes()
+            .filter_map(|d| match d.iso { Iso::Manual(u) => Some(u), Iso::Auto => None })
+            .chain(self.ramp.iter().flat_map(|r| [r.from.iso, r.to.iso]))
+            .collect();
+        isos.sort_unstable();
+        isos.dedup();
+        isos
+    }
+
+    fn dntimes(&self) -> impl Iterator<Item = &DNTime> {
+        std::iter::once(&self.daytime).chain(std::iter::once(&self.nighttime)).chain(self.phases.iter().map(|p| &p.settings))
+    }
+}
+
+/// `Settings` as sent before protocol version 4.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SettingsV1 {
+    pub horizon: f64,
+    pub daytime: DNTimeV1,
+    pub nighttime: DNTimeV1,
+}
+
+/// `Settings` as sent in protocol version 4.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SettingsV4 {
+    pub horizon: f64,
+    pub daytime: DNTimeV1,
+    pub nighttime: DNTimeV1,
+    pub phases: Vec<PhaseV1>,
+}
+
+/// `Settings` as sent in protocol version 5.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SettingsV5 {
+    pub horizon: f64,
+    pub daytime: DNTimeV1,
+    pub nighttime: DNTimeV1,
+    pub phases: Vec<PhaseV1>,
+    pub ramp: Option<Ramp>,
+}
+
+/// `Settings` as sent in protocol version 6.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SettingsV6 {
+    pub horizon: f64,
+    pub daytime: DNTimeV6,
+    pub nighttime: DNTimeV6,
+    pub phases: Vec<PhaseV6>,
+    pub ramp: Option<Ramp>,
+}
+
+impl From<Settings> for SettingsV1 {
+    fn from(s: Settings) -> SettingsV1 {
+        SettingsV1 { horizon: s.horizon, daytime: s.daytime.into(), nighttime: s.nighttime.into() }
+    }
+}
+
+impl From<SettingsV1> for Settings {
+    fn from(s: SettingsV1) -> Settings {
+        Settings { horizon: s.horizon, daytime: s.daytime.into(), nighttime: s.nighttime.into(), phases: vec![], ramp: None }
+    }
+}
+
+impl From<Settings> for SettingsV4 {
+    fn from(s: Settings) -> SettingsV4 {
+        SettingsV4 { horizon: s.horizon, daytime: s.daytime.into(), nighttime: s.nighttime.into(), phases: s.phases.into_iter().map(PhaseV1::from).collect() }
+    }
+}
+
+impl From<SettingsV4> for Settings {
+    fn from(s: SettingsV4) -> Settings {
+        Settings { horizon: s.horizon, daytime: s.daytime.into(), nighttime: s.nighttime.into(), phases: s.phases.into_iter().map(Phase::from).collect(), ramp: None }
+    }
+}
+
+impl From<Settings> for SettingsV5 {
+    fn from(s: Settings) -> SettingsV5 {
+        SettingsV5 { horizon: s.horizon, daytime: s.daytime.into(), nighttime: s.nighttime.into(), phases: s.phases.into_iter().map(PhaseV1::from).collect(), ramp: s.ramp }
+    }
+}
+
+impl From<SettingsV5> for Settings {
+    fn from(s: SettingsV5) -> Settings {
+        Settings { horizon: s.horizon, daytime: s.daytime.into(), nighttime: s.nighttime.into(), phases: s.phases.into_iter().map(Phase::from).collect(), ramp: s.ramp }
+    }
+}
+
+impl From<Settings> for SettingsV6 {
+    fn from(s: Settings) -> SettingsV6 {
+        SettingsV6 { horizon: s.horizon, daytime: s.daytime.into(), nighttime: s.nighttime.into(), phases: s.phases.into_iter().map(PhaseV6::from).collect(), ramp: s.ramp }
+    }
+}
+
+impl From<SettingsV6> for Settings {
+    fn from(s: SettingsV6) -> Settings {
+        Settings { horizon: s.horizon, daytime: s.daytime.into(), nighttime: s.nighttime.into(), phases: s.phases.into_iter().map(Phase::from).collect(), ramp: s.ramp }
+    }
 }
 
 fn deserialize_horizon<'de, D>(d: D) -> Result<f64, D::Error> where D: Deserializer<'de> {
     let value = f64::deserialize(d)?;
-    if value.is_finite() && value >= -90.0 && value <= 90.0 { Ok(value) }
+    if value.is_finite() && (-90.0..=90.0).contains(&value) { Ok(value) }
     else { Err(serde::de::Error::invalid_value(serde::de::Unexpected::Float(value), &"to be -90.0 <= x <= 90.0")) }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dntime::{ Aperture, Frame };
+
+    fn dntime(exposure: f64) -> DNTime {
+        DNTime { frame: Frame::None, exposure: Exposure::Manual(exposure), iso: Iso::Manual(100), aperture: Aperture::Implicit, bracket: None, stack: None }
+    }
+
+    fn phase(name: &str, above: f64, exposure: f64) -> Phase {
+        Phase { name: String::from(name), above, settings: dntime(exposure) }
+    }
+
+    #[test]
+    fn phase_without_phases_uses_horizon() {
+        let settings = Settings { horizon: -6.0, daytime: dntime(0.001), nighttime: dntime(10.0), phases: vec![], ramp: None };
+        assert_eq!(settings.phase(10.0), ("daytime", dntime(0.001)));
+        assert_eq!(settings.phase(-6.0), ("daytime", dntime(0.001)));
+        assert_eq!(settings.phase(-6.1), ("nighttime", dntime(10.0)));
+    }
+
+    #[test]
+    fn phase_picks_first_phase_above() {
+        let phases = vec![phase("day", 0.0, 0.001), phase("civil", -6.0, 1.0), phase("night", -12.0, 10.0)];
+        let settings = Settings { horizon: -6.0, daytime: dntime(0.5), nighttime: dntime(0.5), phases, ramp: None };
+        assert_eq!(settings.phase(30.0), ("day", dntime(0.001)));
+        assert_eq!(settings.phase(0.0), ("day", dntime(0.001)));
+        assert_eq!(settings.phase(-3.0), ("civil", dntime(1.0)));
+        assert_eq!(settings.phase(-12.0), ("night", dntime(10.0)));
+        // below the last phase.
+        assert_eq!(settings.phase(-80.0), ("night", dntime(10.0)));
+    }
+
+    #[test]
+    fn phase_ramp_overrides_exposure_and_iso() {
+        let ramp = Ramp {
+            from: ramp::Anchor { altitude: 0.0, exposure: 0.01, iso: 100 },
+            to: ramp::Anchor { altitude: -10.0, exposure: 10.0, iso: 1600 },
+            curve: ramp::Curve::Linear,
+        };
+        let settings = Settings { horizon: -6.0, daytime: dntime(0.001), nighttime: dntime(10.0), phases: vec![], ramp: Some(ramp) };
+        let (name, d) = settings.phase(-5.0);
+        assert_eq!((name, d.iso), ("ramp", Iso::Manual(400)));
+        assert!(matches!(d.exposure, Exposure::Manual(_)));
+        assert_eq!(settings.phase(-10.5), ("nighttime", dntime(10.0)));
+        assert_eq!(settings.phase(1.0), ("daytime", dntime(0.001)));
+    }
+}
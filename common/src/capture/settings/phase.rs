@@ -0,0 +1,68 @@
+use serde::{ Deserialize, Deserializer, Serialize };
+
+use super::dntime::{ DNTime, DNTimeV1, DNTimeV6 };
+
+/// Settings used while the sun is at or above `above` degrees and below the `above` of the previous phase.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Phase {
+    pub name: String,
+    #[serde(deserialize_with = "deserialize_above")]
+    pub above: f64,
+    pub settings: DNTime,
+}
+
+/// `Phase` as sent before protocol version 6.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PhaseV1 {
+    pub name: String,
+    pub above: f64,
+    pub settings: DNTimeV1,
+}
+
+impl From<Phase> for PhaseV1 {
+    fn from(p: Phase) -> PhaseV1 {
+        PhaseV1 { name: p.name, above: p.above, settings: p.settings.into() }
+    }
+}
+
+impl From<PhaseV1> for Phase {
+    fn from(p: PhaseV1) -> Phase {
+        Phase { name: p.name, above: p.above, settings: p.settings.into() }
+    }
+}
+
+/// `Phase` as sent in protocol version 6.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PhaseV6 {
+    pub name: String,
+    pub above: f64,
+    pub settings: DNTimeV6,
+}
+
+impl From<Phase> for PhaseV6 {
+    fn from(p: Phase) -> PhaseV6 {
+        PhaseV6 { name: p.name, above: p.above, settings: p.settings.into() }
+    }
+}
+
+impl From<PhaseV6> for Phase {
+    fn from(p: PhaseV6) -> Phase {
+        Phase { name: p.name, above: p.above, settings: p.settings.into() }
+    }
+}
+
+fn deserialize_above<'de, D>(d: D) -> Result<f64, D::Error> where D: Deserializer<'de> {
+    let value = f64::deserialize(d)?;
+    if value.is_finite() && (-90.0..=90.0).contains(&value) { Ok(value) }
+    else { Err(serde::de::Error::invalid_value(serde::de::Unexpected::Float(value), &"to be -90.0 <= x <= 90.0")) }
+}
+
+pub(super) fn deserialize_phases<'de, D>(d: D) -> Result<Vec<Phase>, D::Error> where D: Deserializer<'de> {
+    let phases = Vec::<Phase>::deserialize(d)?;
+    for w in phases.windows(2) {
+        if w[1].above >= w[0].above {
+            return Err(serde::de::Error::invalid_value(serde::de::Unexpected::Float(w[1].above), &format!("phases to be ordered by descending altitude. {} follows {} at {}", w[1].name, w[0].name, w[0].above).as_str()));
+        }
+    }
+    Ok(phases)
+}
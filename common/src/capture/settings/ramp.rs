@@ -0,0 +1,150 @@
+use serde::{ Deserialize, Deserializer, Serialize };
+
+use super::dntime::{ ISO_STOPS, VALID_ISO };
+
+/// Interpolates exposure and ISO while the sun is between the altitudes of `from` and `to`.
+/// The total exposure is interpolated in EV, ISO snaps to the anchors' ISO or a full stop between them
+/// and the exposure time compensates for the snapping.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Ramp {
+    pub from: Anchor,
+    pub to: Anchor,
+    #[serde(deserialize_with = "deserialize_curve")]
+    pub curve: Curve,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Anchor {
+    #[serde(deserialize_with = "deserialize_altitude")]
+    pub altitude: f64,
+    #[serde(deserialize_with = "deserialize_exposure")]
+    pub exposure: f64,
+    #[serde(deserialize_with = "deserialize_iso")]
+    pub iso: u32,
+}
+
+/// Maps the position between the anchors, 0 at `from` and 1 at `to`, to the interpolation weight.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Curve {
+    Linear,
+    /// smoothstep. eases in and out of the anchors.
+    Smooth,
+    /// position to the power of x. x > 1 lingers at `from`, x < 1 at `to`.
+    Power(f64),
+}
+
+impl Curve {
+    fn apply(&self, t: f64) -> f64 {
+        match self {
+            Curve::Linear => t,
+            Curve::Smooth => t * t * (3.0 - 2.0 * t),
+            Curve::Power(p) => t.powf(*p),
+        }
+    }
+}
+
+impl Ramp {
+    /// Exposure time and ISO at `altitude`. `None` outside of the anchors.
+    pub fn at(&self, altitude: f64) -> Option<(f64, u32)> {
+        let (from, to) = (&self.from, &self.to);
+        if !(from.altitude.min(to.altitude)..=from.altitude.max(to.altitude)).contains(&altitude) { return None; }
+
+        let t = self.curve.apply((altitude - from.altitude) / (to.altitude - from.altitude));
+        let ev = lerp((from.exposure * from.iso as f64).log2(), (to.exposure * to.iso as f64).log2(), t);
+        let iso = lerp((from.iso as f64).log2(), (to.iso as f64).log2(), t);
+
+        let (low, high) = (from.iso.min(to.iso), from.iso.max(to.iso));
+        let iso = ISO_STOPS.iter().copied().filter(|s| (low..=high).contains(s)).chain([low, high])
+            .min_by(|a, b| ((*a as f64).log2() - iso).abs().total_cmp(&((*b as f64).log2() - iso).abs()))
+            .unwrap();
+        Some((ev.exp2() / iso as f64, iso))
+    }
+}
+
+fn lerp(a: f64, b: f64, t: f64) -> f64 {
+    a + (b - a) * t
+}
+
+pub(super) fn deserialize_ramp<'de, D>(d: D) -> Result<Option<Ramp>, D::Error> where D: Deserializer<'de> {
+    let value = Option::<Ramp>::deserialize(d)?;
+    match &value {
+        Some(r) if r.from.altitude == r.to.altitude => Err(serde::de::Error::invalid_value(serde::de::Unexpected::Float(r.to.altitude), &"the anchors to be at different altitudes")),
+        _ => Ok(value),
+    }
+}
+
+fn deserialize_altitude<'de, D>(d: D) -> Result<f64, D::Error> where D: Deserializer<'de> {
+    let value = f64::deserialize(d)?;
+    if value.is_finite() && (-90.0..=90.0).contains(&value) { Ok(value) }
+    else { Err(serde::de::Error::invalid_value(serde::de::Unexpected::Float(value), &"to be -90.0 <= x <= 90.0")) }
+}
+
+fn deserialize_exposure<'de, D>(d: D) -> Result<f64, D::Error> where D: Deserializer<'de> {
+    let value = f64::deserialize(d)?;
+    if value.is_finite() && value > 0.0 { Ok(value) }
+    else { Err(serde::de::Error::invalid_value(serde::de::Unexpected::Float(value), &"to be greater than 0")) }
+}
+
+fn deserialize_iso<'de, D>(d: D) -> Result<u32, D::Error> where D: Deserializer<'de> {
+    let value = u32::deserialize(d)?;
+    if VALID_ISO.contains(&value) { Ok(value) }
+    else { Err(serde::de::Error::invalid_value(serde::de::Unexpected::Unsigned(value as u64), &format!("to be one of {VALID_ISO:?}").as_str())) }
+}
+
+fn deserialize_curve<'de, D>(d: D) -> Result<Curve, D::Error> where D: Deserializer<'de> {
+    let value = Curve::deserialize(d)?;
+    match value {
+        Curve::Power(p) if !(p.is_finite() && p > 0.0) => Err(serde::de::Error::invalid_value(serde::de::Unexpected::Float(p), &"to be greater than 0")),
+        _ => Ok(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ramp(curve: Curve) -> Ramp {
+        Ramp {
+            from: Anchor { altitude: 0.0, exposure: 0.01, iso: 100 },
+            to: Anchor { altitude: -10.0, exposure: 10.0, iso: 1600 },
+            curve,
+        }
+    }
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9 * b.abs().max(1.0), "{a} != {b}");
+    }
+
+    #[test]
+    fn at_outside_anchors() {
+        assert_eq!(ramp(Curve::Linear).at(0.1), None);
+        assert_eq!(ramp(Curve::Linear).at(-10.1), None);
+    }
+
+    #[test]
+    fn at_anchors() {
+        let (exposure, iso) = ramp(Curve::Linear).at(0.0).unwrap();
+        assert_close(exposure, 0.01);
+        assert_eq!(iso, 100);
+        let (exposure, iso) = ramp(Curve::Linear).at(-10.0).unwrap();
+        assert_close(exposure, 10.0);
+        assert_eq!(iso, 1600);
+    }
+
+    #[test]
+    fn at_interpolates_in_ev() {
+        // halfway in EV between 1 and 16000 ISO seconds, ISO halfway in stops.
+        let (exposure, iso) = ramp(Curve::Linear).at(-5.0).unwrap();
+        assert_eq!(iso, 400);
+        assert_close(exposure * iso as f64, 16000f64.sqrt());
+        assert_eq!(ramp(Curve::Smooth).at(-5.0), ramp(Curve::Linear).at(-5.0));
+    }
+
+    #[test]
+    fn at_applies_curve() {
+        // a quarter of the way at the midpoint.
+        let (exposure, iso) = ramp(Curve::Power(2.0)).at(-5.0).unwrap();
+        assert_eq!(iso, 200);
+        assert_close(exposure * iso as f64, 16000f64.powf(0.25));
+    }
+}
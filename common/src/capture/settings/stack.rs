@@ -0,0 +1,26 @@
+use serde::{ Deserialize, Deserializer, Serialize };
+
+/// Builds a frame from several subframes of the exposure each, stacked by the processor.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Stack {
+    pub subframes: u32,
+    pub method: Method,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Method {
+    Mean,
+    /// rejects satellites, planes and hot pixels that only appear in few subframes.
+    Median,
+}
+
+/// Most subframes a frame may be built from.
+pub const MAX_SUBFRAMES: u32 = 64;
+
+pub(super) fn deserialize_stack<'de, D>(d: D) -> Result<Option<Stack>, D::Error> where D: Deserializer<'de> {
+    let value = Option::<Stack>::deserialize(d)?;
+    match value {
+        Some(s) if !(2..=MAX_SUBFRAMES).contains(&s.subframes) => Err(serde::de::Error::invalid_value(serde::de::Unexpected::Unsigned(s.subframes as u64), &format!("subframes to be 2 <= x <= {MAX_SUBFRAMES}").as_str())),
+        _ => Ok(value),
+    }
+}
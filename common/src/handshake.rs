@@ -0,0 +1,194 @@
+use serde::{ Serialize, Deserialize };
+
+/// Leading bytes of every `Hello`. Frames of peers that predate the handshake start with
+/// a bincode enum tag instead and can therefore never match.
+pub const MAGIC: [u8; 4] = *b"HMTT";
+
+/// Version of the protocol spoken after the handshake. Bump on every change to
+/// `capture::Message` or `processor::Message`.
+///
+/// 1: initial handshake.
+/// 2: uploads are answered with `processor::Message::Ack` or `Nack`.
+/// 3: uploads are transferred in resumable chunks.
+/// 4: settings carry twilight phases.
+/// 5: settings carry an exposure ramp, results carry the settings they were captured with.
+/// 6: settings carry exposure brackets, results carry the frames of a bracket.
+/// 7: settings carry subframe stacks, results carry the subframes.
+/// 8: results carry a frame kind, the processor may request calibration frames.
+/// 9: camera jpgs are uploaded as previews of their raw, the processor may request raws held back by the node.
+/// 10: results carry the id of the camera of the node, nodes may request settings by key.
+pub const PROTOCOL_VERSION: u32 = 10;
+/// Oldest protocol version this build can still fall back to.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+/// Pseudo version of peers that connect without sending a `Hello`.
+pub const LEGACY_PROTOCOL_VERSION: u32 = 0;
+
+/// First protocol version in which uploads are acknowledged.
+pub const ACK_PROTOCOL_VERSION: u32 = 2;
+/// First protocol version in which uploads are chunked.
+pub const CHUNK_PROTOCOL_VERSION: u32 = 3;
+/// First protocol version in which `Settings` has phases.
+pub const PHASES_PROTOCOL_VERSION: u32 = 4;
+/// First protocol version in which `Settings` has a ramp.
+pub const RAMP_PROTOCOL_VERSION: u32 = 5;
+/// First protocol version in which `DNTime` has a bracket and `CaptureResult` its frames.
+pub const BRACKET_PROTOCOL_VERSION: u32 = 6;
+/// First protocol version in which `DNTime` has a stack and `CaptureResult` its subframes.
+pub const STACK_PROTOCOL_VERSION: u32 = 7;
+/// First protocol version with `CaptureResult::kind` and `processor::Message::Calibrate`.
+pub const CALIBRATION_PROTOCOL_VERSION: u32 = 8;
+/// First protocol version with `CaptureResult::raw` and `processor::Message::RequestRaw`.
+pub const PREVIEW_PROTOCOL_VERSION: u32 = 9;
+/// First protocol version with `CaptureResult::camera` and `capture::Message::RequestSettingsOf`.
+pub const CAMERAS_PROTOCOL_VERSION: u32 = 10;
+
+/// First frame sent by a capture node.
+/// Fields may only ever be appended to keep older peers able to read it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Hello {
+    pub magic: [u8; 4],
+    pub protocol_version: u32,
+    pub name: String,
+    pub software_version: String,
+    pub capabilities: Capabilities,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Capabilities {
+    /// file types the node produces, by extension. unknown extensions are ignored by the processor.
+    pub file_types: Vec<String>,
+    pub module: String,
+    pub tracking: bool,
+    /// keys of the camera settings the node requests, see `capture::Message::RequestSettingsOf`. from protocol version 10 on.
+    pub settings_keys: Vec<String>,
+}
+
+/// `Hello` as sent before protocol version 10.
+#[derive(Deserialize)]
+struct HelloV9 {
+    magic: [u8; 4],
+    protocol_version: u32,
+    name: String,
+    software_version: String,
+    capabilities: CapabilitiesV9,
+}
+
+#[derive(Deserialize)]
+struct CapabilitiesV9 {
+    file_types: Vec<String>,
+    module: String,
+    tracking: bool,
+}
+
+impl From<HelloV9> for Hello {
+    fn from(h: HelloV9) -> Hello {
+        let c = h.capabilities;
+        let capabilities = Capabilities { file_types: c.file_types, module: c.module, tracking: c.tracking, settings_keys: vec![] };
+        Hello { magic: h.magic, protocol_version: h.protocol_version, name: h.name, software_version: h.software_version, capabilities }
+    }
+}
+
+/// Answer of the processor to a `Hello`.
+/// Variants and fields may only ever be appended to keep older peers able to read it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Welcome {
+    Accepted { protocol_version: u32, software_version: String, file_types: Vec<String> },
+    Rejected { reason: String },
+}
+
+impl Hello {
+    pub fn new(name: String, capabilities: Capabilities) -> Self {
+        Hello { magic: MAGIC, protocol_version: PROTOCOL_VERSION, name, software_version: software_version(), capabilities }
+    }
+}
+
+pub fn software_version() -> String {
+    String::from(env!("CARGO_PKG_VERSION"))
+}
+
+/// Deserializes a `Hello` in the layout of the protocol version it announces.
+pub fn decode_hello(b: &[u8]) -> bincode::Result<Hello> {
+    // magic and version lead every layout.
+    let (_, version) = bincode::deserialize::<([u8; 4], u32)>(b)?;
+    if version < CAMERAS_PROTOCOL_VERSION {
+        bincode::deserialize::<HelloV9>(b).map(Hello::from)
+    } else {
+        bincode::deserialize(b)
+    }
+}
+
+/// Picks the protocol version to use with a peer announcing `peer_version`.
+/// Returns `None` if no common version exists.
+pub fn negotiate(peer_version: u32) -> Option<u32> {
+    if peer_version < MIN_PROTOCOL_VERSION { None }
+    else { Some(peer_version.min(PROTOCOL_VERSION)) }
+}
+
+/// Shortens `reason` to fit into a websocket close frame, which allows at most 123 bytes.
+pub fn close_reason(reason: &str) -> String {
+    let mut end = reason.len().min(123);
+    while !reason.is_char_boundary(end) { end -= 1; }
+    String::from(&reason[..end])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn capabilities() -> Capabilities {
+        Capabilities { file_types: vec![String::from("jpg")], module: String::from("gphoto2"), tracking: false, settings_keys: vec![String::from("east")] }
+    }
+
+    #[test]
+    fn negotiate_clamps_to_own_version() {
+        assert_eq!(negotiate(LEGACY_PROTOCOL_VERSION), None);
+        assert_eq!(negotiate(MIN_PROTOCOL_VERSION), Some(MIN_PROTOCOL_VERSION));
+        assert_eq!(negotiate(PROTOCOL_VERSION), Some(PROTOCOL_VERSION));
+        assert_eq!(negotiate(PROTOCOL_VERSION + 1), Some(PROTOCOL_VERSION));
+    }
+
+    #[test]
+    fn hello_round_trip() {
+        let hello = Hello::new(String::from("node"), capabilities());
+        let b = bincode::serialize(&hello).unwrap();
+        assert_eq!(b[..4], MAGIC);
+        let decoded = decode_hello(&b).unwrap();
+        assert_eq!(decoded.protocol_version, PROTOCOL_VERSION);
+        assert_eq!(decoded.name, "node");
+        assert_eq!(decoded.capabilities.settings_keys, vec![String::from("east")]);
+    }
+
+    #[test]
+    fn welcome_round_trip() {
+        let welcome = Welcome::Accepted { protocol_version: PROTOCOL_VERSION, software_version: software_version(), file_types: vec![String::from("jpg")] };
+        match bincode::deserialize(&bincode::serialize(&welcome).unwrap()).unwrap() {
+            Welcome::Accepted { protocol_version, file_types, .. } => assert_eq!((protocol_version, file_types), (PROTOCOL_VERSION, vec![String::from("jpg")])),
+            w => panic!("decoded {w:?}"),
+        }
+        let rejected = Welcome::Rejected { reason: String::from("unknown node.") };
+        match bincode::deserialize(&bincode::serialize(&rejected).unwrap()).unwrap() {
+            Welcome::Rejected { reason } => assert_eq!(reason, "unknown node."),
+            w => panic!("decoded {w:?}"),
+        }
+    }
+
+    #[test]
+    fn close_reason_fits_close_frame() {
+        assert_eq!(close_reason("short"), "short");
+        assert_eq!(close_reason(&"a".repeat(200)).len(), 123);
+        // must not split the two byte 'ä' at byte 123.
+        let reason = format!("{}ä", "a".repeat(122));
+        assert_eq!(close_reason(&reason), "a".repeat(122));
+    }
+
+    #[test]
+    fn decode_hello_v9() {
+        let capabilities = (vec![String::from("cr2")], String::from("gphoto2"), true);
+        let b = bincode::serialize(&(MAGIC, PREVIEW_PROTOCOL_VERSION, "node", "0.1.0", &capabilities)).unwrap();
+        let hello = decode_hello(&b).unwrap();
+        assert_eq!((hello.protocol_version, hello.name.as_str()), (PREVIEW_PROTOCOL_VERSION, "node"));
+        assert_eq!(hello.capabilities.file_types, vec![String::from("cr2")]);
+        assert!(hello.capabilities.tracking);
+        assert!(hello.capabilities.settings_keys.is_empty());
+    }
+}
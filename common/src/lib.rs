@@ -1,2 +1,4 @@
 pub mod capture;
-pub mod processor;
\ No newline at end of file
+pub mod processor;
+pub mod handshake;
+pub mod tls;
\ No newline at end of file
@@ -1,10 +1,26 @@
-use crate::capture::settings::Settings;
+use crate::capture::calibration::Calibration;
+use crate::capture::settings::{ Settings, SettingsV1, SettingsV4, SettingsV5, SettingsV6 };
+use crate::handshake::{ PHASES_PROTOCOL_VERSION, RAMP_PROTOCOL_VERSION, BRACKET_PROTOCOL_VERSION, STACK_PROTOCOL_VERSION };
 
 use serde::{ Serialize, Deserialize };
+use uuid::Uuid;
 
 #[derive(Debug, Serialize, Deserialize)]
+#[allow(clippy::large_enum_variant)]
 pub enum Message {
     SetSettings { settings: Settings, cancel_behaviour: CancelBehaviour },
+    /// the upload with this uuid has been stored. sent from protocol version 2 on.
+    Ack { uuid: Uuid },
+    /// the upload with this uuid could not be stored. if `retry` is set, the node should send it again later.
+    Nack { uuid: Uuid, reason: String, retry: bool },
+    /// continue the chunked upload with this uuid at `offset`.
+    Resume { uuid: Uuid, offset: u64 },
+    /// capture calibration frames before the next light frame. sent from protocol version 8 on.
+    Calibrate(Calibration),
+    /// upload the raw with this uuid that is held back on the node. sent from protocol version 9 on.
+    RequestRaw { uuid: Uuid },
+    /// answers `capture::Message::RequestSettingsOf`. sent from protocol version 10 on.
+    SetSettingsOf { key: String, settings: Settings, cancel_behaviour: CancelBehaviour },
 }
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
@@ -12,4 +28,144 @@ pub enum CancelBehaviour {
     Allways,
     IfUnequal,
     Never,
-}
\ No newline at end of file
+}
+
+/// `Message::SetSettings` with an older `Settings` layout. Has to stay the first variant.
+#[derive(Serialize, Deserialize)]
+enum Versioned<S> {
+    SetSettings { settings: S, cancel_behaviour: CancelBehaviour },
+}
+
+/// Serializes `msg` for a peer speaking `protocol_version`. Older peers get settings without what they do not know.
+pub fn encode(msg: &Message, protocol_version: u32) -> bincode::Result<Vec<u8>> {
+    match msg {
+        Message::SetSettings { settings, cancel_behaviour } if protocol_version < PHASES_PROTOCOL_VERSION => {
+            bincode::serialize(&Versioned::SetSettings { settings: SettingsV1::from(settings.clone()), cancel_behaviour: *cancel_behaviour })
+        },
+        Message::SetSettings { settings, cancel_behaviour } if protocol_version < RAMP_PROTOCOL_VERSION => {
+            bincode::serialize(&Versioned::SetSettings { settings: SettingsV4::from(settings.clone()), cancel_behaviour: *cancel_behaviour })
+        },
+        Message::SetSettings { settings, cancel_behaviour } if protocol_version < BRACKET_PROTOCOL_VERSION => {
+            bincode::serialize(&Versioned::SetSettings { settings: SettingsV5::from(settings.clone()), cancel_behaviour: *cancel_behaviour })
+        },
+        Message::SetSettings { settings, cancel_behaviour } if protocol_version < STACK_PROTOCOL_VERSION => {
+            bincode::serialize(&Versioned::SetSettings { settings: SettingsV6::from(settings.clone()), cancel_behaviour: *cancel_behaviour })
+        },
+        msg => bincode::serialize(msg),
+    }
+}
+
+/// Deserializes a message of a peer speaking `protocol_version`.
+pub fn decode(b: &[u8], protocol_version: u32) -> bincode::Result<Message> {
+    if protocol_version < PHASES_PROTOCOL_VERSION {
+        if let Ok(Versioned::<SettingsV1>::SetSettings { settings, cancel_behaviour }) = bincode::deserialize(b) {
+            return Ok(Message::SetSettings { settings: settings.into(), cancel_behaviour });
+        }
+    } else if protocol_version < RAMP_PROTOCOL_VERSION {
+        if let Ok(Versioned::<SettingsV4>::SetSettings { settings, cancel_behaviour }) = bincode::deserialize(b) {
+            return Ok(Message::SetSettings { settings: settings.into(), cancel_behaviour });
+        }
+    } else if protocol_version < BRACKET_PROTOCOL_VERSION {
+        if let Ok(Versioned::<SettingsV5>::SetSettings { settings, cancel_behaviour }) = bincode::deserialize(b) {
+            return Ok(Message::SetSettings { settings: settings.into(), cancel_behaviour });
+        }
+    } else if protocol_version < STACK_PROTOCOL_VERSION {
+        if let Ok(Versioned::<SettingsV6>::SetSettings { settings, cancel_behaviour }) = bincode::deserialize(b) {
+            return Ok(Message::SetSettings { settings: settings.into(), cancel_behaviour });
+        }
+    }
+    bincode::deserialize(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::settings::bracket::Bracket;
+    use crate::capture::settings::dntime::{ Aperture, DNTime, Exposure, Frame, Iso };
+    use crate::capture::settings::phase::Phase;
+    use crate::capture::settings::ramp::{ Anchor, Curve, Ramp };
+    use crate::capture::settings::stack::{ Method, Stack };
+    use crate::handshake::PROTOCOL_VERSION;
+
+    fn dntime(exposure: f64) -> DNTime {
+        DNTime {
+            frame: Frame::Some(60.0),
+            exposure: Exposure::Manual(exposure),
+            iso: Iso::Manual(800),
+            aperture: Aperture::Manual(2.8),
+            bracket: Some(Bracket { offsets: vec![-2.0, 2.0] }),
+            stack: Some(Stack { subframes: 4, method: Method::Median }),
+        }
+    }
+
+    fn settings() -> Settings {
+        Settings {
+            horizon: -6.0,
+            daytime: dntime(0.001),
+            nighttime: dntime(10.0),
+            phases: vec![Phase { name: String::from("blue"), above: -4.0, settings: dntime(1.0) }],
+            ramp: Some(Ramp {
+                from: Anchor { altitude: 0.0, exposure: 0.01, iso: 100 },
+                to: Anchor { altitude: -10.0, exposure: 10.0, iso: 1600 },
+                curve: Curve::Smooth,
+            }),
+        }
+    }
+
+    /// `dntime` without what `DNTimeV1` lacks.
+    fn without_bracket(d: DNTime) -> DNTime {
+        DNTime { bracket: None, stack: None, ..d }
+    }
+
+    fn round_trip(settings: Settings, protocol_version: u32) -> Settings {
+        let msg = Message::SetSettings { settings, cancel_behaviour: CancelBehaviour::IfUnequal };
+        match decode(&encode(&msg, protocol_version).unwrap(), protocol_version).unwrap() {
+            Message::SetSettings { settings, cancel_behaviour: CancelBehaviour::IfUnequal } => settings,
+            msg => panic!("decoded {msg:?}"),
+        }
+    }
+
+    #[test]
+    fn set_settings_current() {
+        assert_eq!(round_trip(settings(), PROTOCOL_VERSION), settings());
+    }
+
+    #[test]
+    fn set_settings_v1() {
+        let s = settings();
+        let expected = Settings { horizon: s.horizon, daytime: without_bracket(s.daytime), nighttime: without_bracket(s.nighttime), phases: vec![], ramp: None };
+        assert_eq!(round_trip(settings(), PHASES_PROTOCOL_VERSION - 1), expected);
+    }
+
+    #[test]
+    fn set_settings_v4() {
+        let s = settings();
+        let phases = s.phases.into_iter().map(|p| Phase { settings: without_bracket(p.settings), ..p }).collect();
+        let expected = Settings { horizon: s.horizon, daytime: without_bracket(s.daytime), nighttime: without_bracket(s.nighttime), phases, ramp: None };
+        assert_eq!(round_trip(settings(), PHASES_PROTOCOL_VERSION), expected);
+    }
+
+    #[test]
+    fn other_messages_unchanged() {
+        let uuid = Uuid::new_v4();
+        let b = encode(&Message::Ack { uuid }, PHASES_PROTOCOL_VERSION - 1).unwrap();
+        assert!(matches!(decode(&b, PHASES_PROTOCOL_VERSION - 1).unwrap(), Message::Ack { uuid: u } if u == uuid));
+    }
+
+    #[test]
+    fn set_settings_v5() {
+        let s = settings();
+        let phases = s.phases.into_iter().map(|p| Phase { settings: without_bracket(p.settings), ..p }).collect();
+        let expected = Settings { horizon: s.horizon, daytime: without_bracket(s.daytime), nighttime: without_bracket(s.nighttime), phases, ramp: s.ramp };
+        assert_eq!(round_trip(settings(), RAMP_PROTOCOL_VERSION), expected);
+    }
+
+    #[test]
+    fn set_settings_v6() {
+        let without_stack = |d: DNTime| DNTime { stack: None, ..d };
+        let s = settings();
+        let phases = s.phases.into_iter().map(|p| Phase { settings: without_stack(p.settings), ..p }).collect();
+        let expected = Settings { horizon: s.horizon, daytime: without_stack(s.daytime), nighttime: without_stack(s.nighttime), phases, ramp: s.ramp };
+        assert_eq!(round_trip(settings(), BRACKET_PROTOCOL_VERSION), expected);
+    }
+}
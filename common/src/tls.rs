@@ -0,0 +1,55 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{ Path, PathBuf };
+
+use rustls::{ Certificate, PrivateKey, RootCertStore };
+use rustls_pemfile::Item;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum TlsError {
+    #[error("unable to read {0:?}. {1}")]
+    IO(PathBuf, std::io::Error),
+
+    #[error("no certificate found in {0:?}.")]
+    NoCertificate(PathBuf),
+
+    #[error("no private key found in {0:?}.")]
+    NoKey(PathBuf),
+
+    #[error("{1} invalid certificates in {0:?}.")]
+    InvalidCertificate(PathBuf, usize),
+
+    #[error("{0}")]
+    Rustls(rustls::Error),
+}
+
+/// Reads all certificates of a pem file.
+pub fn load_certs(path: &Path) -> Result<Vec<Certificate>, TlsError> {
+    let mut reader = BufReader::new(File::open(path).map_err(|e| TlsError::IO(path.into(), e))?);
+    let certs = rustls_pemfile::certs(&mut reader).map_err(|e| TlsError::IO(path.into(), e))?;
+    if certs.is_empty() { return Err(TlsError::NoCertificate(path.into())); }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+/// Reads the first RSA, PKCS8 or EC private key of a pem file.
+pub fn load_key(path: &Path) -> Result<PrivateKey, TlsError> {
+    let mut reader = BufReader::new(File::open(path).map_err(|e| TlsError::IO(path.into(), e))?);
+    loop {
+        match rustls_pemfile::read_one(&mut reader).map_err(|e| TlsError::IO(path.into(), e))? {
+            Some(Item::RSAKey(k) | Item::PKCS8Key(k) | Item::ECKey(k)) => return Ok(PrivateKey(k)),
+            Some(_) => { },
+            None => return Err(TlsError::NoKey(path.into())),
+        }
+    }
+}
+
+/// Builds a root store of all certificates in a pem file.
+pub fn load_roots(path: &Path) -> Result<RootCertStore, TlsError> {
+    let certs: Vec<Vec<u8>> = load_certs(path)?.into_iter().map(|c| c.0).collect();
+    let mut roots = RootCertStore::empty();
+    match roots.add_parsable_certificates(&certs) {
+        (_, 0) => Ok(roots),
+        (_, invalid) => Err(TlsError::InvalidCertificate(path.into(), invalid)),
+    }
+}
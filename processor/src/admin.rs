@@ -0,0 +1,221 @@
+use std::convert::Infallible;
+
+use hyper::{ Body, Method, Request, Response, Server, StatusCode };
+use hyper::service::{ make_service_fn, service_fn };
+use log::{ error, info, debug };
+
+use common::capture::{calibration::{Calibration, FrameKind}, valid_id};
+use common::processor::{CancelBehaviour, Message as PMsg};
+use uuid::Uuid;
+
+use crate::registry::PushError;
+use crate::settings::{ self, SettingsError };
+
+/// Serves the local admin endpoint described in `defaults/admin.toml`.
+pub async fn serve() {
+    let addr = crate::CONFIG.admin.socket;
+    let server = match Server::try_bind(&addr) {
+        Ok(b) => b.serve(make_service_fn(|_| async { Ok::<_, Infallible>(service_fn(handle)) })),
+        Err(e) => {
+            error!("unable to bind admin endpoint to {addr}. {e}");
+            return;
+        },
+    };
+    info!("admin endpoint listening on http://{addr}");
+    if let Err(e) = server.await {
+        error!("admin endpoint failed. {e}");
+    }
+}
+
+async fn handle(req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    debug!("admin {method} {uri}", method = req.method(), uri = req.uri());
+    let path = req.uri().path().trim_matches('/').to_owned();
+    let segments: Vec<&str> = path.split('/').collect();
+    let response = match (req.method(), segments.as_slice()) {
+        (&Method::GET, ["nodes"]) => {
+            let nodes: String = crate::REGISTRY.nodes().iter()
+                .map(|n| format!("{name} {addr} protocol_version={protocol_version}\n", name = n.name, addr = n.addr, protocol_version = n.protocol_version))
+                .collect();
+            reply(StatusCode::OK, nodes)
+        },
+        (&Method::PUT, ["nodes", name, "settings"]) => {
+            let name = name.to_string();
+            update(req, &name).await
+        },
+        (&Method::PUT, ["cameras", key, "settings"]) => {
+            let key = key.to_string();
+            update_camera(req, &key).await
+        },
+        (&Method::POST, ["nodes", name, "calibrate"]) => {
+            let name = name.to_string();
+            calibrate(req.uri().query(), &name).await
+        },
+        (&Method::POST, ["nodes", name, "raws", uuid]) => {
+            let (name, uuid) = (name.to_string(), uuid.to_string());
+            request_raw(&name, &uuid).await
+        },
+        _ => reply(StatusCode::NOT_FOUND, String::from("not found.\n")),
+    };
+    Ok(response)
+}
+
+/// Stores the settings in the body and pushes them to the node if it is connected.
+/// Answers 200 if they were delivered and 202 if they are only stored.
+async fn update(req: Request<Body>, name: &str) -> Response<Body> {
+    if !valid_id(name) {
+        return reply(StatusCode::BAD_REQUEST, format!("invalid node name {name:?}.\n"));
+    }
+    let cancel_behaviour = match cancel_behaviour(req.uri().query()) {
+        Ok(c) => c,
+        Err(e) => return reply(StatusCode::BAD_REQUEST, e),
+    };
+    let body = match hyper::body::to_bytes(req.into_body()).await.map(|b| String::from_utf8(b.to_vec())) {
+        Ok(Ok(body)) => body,
+        Ok(Err(e)) => return reply(StatusCode::BAD_REQUEST, format!("body is not utf-8. {e}\n")),
+        Err(e) => return reply(StatusCode::BAD_REQUEST, format!("unable to read body. {e}\n")),
+    };
+
+    let settings = match settings::store(name, &body) {
+        Ok(s) => s,
+        Err(e @ SettingsError::Invalid(_)) => return reply(StatusCode::BAD_REQUEST, format!("{e}\n")),
+        Err(e) => {
+            error!("{e}");
+            return reply(StatusCode::INTERNAL_SERVER_ERROR, format!("{e}\n"));
+        },
+    };
+
+    match crate::REGISTRY.push(name, PMsg::SetSettings { settings, cancel_behaviour }).await {
+        Ok(()) => reply(StatusCode::OK, format!("delivered to {name} with {cancel_behaviour:?}.\n")),
+        Err(e @ PushError::NotConnected(_)) => reply(StatusCode::ACCEPTED, format!("stored. {e} it receives the settings on its next request.\n")),
+        Err(e) => {
+            error!("{e}");
+            reply(StatusCode::ACCEPTED, format!("stored, but not delivered. {e}\n"))
+        },
+    }
+}
+
+/// Stores the camera settings in the body and pushes them to every connected node whose cameras use `key`.
+/// Answers 200 if they were delivered to all of them and 202 if they are only stored.
+async fn update_camera(req: Request<Body>, key: &str) -> Response<Body> {
+    if !valid_id(key) {
+        return reply(StatusCode::BAD_REQUEST, format!("invalid settings key {key:?}.\n"));
+    }
+    let cancel_behaviour = match cancel_behaviour(req.uri().query()) {
+        Ok(c) => c,
+        Err(e) => return reply(StatusCode::BAD_REQUEST, e),
+    };
+    let body = match hyper::body::to_bytes(req.into_body()).await.map(|b| String::from_utf8(b.to_vec())) {
+        Ok(Ok(body)) => body,
+        Ok(Err(e)) => return reply(StatusCode::BAD_REQUEST, format!("body is not utf-8. {e}\n")),
+        Err(e) => return reply(StatusCode::BAD_REQUEST, format!("unable to read body. {e}\n")),
+    };
+
+    let settings = match settings::store_camera(key, &body) {
+        Ok(s) => s,
+        Err(e @ SettingsError::Invalid(_)) => return reply(StatusCode::BAD_REQUEST, format!("{e}\n")),
+        Err(e) => {
+            error!("{e}");
+            return reply(StatusCode::INTERNAL_SERVER_ERROR, format!("{e}\n"));
+        },
+    };
+
+    let nodes = crate::REGISTRY.users(key);
+    if nodes.is_empty() {
+        return reply(StatusCode::ACCEPTED, format!("stored. no connected node uses the camera settings {key}. they are received on the next request.\n"));
+    }
+    let mut status = StatusCode::OK;
+    let mut body = String::new();
+    for name in nodes {
+        let msg = PMsg::SetSettingsOf { key: key.to_owned(), settings: settings.clone(), cancel_behaviour };
+        match crate::REGISTRY.push(&name, msg).await {
+            Ok(()) => body.push_str(&format!("delivered to {name} with {cancel_behaviour:?}.\n")),
+            Err(e) => {
+                error!("{e}");
+                status = StatusCode::ACCEPTED;
+                body.push_str(&format!("stored, but not delivered. {e}\n"));
+            },
+        }
+    }
+    reply(status, body)
+}
+
+/// Requests calibration frames from a connected node. They are captured before its next light frame.
+async fn calibrate(query: Option<&str>, name: &str) -> Response<Body> {
+    let param = |key: &str| query.unwrap_or("").split('&').find_map(|kv| kv.strip_prefix(key)?.strip_prefix('='));
+
+    let kind = match param("kind").map(|k| (k, FrameKind::from_name(k))) {
+        Some((_, Some(k))) if k != FrameKind::Light => k,
+        Some((k, _)) => return reply(StatusCode::BAD_REQUEST, format!("invalid kind {k:?}. expected dark, flat or bias.\n")),
+        None => return reply(StatusCode::BAD_REQUEST, String::from("kind is missing. expected dark, flat or bias.\n")),
+    };
+    let frames = match param("frames").map(str::parse::<u32>) {
+        None => 1,
+        Some(Ok(f)) if f > 0 => f,
+        Some(_) => return reply(StatusCode::BAD_REQUEST, String::from("frames has to be a number greater than 0.\n")),
+    };
+    let covered = param("covered") == Some("yes");
+    if kind.covered() && !covered {
+        return reply(StatusCode::BAD_REQUEST, format!("{kind} frames need the lens covered. put the cap on and confirm with covered=yes.\n", kind = kind.name()));
+    }
+
+    match crate::REGISTRY.push(name, PMsg::Calibrate(Calibration { kind, frames, covered })).await {
+        Ok(()) => reply(StatusCode::OK, format!("requested {frames} {kind} frames from {name}.\n", kind = kind.name())),
+        Err(e @ PushError::NotConnected(_)) => reply(StatusCode::NOT_FOUND, format!("{e}\n")),
+        Err(e) => {
+            error!("{e}");
+            reply(StatusCode::BAD_GATEWAY, format!("{e}\n"))
+        },
+    }
+}
+
+/// Requests a raw the node held back after uploading its preview.
+async fn request_raw(name: &str, uuid: &str) -> Response<Body> {
+    let uuid = match Uuid::parse_str(uuid) {
+        Ok(u) => u,
+        Err(e) => return reply(StatusCode::BAD_REQUEST, format!("invalid uuid {uuid:?}. {e}\n")),
+    };
+    match crate::REGISTRY.push(name, PMsg::RequestRaw { uuid }).await {
+        Ok(()) => reply(StatusCode::OK, format!("requested raw {uuid} from {name}.\n")),
+        Err(e @ PushError::NotConnected(_)) => reply(StatusCode::NOT_FOUND, format!("{e}\n")),
+        Err(e) => {
+            error!("{e}");
+            reply(StatusCode::BAD_GATEWAY, format!("{e}\n"))
+        },
+    }
+}
+
+fn cancel_behaviour(query: Option<&str>) -> Result<CancelBehaviour, String> {
+    let value = query.unwrap_or("").split('&')
+        .find_map(|kv| kv.strip_prefix("cancel="));
+    match value {
+        None | Some("ifunequal") => Ok(CancelBehaviour::IfUnequal),
+        Some("allways" | "always") => Ok(CancelBehaviour::Allways),
+        Some("never") => Ok(CancelBehaviour::Never),
+        Some(v) => Err(format!("invalid cancel behaviour {v:?}. expected allways, ifunequal or never.\n")),
+    }
+}
+
+fn reply(status: StatusCode, body: String) -> Response<Body> {
+    let mut response = Response::new(Body::from(body));
+    *response.status_mut() = status;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_cancel_behaviour() {
+        assert!(matches!(cancel_behaviour(None), Ok(CancelBehaviour::IfUnequal)));
+        assert!(matches!(cancel_behaviour(Some("")), Ok(CancelBehaviour::IfUnequal)));
+        assert!(matches!(cancel_behaviour(Some("other=1")), Ok(CancelBehaviour::IfUnequal)));
+        assert!(matches!(cancel_behaviour(Some("cancel=ifunequal")), Ok(CancelBehaviour::IfUnequal)));
+        assert!(matches!(cancel_behaviour(Some("cancel=always")), Ok(CancelBehaviour::Allways)));
+        assert!(matches!(cancel_behaviour(Some("other=1&cancel=allways")), Ok(CancelBehaviour::Allways)));
+        assert!(matches!(cancel_behaviour(Some("cancel=never")), Ok(CancelBehaviour::Never)));
+        assert_eq!(cancel_behaviour(Some("cancel=sometimes")).unwrap_err(), "invalid cancel behaviour \"sometimes\". expected allways, ifunequal or never.\n");
+        assert!(cancel_behaviour(Some("cancel=")).is_err());
+        assert!(cancel_behaviour(Some("cancel=Never")).is_err());
+    }
+}
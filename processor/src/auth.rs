@@ -0,0 +1,59 @@
+use tokio_tungstenite::tungstenite::http::{ HeaderValue, header::AUTHORIZATION };
+use tokio_tungstenite::tungstenite::handshake::server::Request;
+
+/// Checks the bearer token of a connecting node against `auth.nodes`.
+/// Nodes without a configured token are let in unless `auth.require_token` is set.
+pub fn authorize(name: &str, req: &Request) -> Result<(), String> {
+    check(name, req, crate::CONFIG.auth.token(name), crate::CONFIG.auth.require_token)
+}
+
+/// Checks the bearer token of `req` against the `expected` token of `name`.
+fn check(name: &str, req: &Request, expected: Option<&str>, require_token: bool) -> Result<(), String> {
+    let expected = match expected {
+        Some(t) => t,
+        None if require_token => return Err(format!("no token configured for {name}. (auth.require_token)")),
+        None => return Ok(()),
+    };
+
+    match req.headers().get(AUTHORIZATION).map(HeaderValue::to_str) {
+        Some(Ok(v)) => match v.strip_prefix("Bearer ") {
+            Some(token) if eq(token.as_bytes(), expected.as_bytes()) => Ok(()),
+            Some(_) => Err(format!("{name} sent an invalid token.")),
+            None => Err(format!("{name} sent an unsupported authorization scheme.")),
+        },
+        Some(Err(_)) => Err(format!("{name} sent a malformed authorization header.")),
+        None => Err(format!("{name} sent no token.")),
+    }
+}
+
+/// Compares in constant time, so the token cannot be guessed byte by byte.
+fn eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(authorization: Option<&str>) -> Request {
+        let mut req = Request::builder().uri("/");
+        if let Some(a) = authorization { req = req.header(AUTHORIZATION, a); }
+        req.body(()).unwrap()
+    }
+
+    #[test]
+    fn check_tokens() {
+        assert_eq!(check("east", &request(Some("Bearer secret")), Some("secret"), false), Ok(()));
+        assert_eq!(check("east", &request(None), Some("secret"), false), Err(String::from("east sent no token.")));
+        assert_eq!(check("east", &request(Some("Bearer secreT")), Some("secret"), false), Err(String::from("east sent an invalid token.")));
+        assert_eq!(check("east", &request(Some("Bearer secret2")), Some("secret"), false), Err(String::from("east sent an invalid token.")));
+        assert_eq!(check("east", &request(Some("Basic secret")), Some("secret"), false), Err(String::from("east sent an unsupported authorization scheme.")));
+    }
+
+    #[test]
+    fn check_unknown_nodes() {
+        assert_eq!(check("west", &request(None), None, false), Ok(()));
+        assert_eq!(check("west", &request(Some("Bearer secret")), None, false), Ok(()));
+        assert_eq!(check("west", &request(Some("Bearer secret")), None, true), Err(String::from("no token configured for west. (auth.require_token)")));
+    }
+}
@@ -0,0 +1,72 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Local};
+use log::info;
+
+use common::capture::calibration::FrameKind;
+use common::capture::settings::dntime::{DNTime, Exposure, Iso, Aperture};
+use common::capture::FileType;
+
+use crate::CONFIG;
+
+/// Directory of the calibration library holding the frames of `kind` of `node` captured with `settings`.
+/// darks are kept by exposure and ISO, bias frames by ISO and flats by aperture and ISO.
+pub fn directory(node: &str, kind: FrameKind, settings: Option<&DNTime>) -> PathBuf {
+    let dir = CONFIG.general.calibration.join(node).join(kind.name());
+    let settings = match settings {
+        Some(s) => s,
+        None => return dir.join("unknown"),
+    };
+    let exposure = match settings.exposure {
+        Exposure::Manual(f) => format!("{f}s"),
+        Exposure::Auto => String::from("auto"),
+    };
+    let iso = match settings.iso {
+        Iso::Manual(u) => format!("iso{u}"),
+        Iso::Auto => String::from("isoauto"),
+    };
+    let aperture = match settings.aperture {
+        Aperture::Manual(f) => format!("f{f}"),
+        Aperture::Auto | Aperture::Implicit => String::from("fauto"),
+    };
+    match kind {
+        FrameKind::Dark => dir.join(format!("{exposure}_{iso}")),
+        FrameKind::Bias => dir.join(iso),
+        FrameKind::Flat | FrameKind::Light => dir.join(format!("{aperture}_{iso}")),
+    }
+}
+
+/// Exposure in seconds and ISO of a dark directory named by `directory`.
+pub fn parse_dark_directory(name: &str) -> Option<(f64, u32)> {
+    let (exposure, iso) = name.split_once("s_iso")?;
+    Some((exposure.parse().ok()?, iso.parse().ok()?))
+}
+
+/// Moves the calibration frame at `filepath` into the library. Returns its new path.
+pub fn file(node: &str, kind: FrameKind, settings: Option<&DNTime>, time: DateTime<Local>, file_type: FileType, filepath: &Path) -> io::Result<PathBuf> {
+    let dir = directory(node, kind, settings);
+    std::fs::create_dir_all(&dir)?;
+    let target = dir.join(format!("{ts}.{ext}", ts = time.format("%Y-%m-%d %H:%M:%S%.3f"), ext = file_type.ext()));
+    if std::fs::rename(filepath, &target).is_err() {
+        // tmp_path may be on another file system.
+        std::fs::copy(filepath, &target)?;
+        std::fs::remove_file(filepath)?;
+    }
+    info!("filed {kind} frame of {node} as {target:?}.", kind = kind.name());
+    Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_dark_directories() {
+        assert_eq!(parse_dark_directory("30s_iso800"), Some((30.0, 800)));
+        assert_eq!(parse_dark_directory("0.5s_iso100"), Some((0.5, 100)));
+        assert_eq!(parse_dark_directory("auto_iso800"), None);
+        assert_eq!(parse_dark_directory("30s_isoauto"), None);
+        assert_eq!(parse_dark_directory("unknown"), None);
+    }
+}
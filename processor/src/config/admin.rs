@@ -0,0 +1,19 @@
+use std::net::SocketAddr;
+
+use serde::{ Deserialize, Deserializer, de::Unexpected };
+
+#[derive(Debug, Deserialize)]
+pub struct Admin {
+    pub enable: bool,
+
+    #[serde(deserialize_with = "deserialize_socket")]
+    pub socket: SocketAddr,
+}
+
+fn deserialize_socket<'de, D>(d: D) -> Result<SocketAddr, D::Error> where D: Deserializer<'de> {
+    let s = String::deserialize(d)?;
+    match s.parse::<SocketAddr>() {
+        Ok(a) => Ok(a),
+        Err(e) => Err(serde::de::Error::invalid_value(Unexpected::Str(&s), &format!("to be ip:port. (admin.socket) {e}").as_str())),
+    }
+}
@@ -0,0 +1,35 @@
+use serde::{ Deserialize, Deserializer, de::Unexpected };
+
+#[derive(Debug, Deserialize)]
+pub struct Auth {
+    /// refuse nodes that have no token configured.
+    pub require_token: bool,
+
+    #[serde(deserialize_with = "deserialize_nodes")]
+    pub nodes: Vec<Node>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Node {
+    pub name: String,
+    pub token: String,
+}
+
+impl Auth {
+    pub fn token(&self, name: &str) -> Option<&str> {
+        self.nodes.iter().find(|n| n.name == name).map(|n| n.token.as_str())
+    }
+}
+
+fn deserialize_nodes<'de, D>(d: D) -> Result<Vec<Node>, D::Error> where D: Deserializer<'de> {
+    let nodes = Vec::<Node>::deserialize(d)?;
+    for (i, n) in nodes.iter().enumerate() {
+        if n.token.is_empty() {
+            return Err(serde::de::Error::invalid_value(Unexpected::Str(&n.token), &format!("to be a non empty token. (auth.nodes.{name})", name = n.name).as_str()));
+        }
+        if nodes[..i].iter().any(|o| o.name == n.name) {
+            return Err(serde::de::Error::invalid_value(Unexpected::Str(&n.name), &"to be configured only once. (auth.nodes)"));
+        }
+    }
+    Ok(nodes)
+}
@@ -0,0 +1,29 @@
+use std::path::PathBuf;
+
+use serde::{ Deserialize, Deserializer, de::Unexpected };
+
+#[derive(Debug, Deserialize)]
+pub struct Darks {
+    /// subtract the best matching dark of the calibration library before development.
+    pub subtract: bool,
+
+    /// largest relative difference between the exposure of a dark and a light frame.
+    #[serde(deserialize_with = "deserialize_tolerance")]
+    pub exposure_tolerance: f64,
+
+    /// largest difference in sensor temperature in °C, if both temperatures are known.
+    #[serde(deserialize_with = "deserialize_tolerance")]
+    pub temperature_tolerance: f64,
+
+    /// interpolate hot and dead pixels found in each raw.
+    pub hot_pixels: bool,
+
+    /// reads the sensor temperature. temperatures are unknown if it is not installed.
+    pub exiftool: PathBuf,
+}
+
+fn deserialize_tolerance<'de, D>(d: D) -> Result<f64, D::Error> where D: Deserializer<'de> {
+    let value = f64::deserialize(d)?;
+    if value.is_finite() && value >= 0.0 { Ok(value) }
+    else { Err(serde::de::Error::invalid_value(Unexpected::Float(value), &"to be at least 0. (darks)")) }
+}
@@ -10,8 +10,19 @@ pub struct General {
 
     pub tmp_path: PathBuf,
 
+    pub ledger: PathBuf,
+
+    pub settings: PathBuf,
+
+    pub calibration: PathBuf,
+
     #[serde(deserialize_with = "deserialize_queue")]
     pub queue: usize,
+
+    pub accept_legacy: bool,
+
+    #[serde(deserialize_with = "deserialize_max_upload")]
+    pub max_upload: u64,
 }
 
 fn deserialize_socket<'de, D>(d: D) -> Result<Url, D::Error> where D: Deserializer<'de> {
@@ -25,7 +36,13 @@ fn deserialize_socket<'de, D>(d: D) -> Result<Url, D::Error> where D: Deserializ
 fn deserialize_queue<'de, D>(d: D) -> Result<usize, D::Error> where D: Deserializer<'de> {
     let s = String::deserialize(d)?;
     match s.parse::<usize>() {
+        Ok(0) => Err(serde::de::Error::invalid_value(Unexpected::Unsigned(0), &"to be greater than zero. (general.queue)")),
         Ok(u) => Ok(u),
         Err(e) => Err(serde::de::Error::invalid_value(Unexpected::Str(&s), &format!("to be an usize. (general.queue) {e}").as_str())),
     }
-}
\ No newline at end of file
+}
+fn deserialize_max_upload<'de, D>(d: D) -> Result<u64, D::Error> where D: Deserializer<'de> {
+    let value = u64::deserialize(d)?;
+    if value > 0 { Ok(value.saturating_mul(1024 * 1024)) }
+    else { Err(serde::de::Error::invalid_value(Unexpected::Unsigned(value), &"to be greater than zero. (general.max_upload)")) }
+}
@@ -0,0 +1,15 @@
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct Hdr {
+    /// merge the frames of an exposure bracket into one image. otherwise only the frame at the base exposure is processed.
+    pub merge: bool,
+
+    /// align the frames before merging. tracked or windy brackets may be offset by a few pixels.
+    pub align: bool,
+
+    pub enfuse: PathBuf,
+    pub align_image_stack: PathBuf,
+}
@@ -1,11 +1,22 @@
 mod general;
 mod logging;
+pub mod tls;
+pub mod auth;
+pub mod admin;
+pub mod hdr;
+pub mod stack;
+pub mod darks;
 
 use serde::Deserialize;
-use config_rs;
 
 use general::General;
 use logging::Logging;
+use tls::Tls;
+use auth::Auth;
+use admin::Admin;
+use hdr::Hdr;
+use stack::Stack;
+use darks::Darks;
 
 const CONFIGS: &[&str] = &["test.toml", "nonexistant.toml"];
 
@@ -13,6 +24,12 @@ const CONFIGS: &[&str] = &["test.toml", "nonexistant.toml"];
 pub struct Config {
     pub general: General,
     pub logging: Logging,
+    pub tls: Tls,
+    pub auth: Auth,
+    pub admin: Admin,
+    pub hdr: Hdr,
+    pub stack: Stack,
+    pub darks: Darks,
 }
 
 impl Config {
@@ -20,6 +37,12 @@ impl Config {
         let mut config_rs_builder = config_rs::Config::builder()
             .add_source(config_rs::File::from_str(include_str!("defaults/general.toml"), config_rs::FileFormat::Toml))
             .add_source(config_rs::File::from_str(include_str!("defaults/logging.toml"), config_rs::FileFormat::Toml))
+            .add_source(config_rs::File::from_str(include_str!("defaults/tls.toml"), config_rs::FileFormat::Toml))
+            .add_source(config_rs::File::from_str(include_str!("defaults/auth.toml"), config_rs::FileFormat::Toml))
+            .add_source(config_rs::File::from_str(include_str!("defaults/admin.toml"), config_rs::FileFormat::Toml))
+            .add_source(config_rs::File::from_str(include_str!("defaults/hdr.toml"), config_rs::FileFormat::Toml))
+            .add_source(config_rs::File::from_str(include_str!("defaults/stack.toml"), config_rs::FileFormat::Toml))
+            .add_source(config_rs::File::from_str(include_str!("defaults/darks.toml"), config_rs::FileFormat::Toml))
             ;
         for s in CONFIGS {
             config_rs_builder = config_rs_builder.add_source(config_rs::File::with_name(s).required(false));
@@ -0,0 +1,13 @@
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct Stack {
+    /// align the subframes before stacking. required unless the camera is tracked perfectly.
+    pub align: bool,
+
+    pub align_image_stack: PathBuf,
+    /// imagemagick
+    pub convert: PathBuf,
+}
@@ -0,0 +1,28 @@
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct Tls {
+    pub enable: bool,
+
+    pub cert: PathBuf,
+    pub key: PathBuf,
+
+    /// CA used to verify client certificates. required unless `client_auth` is `none`.
+    #[serde(default)]
+    pub client_ca: Option<PathBuf>,
+
+    pub client_auth: ClientAuth,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClientAuth {
+    /// client certificates are not requested.
+    None,
+    /// client certificates are verified if presented.
+    Optional,
+    /// nodes without a valid client certificate are refused.
+    Required,
+}
@@ -0,0 +1,180 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+use log::{debug, info, warn, error};
+
+use common::capture::calibration::FrameKind;
+use common::capture::settings::dntime::{DNTime, Exposure, Iso};
+
+use crate::CONFIG;
+use crate::calibration;
+use crate::develop::Intermediates;
+
+lazy_static! {
+    /// Sensor temperatures of the darks in the library. Darks are never rewritten, so they are read once.
+    static ref TEMPERATURES: Mutex<HashMap<PathBuf, Option<f64>>> = Mutex::new(HashMap::new());
+    /// Node, exposure and ISO without a matching dark that were already warned about.
+    static ref MISSING: Mutex<HashSet<(String, u64, u32)>> = Mutex::new(HashSet::new());
+}
+
+/// Writes a partial RawTherapee profile to `tmp` that subtracts the best matching dark of `node` from `raw`
+/// and filters hot pixels, as configured. Returns `None` if there is nothing to apply.
+pub fn profile(node: &str, settings: Option<&DNTime>, raw: &Path, tmp: &Path, intermediates: &mut Intermediates) -> Option<PathBuf> {
+    let dark = if CONFIG.darks.subtract { find(node, settings, raw) } else { None };
+    if dark.is_none() && !CONFIG.darks.hot_pixels { return None; }
+
+    let mut pp3 = String::from("[RAW]\n");
+    if let Some(dark) = &dark {
+        pp3.push_str(&format!("DarkFrame={}\nDarkFrameAuto=false\n", dark.display()));
+    }
+    if CONFIG.darks.hot_pixels {
+        pp3.push_str("HotPixelFilter=true\nDeadPixelFilter=true\n");
+    }
+
+    let path = tmp.join(raw.file_stem().unwrap_or_default()).with_extension("darks.pp3");
+    intermediates.add(path.clone());
+    if let Err(e) = std::fs::write(&path, pp3) {
+        error!("unable to write dark profile {path:?}. {raw:?} is developed without it. {e}");
+        return None;
+    }
+    Some(path)
+}
+
+/// Best matching dark for a light frame captured with `settings`.
+/// The ISO has to be equal and the exposure within the tolerance. Of the group of the closest exposure,
+/// the dark closest in sensor temperature is used, then the newest one. Darks too far off in temperature are skipped.
+fn find(node: &str, settings: Option<&DNTime>, raw: &Path) -> Option<PathBuf> {
+    let (exposure, iso) = match settings.map(|s| (s.exposure, s.iso)) {
+        Some((Exposure::Manual(e), Iso::Manual(i))) => (e, i),
+        Some(_) => {
+            debug!("{raw:?} has automatic exposure or ISO. no dark is subtracted.");
+            return None;
+        },
+        None => {
+            debug!("{raw:?} does not record its settings. no dark is subtracted.");
+            return None;
+        },
+    };
+
+    let library = CONFIG.general.calibration.join(node).join(FrameKind::Dark.name());
+    let group = std::fs::read_dir(&library).into_iter().flatten().flatten()
+        .filter_map(|entry| {
+            let (e, i) = calibration::parse_dark_directory(&entry.file_name().to_string_lossy())?;
+            let deviation = (e - exposure).abs() / exposure;
+            (i == iso && deviation <= CONFIG.darks.exposure_tolerance).then(|| (deviation, entry.path()))
+        })
+        .min_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(_, path)| path);
+
+    let dark = group.as_deref().and_then(|group| select(group, raw));
+    match &dark {
+        Some(dark) => info!("subtracting {dark:?} from {raw:?}."),
+        None => {
+            let key = (node.to_string(), exposure.to_bits(), iso);
+            if MISSING.lock().unwrap().insert(key) {
+                warn!("no dark of {node} matches exposure={exposure}s iso={iso}. frames are developed without dark subtraction. capture darks to fill {library:?}.");
+            } else {
+                debug!("no dark of {node} matches exposure={exposure}s iso={iso}.");
+            }
+        },
+    }
+    dark
+}
+
+fn select(group: &Path, raw: &Path) -> Option<PathBuf> {
+    let darks = std::fs::read_dir(group).into_iter().flatten().flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .map(|dark| { let t = cached_temperature(&dark); (dark, t) })
+        .collect::<Vec<_>>();
+    let dark = rank(darks, temperature(raw), CONFIG.darks.temperature_tolerance)?;
+    Some(std::fs::canonicalize(&dark).unwrap_or(dark))
+}
+
+/// Picks the dark closest in sensor temperature to a light frame captured at `light` °C out of `darks` and their temperatures.
+/// Darks more than `tolerance` off are skipped, those of unknown temperature only win if no temperature is close.
+/// Ties go to the newest dark.
+fn rank(mut darks: Vec<(PathBuf, Option<f64>)>, light: Option<f64>, tolerance: f64) -> Option<PathBuf> {
+    // file names are capture times. newest first.
+    darks.sort_by(|a, b| b.0.cmp(&a.0));
+
+    let mut best: Option<(f64, PathBuf)> = None;
+    for (dark, t) in darks {
+        let distance = match (light, t) {
+            (Some(l), Some(d)) if (l - d).abs() > tolerance => {
+                debug!("skipping {dark:?}. it was captured at {d}°C, the light frame at {l}°C.");
+                continue;
+            },
+            (Some(l), Some(d)) => (l - d).abs(),
+            _ => f64::INFINITY,
+        };
+        let better = match &best {
+            None => true,
+            Some((d, _)) => distance < *d,
+        };
+        if better { best = Some((distance, dark)); }
+    }
+    best.map(|(_, dark)| dark)
+}
+
+fn cached_temperature(dark: &Path) -> Option<f64> {
+    if let Some(t) = TEMPERATURES.lock().unwrap().get(dark) { return *t; }
+    let t = temperature(dark);
+    TEMPERATURES.lock().unwrap().insert(dark.into(), t);
+    t
+}
+
+/// Sensor temperature in °C recorded by the camera, if any.
+fn temperature(raw: &Path) -> Option<f64> {
+    let output = match Command::new(&CONFIG.darks.exiftool).args(["-s3", "-CameraTemperature"]).arg(raw).output() {
+        Ok(o) => o,
+        Err(e) => {
+            debug!("unable to run {exiftool:?}. sensor temperatures are unknown. {e}", exiftool = CONFIG.darks.exiftool);
+            return None;
+        },
+    };
+    // exiftool prints e.g. "23 C".
+    String::from_utf8_lossy(&output.stdout).split_whitespace().next()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn darks(darks: &[(&str, Option<f64>)]) -> Vec<(PathBuf, Option<f64>)> {
+        darks.iter().map(|(name, t)| (PathBuf::from(name), *t)).collect()
+    }
+
+    #[test]
+    fn rank_prefers_closest_temperature() {
+        let darks = darks(&[("2024-01-01.cr2", Some(10.0)), ("2024-01-03.cr2", Some(14.0)), ("2024-01-02.cr2", Some(11.5))]);
+        assert_eq!(rank(darks, Some(11.0), 3.0), Some(PathBuf::from("2024-01-02.cr2")));
+    }
+
+    #[test]
+    fn rank_prefers_known_temperature_over_newer_unknown() {
+        let darks = darks(&[("2024-01-01.cr2", Some(12.0)), ("2024-01-05.cr2", None)]);
+        assert_eq!(rank(darks, Some(10.0), 3.0), Some(PathBuf::from("2024-01-01.cr2")));
+    }
+
+    #[test]
+    fn rank_skips_darks_beyond_tolerance() {
+        let only_far = darks(&[("2024-01-01.cr2", Some(20.0))]);
+        assert_eq!(rank(only_far, Some(10.0), 3.0), None);
+        let far_and_unknown = darks(&[("2024-01-01.cr2", None), ("2024-01-02.cr2", Some(20.0))]);
+        assert_eq!(rank(far_and_unknown, Some(10.0), 3.0), Some(PathBuf::from("2024-01-01.cr2")));
+    }
+
+    #[test]
+    fn rank_ties_go_to_newest() {
+        let same = darks(&[("2024-01-01.cr2", Some(10.0)), ("2024-01-03.cr2", Some(10.0)), ("2024-01-02.cr2", Some(10.0))]);
+        assert_eq!(rank(same, Some(10.0), 3.0), Some(PathBuf::from("2024-01-03.cr2")));
+        // without the temperature of the light frame, all are unknown.
+        let unknown = darks(&[("2024-01-01.cr2", Some(10.0)), ("2024-01-02.cr2", Some(30.0))]);
+        assert_eq!(rank(unknown, None, 3.0), Some(PathBuf::from("2024-01-02.cr2")));
+        assert_eq!(rank(vec![], Some(10.0), 3.0), None);
+    }
+}
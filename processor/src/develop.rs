@@ -0,0 +1,91 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use log::{debug, error};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ToolError {
+    #[error("unable to run {0}. {1}")]
+    Spawn(String, io::Error),
+
+    #[error("{0} exited with {1}. {2}")]
+    Failed(String, std::process::ExitStatus, String),
+
+    #[error("{0} did not produce {1:?}.")]
+    Missing(String, PathBuf),
+}
+
+/// Files written while processing. Removed when dropped.
+#[derive(Default)]
+pub struct Intermediates(Vec<PathBuf>);
+
+impl Intermediates {
+    pub fn add(&mut self, path: PathBuf) {
+        self.0.push(path);
+    }
+}
+
+impl Drop for Intermediates {
+    fn drop(&mut self) {
+        for f in &self.0 {
+            if let Err(e) = std::fs::remove_file(f) {
+                if e.kind() != io::ErrorKind::NotFound { error!("cannot delete {f:?}. {e}"); }
+            }
+        }
+    }
+}
+
+/// Develops `raws` into 16 bit tiffs in `tmp`. `profiles` holds a partial profile applied on top for each raw.
+pub fn tiffs(raws: &[PathBuf], profiles: &[Option<PathBuf>], is_night: bool, tmp: &Path, intermediates: &mut Intermediates) -> Result<Vec<PathBuf>, ToolError> {
+    let mut tiffs = vec![];
+    for (raw, profile) in raws.iter().zip(profiles) {
+        let tiff = tmp.join(raw.file_stem().unwrap_or_default()).with_extension("tif");
+        intermediates.0.push(tiff.clone());
+
+        let mut args = vec!["-Y", "-t", "-b16"];
+        args.push("-p"); args.push(if is_night { "profiles/nighttime.pp3" } else { "profiles/daytime.pp3" });
+        if let Some(profile) = profile { args.push("-p"); args.push(profile.to_str().unwrap()); }
+        args.push("-o"); args.push(tiff.to_str().unwrap());
+        args.push("-c"); args.push(raw.to_str().unwrap());
+        run(Path::new("rawtherapee-cli"), &args)?;
+
+        expect(Path::new("rawtherapee-cli"), &tiff)?;
+        tiffs.push(tiff);
+    }
+    Ok(tiffs)
+}
+
+/// Aligns `tiffs` to the first of them with align_image_stack. Returns the aligned tiffs in the same order.
+pub fn align(program: &Path, tiffs: &[PathBuf], tmp: &Path, intermediates: &mut Intermediates) -> Result<Vec<PathBuf>, ToolError> {
+    let prefix = tmp.join(format!("{}-aligned-", tiffs[0].file_stem().unwrap_or_default().to_string_lossy()));
+    let aligned = (0..tiffs.len()).map(|i| PathBuf::from(format!("{}{i:04}.tif", prefix.display()))).collect::<Vec<_>>();
+    intermediates.0.extend(aligned.iter().cloned());
+
+    let mut args = vec!["-a", prefix.to_str().unwrap()];
+    args.extend(tiffs.iter().map(|t| t.to_str().unwrap()));
+    run(program, &args)?;
+
+    for a in &aligned { expect(program, a)?; }
+    Ok(aligned)
+}
+
+pub fn run(program: &Path, args: &[&str]) -> Result<(), ToolError> {
+    let name = program.display().to_string();
+    debug!("running {name} {args}", args = args.join(" "));
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .map_err(|e| ToolError::Spawn(name.clone(), e))?;
+    if !output.status.success() {
+        return Err(ToolError::Failed(name, output.status, String::from_utf8_lossy(&output.stderr).trim().to_string()));
+    }
+    Ok(())
+}
+
+/// Fails if `program` did not produce `path`.
+pub fn expect(program: &Path, path: &Path) -> Result<(), ToolError> {
+    if path.exists() { Ok(()) }
+    else { Err(ToolError::Missing(program.display().to_string(), path.into())) }
+}
@@ -0,0 +1,130 @@
+use std::borrow::Cow;
+use std::time::Duration;
+
+use futures::{ SinkExt, StreamExt };
+use log::{ error, warn, info, debug };
+use tokio::time::timeout;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+
+use common::capture::{ FileType, valid_id };
+use common::handshake::{ self, Welcome, MAGIC, PROTOCOL_VERSION, MIN_PROTOCOL_VERSION, LEGACY_PROTOCOL_VERSION };
+
+use crate::{ Ws, WebSocketError };
+
+const HELLO_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub enum Handshake {
+    /// peer sent a compatible `Hello` and was welcomed. `settings_keys` are the camera settings it may request.
+    Accepted { protocol_version: u32, settings_keys: Vec<String> },
+    /// peer predates the handshake. carries its first frame, which still has to be processed.
+    Legacy(Vec<u8>),
+}
+
+/// Waits for the `Hello` of a freshly connected capture node and answers it.
+/// Incompatible peers are sent a reason and closed; `Err(WebSocketError::Rejected)` is returned.
+pub async fn handshake(ws: &mut Ws, name: &str) -> Result<Handshake, WebSocketError> {
+    let first = timeout(HELLO_TIMEOUT, async {
+        loop {
+            match ws.next().await {
+                Some(Ok(Message::Binary(b))) => return Ok(Some(b)),
+                Some(Ok(Message::Ping(_) | Message::Pong(_))) => { },
+                Some(Ok(Message::Text(_) | Message::Frame(_))) => return Ok(None),
+                Some(Ok(Message::Close(c))) => {
+                    debug!("{name} closed before sending hello. {c:?}");
+                    return Err(WebSocketError::Rejected(format!("{name} closed before sending hello.")))
+                },
+                Some(Err(e)) => return Err(WebSocketError::Read(e)),
+                None => return Err(WebSocketError::Rejected(format!("{name} disconnected before sending hello."))),
+            }
+        }
+    }).await;
+
+    let b = match first {
+        Ok(Ok(Some(b))) => b,
+        Ok(Ok(None)) => return reject(ws, name, LEGACY_PROTOCOL_VERSION, String::from("expected a binary hello frame.")).await,
+        Ok(Err(e)) => return Err(e),
+        Err(_) => return reject(ws, name, LEGACY_PROTOCOL_VERSION, format!("no hello received within {HELLO_TIMEOUT:?}.")).await,
+    };
+
+    if !b.starts_with(&MAGIC) {
+        if crate::CONFIG.general.accept_legacy {
+            warn!("{name} did not send a hello. falling back to legacy protocol version {LEGACY_PROTOCOL_VERSION}. consider updating the node.");
+            return Ok(Handshake::Legacy(b));
+        } else {
+            return reject(ws, name, LEGACY_PROTOCOL_VERSION, String::from("legacy peers without hello are not accepted. (general.accept_legacy)")).await;
+        }
+    }
+
+    let hello = match handshake::decode_hello(&b) {
+        Ok(h) => h,
+        Err(e) => {
+            // fields are only ever appended, so the version can be read even if the rest cannot.
+            let version = bincode::deserialize::<([u8; 4], u32)>(&b).map(|(_, v)| v).unwrap_or(LEGACY_PROTOCOL_VERSION);
+            return reject(ws, name, version, format!("cannot deserialize hello. {e}")).await;
+        },
+    };
+    debug!("received {hello:?}");
+
+    if hello.name != name {
+        warn!("{name} introduced itself as {hello_name}.", hello_name = hello.name);
+    }
+
+    let protocol_version = match handshake::negotiate(hello.protocol_version) {
+        Some(v) => v,
+        None => return reject(ws, name, hello.protocol_version,
+            format!("protocol version {v} is not supported. processor supports {MIN_PROTOCOL_VERSION} to {PROTOCOL_VERSION}.", v = hello.protocol_version)).await,
+    };
+
+    let unknown: Vec<&String> = hello.capabilities.file_types.iter()
+        .filter(|t| FileType::from_ext(t).is_none())
+        .collect();
+    if !unknown.is_empty() {
+        warn!("{name} announced unknown file types {unknown:?}. uploads of those will fail.");
+    }
+    if unknown.len() == hello.capabilities.file_types.len() {
+        return reject(ws, name, hello.protocol_version,
+            format!("none of the file types {types:?} are supported.", types = hello.capabilities.file_types)).await;
+    }
+
+    let invalid: Vec<&String> = hello.capabilities.settings_keys.iter().filter(|k| !valid_id(k)).collect();
+    if !invalid.is_empty() {
+        return reject(ws, name, hello.protocol_version, format!("invalid settings keys {invalid:?}.")).await;
+    }
+
+    let welcome = Welcome::Accepted {
+        protocol_version,
+        software_version: handshake::software_version(),
+        file_types: FileType::ALL.iter().map(|t| t.ext()).collect(),
+    };
+    ws.send(Message::Binary(bincode::serialize(&welcome).unwrap())).await
+        .map_err(WebSocketError::Write)?;
+
+    info!("{name} ({module}, tracking={tracking}) running {software_version} connected using protocol version {protocol_version}.",
+        module = hello.capabilities.module,
+        tracking = hello.capabilities.tracking,
+        software_version = hello.software_version,
+    );
+    if protocol_version < PROTOCOL_VERSION {
+        warn!("{name} only supports protocol version {protocol_version}. falling back.");
+    }
+
+    Ok(Handshake::Accepted { protocol_version, settings_keys: hello.capabilities.settings_keys })
+}
+
+async fn reject(ws: &mut Ws, name: &str, peer_version: u32, reason: String) -> Result<Handshake, WebSocketError> {
+    error!("rejecting {name}. {reason}");
+    // peers without a hello cannot read a welcome. they only get the close frame.
+    if peer_version != LEGACY_PROTOCOL_VERSION {
+        let welcome = Welcome::Rejected { reason: reason.clone() };
+        ws.send(Message::Binary(bincode::serialize(&welcome).unwrap())).await
+            .map_err(WebSocketError::Write)?;
+    }
+    ws.send(Message::Close(Some(CloseFrame {
+        code: CloseCode::Policy,
+        reason: Cow::Owned(handshake::close_reason(&reason)),
+    }))).await
+        .map_err(WebSocketError::Write)?;
+    Err(WebSocketError::Rejected(reason))
+}
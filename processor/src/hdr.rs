@@ -0,0 +1,21 @@
+use std::path::{Path, PathBuf};
+
+use crate::CONFIG;
+use crate::develop::{self, Intermediates, ToolError};
+
+/// Develops the raws of an exposure bracket and fuses them into the jpg `output`.
+/// `profiles` holds the dark profile of each raw. Intermediate files are written to `tmp` and removed afterwards.
+pub fn merge(raws: &[PathBuf], profiles: &[Option<PathBuf>], is_night: bool, tmp: &Path, output: &Path) -> Result<(), ToolError> {
+    let mut intermediates = Intermediates::default();
+    let mut tiffs = develop::tiffs(raws, profiles, is_night, tmp, &mut intermediates)?;
+    if CONFIG.hdr.align {
+        tiffs = develop::align(&CONFIG.hdr.align_image_stack, &tiffs, tmp, &mut intermediates)?;
+    }
+
+    let output_arg = format!("--output={}", output.display());
+    let mut args = vec!["--compression=90", output_arg.as_str()];
+    args.extend(tiffs.iter().map(|t| t.to_str().unwrap()));
+    develop::run(&CONFIG.hdr.enfuse, &args)?;
+
+    develop::expect(&CONFIG.hdr.enfuse, output)
+}
@@ -0,0 +1,46 @@
+use std::collections::HashSet;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+
+use log::{ warn, info };
+use uuid::Uuid;
+
+/// Uuids of all uploads that have been stored so far.
+/// Backed by a file with one uuid per line, so duplicates are recognized across restarts.
+pub struct Ledger {
+    path: PathBuf,
+    uuids: HashSet<Uuid>,
+}
+
+impl Ledger {
+    pub fn load(path: PathBuf) -> Ledger {
+        let uuids: HashSet<Uuid> = match fs::read_to_string(&path) {
+            Ok(s) => s.lines()
+                .filter_map(|l| match Uuid::parse_str(l.trim()) {
+                    Ok(u) => Some(u),
+                    Err(e) => { warn!("ignoring invalid line \"{l}\" in ledger. {e}"); None },
+                })
+                .collect(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashSet::new(),
+            Err(e) => { warn!("unable to read ledger {path:?}. starting empty. {e}"); HashSet::new() },
+        };
+        info!("ledger {path:?} knows {len} uploads.", len = uuids.len());
+        Ledger { path, uuids }
+    }
+
+    pub fn contains(&self, uuid: &Uuid) -> bool {
+        self.uuids.contains(uuid)
+    }
+
+    pub fn insert(&mut self, uuid: Uuid) -> std::io::Result<()> {
+        if self.uuids.insert(uuid) {
+            if let Some(parent) = self.path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let mut f = OpenOptions::new().create(true).append(true).open(&self.path)?;
+            writeln!(f, "{}", uuid.as_hyphenated())?;
+        }
+        Ok(())
+    }
+}
@@ -1,22 +1,39 @@
 mod config;
 mod logging;
-
-use std::borrow::Cow;
+mod handshake;
+mod ledger;
+mod transfer;
+mod tls;
+mod auth;
+mod settings;
+mod registry;
+mod admin;
+mod develop;
+mod hdr;
+mod stack;
+mod calibration;
+mod darks;
+
+use std::collections::HashMap;
 use std::net::SocketAddr;
-use std::path::PathBuf;
-use std::time::Duration;
+use std::path::{Path, PathBuf};
+use std::sync::{ Arc, Mutex };
 
-use common::capture::settings::dntime::{DNTime, Frame, Exposure, Aperture, Iso};
-use futures::future::pending;
 use regex::Regex;
 use size_format::SizeFormatterBinary;
 use thiserror::Error;
 
-use common::capture::{Message as CMsg, CaptureResult};
+use common::capture::{Message as CMsg, CaptureResult, FileType, calibration::FrameKind, valid_id};
+use common::handshake::{LEGACY_PROTOCOL_VERSION, ACK_PROTOCOL_VERSION, PHASES_PROTOCOL_VERSION, BRACKET_PROTOCOL_VERSION, STACK_PROTOCOL_VERSION, CALIBRATION_PROTOCOL_VERSION, PREVIEW_PROTOCOL_VERSION};
 use common::processor::{Message as PMsg, CancelBehaviour};
-use common::{ self, capture::settings::Settings };
+use common::capture::settings::{Settings, dntime::DNTime, stack::Method};
 
 use config::Config;
+use handshake::Handshake;
+use ledger::Ledger;
+use registry::{Registry, Push};
+use transfer::{Incoming, Progress};
+use tls::Stream;
 
 use futures::{ SinkExt, StreamExt };
 use log::{ error, warn, info, debug };
@@ -24,7 +41,10 @@ use log::{ error, warn, info, debug };
 use lazy_static::lazy_static;
 use tokio::fs;
 use tokio::net::{ TcpListener, TcpStream };
-use tokio::time::{Instant, sleep};
+use tokio::sync::Semaphore;
+use tokio::time::Instant;
+use tokio_rustls::TlsAcceptor;
+use tokio_tungstenite::WebSocketStream;
 
 use uuid::Uuid;
 use chrono::{ DateTime, Local };
@@ -32,6 +52,10 @@ use chrono::{ DateTime, Local };
 
 lazy_static!{
     static ref CONFIG: Config = Config::new();
+    static ref LEDGER: Mutex<Ledger> = Mutex::new(Ledger::load(CONFIG.general.ledger.clone()));
+    static ref REGISTRY: Registry = Registry::new();
+    /// permits for running post processings, see `general.queue`.
+    static ref POSTPROCESSING: Arc<Semaphore> = Arc::new(Semaphore::new(CONFIG.general.queue));
 }
 
 #[tokio::main]
@@ -42,19 +66,32 @@ async fn main() {
     warn!("warn");
     info!("info");
     debug!("debug");
-    
+
+    lazy_static::initialize(&LEDGER);
+    settings::check();
+
+    if CONFIG.admin.enable {
+        tokio::spawn(admin::serve());
+    }
+
+    let acceptor = tls::acceptor();
     let listener = TcpListener::bind(&CONFIG.general.socket.as_str()).await.unwrap();
 
     loop {
         if let Ok((stream, addr)) = listener.accept().await {
             debug!("received new connection from {addr}");
-            tokio::spawn(accept_connection(stream, addr));
+            tokio::spawn(accept_connection(stream, addr, acceptor.clone()));
         }
     }
 }
 
+type Ws = WebSocketStream<Box<dyn Stream>>;
+
 #[derive(Error, Debug)]
 enum WebSocketError {
+    #[error("tls handshake failed: {0}")]
+    Tls(std::io::Error),
+
     #[error("handshake failed: {0}")]
     Handshake(tokio_tungstenite::tungstenite::Error),
 
@@ -67,26 +104,39 @@ enum WebSocketError {
     #[error("parse failed")]
     Parse(bincode::ErrorKind),
 
+    #[error("peer rejected. {0}")]
+    Rejected(String),
 }
 
-async fn accept_connection(stream: TcpStream, addr: SocketAddr) {
-    if let Err(e) = handle_connection(stream, addr).await {
+async fn accept_connection(stream: TcpStream, addr: SocketAddr, acceptor: Option<TlsAcceptor>) {
+    if let Err(e) = handle_connection(stream, addr, acceptor).await {
         match e {
+            WebSocketError::Tls(e) => warn!("tls handshake with {addr} failed. {e}"),
             WebSocketError::Handshake(e) => warn!("handshake failed. {e}"),
             WebSocketError::Read(e) => warn!("read error. {e}"),
             WebSocketError::Write(e) => warn!("write error. {e}"),
             WebSocketError::Parse(e) => warn!("parse failed {e}"),
+            WebSocketError::Rejected(e) => warn!("peer rejected. {e}"),
         }
     } else {
         debug!("done.");
     }
 }
 
-async fn handle_connection(stream: TcpStream, _addr: SocketAddr) -> Result<(), WebSocketError> {
+async fn handle_connection(stream: TcpStream, addr: SocketAddr, acceptor: Option<TlsAcceptor>) -> Result<(), WebSocketError> {
+    let (stream, certificate): (Box<dyn Stream>, _) = match acceptor {
+        Some(acceptor) => {
+            let stream = acceptor.accept(stream).await.map_err(WebSocketError::Tls)?;
+            let certificate = stream.get_ref().1.peer_certificates().and_then(|c| c.first()).cloned();
+            (Box::new(stream), certificate)
+        },
+        None => (Box::new(stream), None),
+    };
 
-    let mut name = format!("unknown");
+    let mut name = String::from("unknown");
     let mut coordinates = None;
 
+    #[allow(clippy::result_large_err)]
     let callback = |req: &tokio_tungstenite::tungstenite::handshake::server::Request, response: tokio_tungstenite::tungstenite::handshake::server::Response| {
         debug!("query={query:?}", query = req.uri().query());
         let query = req.uri().query().unwrap_or("");
@@ -101,12 +151,20 @@ async fn handle_connection(stream: TcpStream, _addr: SocketAddr) -> Result<(), W
             
             if let (Some(lat), Some(lon))= (latitude_regex.captures(query), longitude_regex.captures(query)) {
                 if let (Ok(lat), Ok(lon)) = (lat.get(1).unwrap().as_str().parse::<f64>(), lon.get(1).unwrap().as_str().parse::<f64>()) {
-                    if lat.is_finite() && lat >= -90.0 && lat <= 90.0 && lon.is_finite() && lon >= -180.0 && lon <= 180.0 {
+                    if lat.is_finite() && (-90.0..=90.0).contains(&lat) && lon.is_finite() && (-180.0..=180.0).contains(&lon) {
                         coordinates = Some((lat, lon));
                         debug!("(lat, lon)=({lat}, {lon})");
                     }
                 }
             }
+            if let Err(e) = auth::authorize(&name, req) {
+                warn!("refusing connection. {e}");
+                return Err(tokio_tungstenite::tungstenite::http::Response::builder().status(401).body(None).unwrap());
+            }
+            if let Some(Err(e)) = certificate.as_ref().map(|c| tls::authorize(c, &name)) {
+                warn!("refusing connection. {e}");
+                return Err(tokio_tungstenite::tungstenite::http::Response::builder().status(401).body(None).unwrap());
+            }
             Ok(response)
         } else {
             Err(tokio_tungstenite::tungstenite::http::Response::builder().status(401).body(None).unwrap())
@@ -115,68 +173,34 @@ async fn handle_connection(stream: TcpStream, _addr: SocketAddr) -> Result<(), W
 
     let mut ws = tokio_tungstenite::accept_hdr_async(stream, callback)
         .await
-        .map_err(|e| { WebSocketError::Handshake(e) })?;
-
-
-    let mut interval = tokio::time::interval(Duration::from_millis(2000));
-
-    let timeout = sleep(Duration::from_millis(120000000));
-    tokio::pin!(timeout);
+        .map_err(WebSocketError::Handshake)?;
 
-    let mut open = true;
+    let (protocol_version, settings_keys, first) = match handshake::handshake(&mut ws, &name).await? {
+        Handshake::Accepted { protocol_version, settings_keys } => (protocol_version, settings_keys, None),
+        Handshake::Legacy(b) => (LEGACY_PROTOCOL_VERSION, vec![], Some(b)),
+    };
 
-    let mut once = true;
+    let mut peer = Peer { name, protocol_version, open: true, incoming: HashMap::new(), settings_keys: settings_keys.clone() };
 
-    let settings = Settings {
-        horizon: -0.67,
-        daytime: DNTime { frame: Frame::Some(20.0), exposure: Exposure::Auto, iso: Iso::Manual(100), aperture: Aperture::Auto },
-        nighttime:  DNTime { frame: Frame::None, exposure: Exposure::Manual(60.0 * 3.0), iso: Iso::Manual(800), aperture: Aperture::Manual(3.5) }
-    };
+    debug!("{name} connected using protocol version {protocol_version}", name = peer.name);
+    let mut registration = REGISTRY.register(&peer.name, addr, protocol_version, settings_keys);
 
-    debug!("{name} connected");
+    if let Some(b) = first {
+        handle_binary(&mut ws, &mut peer, b).await?;
+    }
 
     loop {
         tokio::select! {
             next = ws.next() => {
                 match next {
                     Some(item) => {
-                        match item.map_err(|e| { WebSocketError::Read(e) })? {
+                        match item.map_err(WebSocketError::Read)? {
                             tokio_tungstenite::tungstenite::Message::Text(_) => { },
-                            tokio_tungstenite::tungstenite::Message::Binary(b) => {
-                                let total = b.len();
-                                let msg: CMsg = bincode::deserialize(&b)
-                                    .map_err(|e| { WebSocketError::Parse(*e) })?;
-                                match msg {
-                                    CMsg::RequestSettings => {                
-                                        if open {
-                                            let settings = settings.clone();
-                                            ws.send(tokio_tungstenite::tungstenite::Message::Binary(bincode::serialize(
-                                                &PMsg::SetSettings{ settings, cancel_behaviour: CancelBehaviour::Allways }
-                                            ).unwrap()))
-                                                .await
-                                                .map_err(|e| { WebSocketError::Write(e) })?;
-                                        }
-                                    },
-                                    CMsg::Upload(b) => {
-                                        debug!("received Upload {b:?} total={total}B", total = SizeFormatterBinary::new(total as u64));
-                                        let CaptureResult { uuid, time, is_night, file_type, file } = b;
-
-                                        let filename = format!("{uuid}.{ext}", uuid = uuid.as_hyphenated().to_string(), ext = file_type.ext());
-                                        let filepath = PathBuf::from(&CONFIG.general.tmp_path)
-                                            .join(&filename);
-                                        if let Err(e) = fs::write(&filepath, &file).await {
-                                            error!("unable to write file. {e}");
-                                        }
-                                        if let Err(e) = scuffed_postprocesssing(filepath, uuid, time, is_night) {
-                                            error!("Error processing image {e:?}")
-                                        }
-                                    }
-                                }
-                            },
+                            tokio_tungstenite::tungstenite::Message::Binary(b) => handle_binary(&mut ws, &mut peer, b).await?,
                             tokio_tungstenite::tungstenite::Message::Ping(_) | tokio_tungstenite::tungstenite::Message::Pong(_) => { },
                             tokio_tungstenite::tungstenite::Message::Close(c) => {
                                 debug!("received close frame {c:?}");
-                                open = false;
+                                peer.open = false;
                             },
                             tokio_tungstenite::tungstenite::Message::Frame(_) => unreachable!(),
                         }
@@ -184,58 +208,371 @@ async fn handle_connection(stream: TcpStream, _addr: SocketAddr) -> Result<(), W
                     None => { return Ok(()); },
                 }
             },
-            // _ = interval.tick() => {
-            //     let settings = settings.clone();
-
-            //     if open && once {
-            //         once = false;
-            //         debug!("sending settings unsolicited.");
-            //         ws.send(tokio_tungstenite::tungstenite::Message::Binary(bincode::serialize(
-            //             &PMsg::SetSettings{ settings, cancel_behaviour: CancelBehaviour::Allways }
-            //         ).unwrap()))
-            //             .await
-            //             .map_err(|e| { WebSocketError::Write(e) })?;
-            //     }
-            // },
-            // _ = async { if open { timeout.as_mut().await } else { pending().await } } => {
-            //     open = false;
-            //     ws.send(tokio_tungstenite::tungstenite::Message::Close(Some(tokio_tungstenite::tungstenite::protocol::CloseFrame {
-            //         code: tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode::Normal ,
-            //         reason: Cow::Owned(format!("bye!")) 
-            //     }))).await.unwrap();
-            // },
+            Some(Push { msg, delivered }) = registration.rx.recv() => {
+                if !peer.open {
+                    let _ = delivered.send(Err(String::from("connection is closing.")));
+                    continue;
+                }
+                let sent = match msg {
+                    PMsg::SetSettings { settings, cancel_behaviour } => {
+                        debug!("pushing settings to {name} with {cancel_behaviour:?}.", name = peer.name);
+                        send_settings(&mut ws, &peer, settings, cancel_behaviour).await
+                    },
+                    PMsg::Calibrate(_) if peer.protocol_version < CALIBRATION_PROTOCOL_VERSION => {
+                        let _ = delivered.send(Err(format!("protocol version {v} does not support calibration frames.", v = peer.protocol_version)));
+                        continue;
+                    },
+                    PMsg::RequestRaw { .. } if peer.protocol_version < PREVIEW_PROTOCOL_VERSION => {
+                        let _ = delivered.send(Err(format!("protocol version {v} does not hold back raws.", v = peer.protocol_version)));
+                        continue;
+                    },
+                    msg => {
+                        debug!("pushing {msg:?} to {name}.", name = peer.name);
+                        ws.send(tokio_tungstenite::tungstenite::Message::Binary(common::processor::encode(&msg, peer.protocol_version).unwrap()))
+                            .await
+                            .map_err(WebSocketError::Write)
+                    },
+                };
+                match sent {
+                    Ok(()) => { let _ = delivered.send(Ok(())); },
+                    Err(e) => {
+                        let _ = delivered.send(Err(e.to_string()));
+                        return Err(e);
+                    },
+                }
+            },
         }         
     }
 }
 
-fn scuffed_postprocesssing(filepath: PathBuf, uuid: Uuid, time: DateTime<Local>, is_night: bool) -> Result<(), Box<dyn std::error::Error>> {
+/// state of one connected capture node.
+struct Peer {
+    name: String,
+    protocol_version: u32,
+    open: bool,
+    /// chunked uploads announced on this connection.
+    incoming: HashMap<Uuid, Incoming>,
+    /// keys of the camera settings the node announced. it may only request those.
+    settings_keys: Vec<String>,
+}
+
+async fn handle_binary(ws: &mut Ws, peer: &mut Peer, b: Vec<u8>) -> Result<(), WebSocketError> {
+    let total = b.len();
+    let msg = common::capture::decode(&b, peer.protocol_version)
+        .map_err(|e| { WebSocketError::Parse(*e) })?;
+    match msg {
+        CMsg::RequestSettings => {
+            let settings = match settings::load(&peer.name) {
+                Ok(s) => s,
+                Err(e) => {
+                    error!("unable to answer settings request. invalid settings for {name}. {e}", name = peer.name);
+                    return Ok(());
+                },
+            };
+            if peer.open {
+                send_settings(ws, peer, settings, CancelBehaviour::Allways).await?;
+            }
+        },
+        CMsg::RequestSettingsOf { key } => {
+            if !peer.settings_keys.contains(&key) {
+                warn!("{name} requested camera settings {key:?}, which it did not announce.", name = peer.name);
+                return Ok(());
+            }
+            let settings = match settings::load_camera(&key) {
+                Ok(s) => s,
+                Err(e) => {
+                    error!("unable to answer settings request of {name}. invalid settings for {key}. {e}", name = peer.name);
+                    return Ok(());
+                },
+            };
+            if peer.open {
+                let b = common::processor::encode(&PMsg::SetSettingsOf { key, settings, cancel_behaviour: CancelBehaviour::Allways }, peer.protocol_version).unwrap();
+                ws.send(tokio_tungstenite::tungstenite::Message::Binary(b))
+                    .await
+                    .map_err(WebSocketError::Write)?;
+            }
+        },
+        CMsg::Upload(b) => {
+            debug!("received Upload {b:?} total={total}B", total = SizeFormatterBinary::new(total as u64));
+            store(ws, peer, b).await?;
+        },
+        CMsg::UploadBegin { uuid, size, checksum } => {
+            debug!("{name} announced {uuid} of {size}B.", name = peer.name, size = SizeFormatterBinary::new(size));
+            if LEDGER.lock().unwrap().contains(&uuid) {
+                info!("{uuid} was already stored. ignoring duplicate.");
+                return acknowledge(ws, peer.protocol_version, PMsg::Ack { uuid }).await;
+            }
+            if size > CONFIG.general.max_upload {
+                warn!("{name} announced {uuid} of {size}B, which exceeds general.max_upload.", name = peer.name, size = SizeFormatterBinary::new(size));
+                return acknowledge(ws, peer.protocol_version, PMsg::Nack { uuid, reason: String::from("upload exceeds general.max_upload."), retry: false }).await;
+            }
+            match Incoming::begin(uuid, size, checksum, peer.protocol_version).await {
+                Ok((incoming, progress)) => {
+                    peer.incoming.insert(uuid, incoming);
+                    handle_progress(ws, peer, uuid, progress).await?;
+                },
+                Err(e) => {
+                    error!("unable to prepare upload {uuid}. {e}");
+                    acknowledge(ws, peer.protocol_version, PMsg::Nack { uuid, reason: format!("unable to prepare upload. {e}"), retry: true }).await?;
+                },
+            }
+        },
+        CMsg::UploadChunk { uuid, offset, data } => {
+            let progress = match peer.incoming.get(&uuid) {
+                Some(incoming) => incoming.chunk(offset, &data).await,
+                None => {
+                    warn!("received chunk of {uuid} without UploadBegin.");
+                    return acknowledge(ws, peer.protocol_version, PMsg::Nack { uuid, reason: String::from("upload was not announced."), retry: true }).await;
+                },
+            };
+            match progress {
+                Ok(Some(progress)) => handle_progress(ws, peer, uuid, progress).await?,
+                Ok(None) => { },
+                Err(e) => {
+                    error!("unable to write chunk of {uuid}. {e}");
+                    peer.incoming.remove(&uuid);
+                    acknowledge(ws, peer.protocol_version, PMsg::Nack { uuid, reason: format!("unable to write chunk. {e}"), retry: true }).await?;
+                },
+            }
+        },
+    }
+    Ok(())
+}
+
+async fn handle_progress(ws: &mut Ws, peer: &mut Peer, uuid: Uuid, progress: Progress) -> Result<(), WebSocketError> {
+    match progress {
+        Progress::Resume(offset) => acknowledge(ws, peer.protocol_version, PMsg::Resume { uuid, offset }).await,
+        Progress::Complete(c) => {
+            peer.incoming.remove(&uuid);
+            debug!("received all chunks of {c:?}");
+            store(ws, peer, *c).await
+        },
+        Progress::Corrupt { reason, retry } => {
+            peer.incoming.remove(&uuid);
+            error!("upload {uuid} from {name} is corrupt. {reason}", name = peer.name);
+            acknowledge(ws, peer.protocol_version, PMsg::Nack { uuid, reason, retry }).await
+        },
+    }
+}
+
+/// Answer to an upload that is not to be stored, because it already was or it is invalid.
+fn screen(ledger: &Ledger, name: &str, uuid: Uuid, camera: Option<&str>) -> Option<PMsg> {
+    if ledger.contains(&uuid) {
+        info!("{uuid} was already stored. ignoring duplicate.");
+        return Some(PMsg::Ack { uuid });
+    }
+    if let Some(camera) = camera.filter(|c| !valid_id(c)) {
+        warn!("{name} sent {uuid} of the invalid camera {camera:?}.");
+        return Some(PMsg::Nack { uuid, reason: format!("invalid camera id {camera:?}."), retry: false });
+    }
+    None
+}
+
+/// Writes a complete upload, acknowledges it and runs the post processing.
+async fn store(ws: &mut Ws, peer: &Peer, c: CaptureResult) -> Result<(), WebSocketError> {
+    let CaptureResult { uuid, time, is_night, file_type, file, settings, bracket, subframes, kind, raw, camera } = c;
+
+    let answer = screen(&LEDGER.lock().unwrap(), &peer.name, uuid, camera.as_deref());
+    if let Some(answer) = answer {
+        return acknowledge(ws, peer.protocol_version, answer).await;
+    }
+    // the calibration library and darks are kept per camera.
+    let source = match &camera {
+        Some(camera) => format!("{name}.{camera}", name = peer.name),
+        None => peer.name.clone(),
+    };
+
+    let filename = format!("{uuid}.{ext}", uuid = uuid.as_hyphenated(), ext = file_type.ext());
+    let filepath = PathBuf::from(&CONFIG.general.tmp_path)
+        .join(&filename);
+    if let Err(e) = fs::write(&filepath, &file).await {
+        error!("unable to write file. {e}");
+        return acknowledge(ws, peer.protocol_version, PMsg::Nack { uuid, reason: format!("unable to write file. {e}"), retry: true }).await;
+    }
+    let mut bracket_filepaths = vec![];
+    for (i, b) in bracket.iter().enumerate() {
+        let filepath = PathBuf::from(&CONFIG.general.tmp_path)
+            .join(format!("{uuid}-{n}.{ext}", uuid = uuid.as_hyphenated(), n = i + 1, ext = file_type.ext()));
+        if let Err(e) = fs::write(&filepath, &b.file).await {
+            error!("unable to write bracket file. {e}");
+            return acknowledge(ws, peer.protocol_version, PMsg::Nack { uuid, reason: format!("unable to write bracket file. {e}"), retry: true }).await;
+        }
+        bracket_filepaths.push((b.offset, filepath, b.settings.clone()));
+    }
+    let mut subframe_filepaths = vec![];
+    for (i, s) in subframes.iter().enumerate() {
+        let filepath = PathBuf::from(&CONFIG.general.tmp_path)
+            .join(format!("{uuid}-s{n}.{ext}", uuid = uuid.as_hyphenated(), n = i + 2, ext = file_type.ext()));
+        if let Err(e) = fs::write(&filepath, s).await {
+            error!("unable to write subframe file. {e}");
+            return acknowledge(ws, peer.protocol_version, PMsg::Nack { uuid, reason: format!("unable to write subframe file. {e}"), retry: true }).await;
+        }
+        subframe_filepaths.push(filepath);
+    }
+    if let Err(e) = LEDGER.lock().unwrap().insert(uuid) {
+        error!("unable to record {uuid} in ledger. {e}");
+    }
+    acknowledge(ws, peer.protocol_version, PMsg::Ack { uuid }).await?;
+
+    // post processing runs on the blocking pool, at most general.queue at once. further uploads wait here.
+    let permit = POSTPROCESSING.clone().acquire_owned().await.unwrap();
+    tokio::task::spawn_blocking(move || {
+        let _permit = permit;
+        if let Some(raw) = raw {
+            publish_preview(&source, camera.as_deref(), &filepath, uuid, raw, time);
+            return;
+        }
+
+        if kind != FrameKind::Light {
+            if let Err(e) = calibration::file(&source, kind, settings.as_ref(), time, file_type, &filepath) {
+                error!("unable to file {kind} frame {uuid} into the calibration library. {e}", kind = kind.name());
+            }
+            return;
+        }
+
+        let method = settings.as_ref().and_then(|s| s.stack).map_or(Method::Mean, |s| s.method);
+        match &settings {
+            Some(s) => info!("{uuid} was captured with exposure={exposure:?} iso={iso:?} aperture={aperture:?}.", exposure = s.exposure, iso = s.iso, aperture = s.aperture),
+            None => debug!("{uuid} does not record the settings it was captured with."),
+        }
+        for b in &bracket {
+            if let Some(s) = &b.settings {
+                debug!("{uuid} bracket frame at {offset:+} EV was captured with exposure={exposure:?}.", offset = b.offset, exposure = s.exposure);
+            }
+        }
+
+        if let Err(e) = scuffed_postprocesssing(&source, camera.as_deref(), settings.as_ref(), file_type, filepath, bracket_filepaths, subframe_filepaths, method, uuid, time, is_night) {
+            error!("Error processing image {e:?}")
+        }
+    });
+    Ok(())
+}
+
+/// Directory below `root` the frames of `camera` are filed in, `root` itself for nodes with a single camera.
+fn filing_directory(root: &str, camera: Option<&str>) -> PathBuf {
+    let dir = PathBuf::from(root);
+    let Some(camera) = camera else { return dir };
+    let dir = dir.join(camera);
+    if let Err(e) = std::fs::create_dir_all(&dir) {
+        error!("unable to create {dir:?}. {e}");
+    }
+    dir
+}
+
+/// Publishes the camera jpg at `filepath` where the development of the raw `raw` will be written.
+/// A jpg that is already there was developed from the raw and is kept.
+fn publish_preview(node: &str, camera: Option<&str>, filepath: &Path, uuid: Uuid, raw: Uuid, time: DateTime<Local>) {
+    let jpg_filepath = filing_directory("images", camera)
+        .join(format!("{ts}.jpg", ts = time.format("%Y-%m-%d %H:%M:%S")));
+    if jpg_filepath.exists() {
+        debug!("{raw} was developed before its preview {uuid} arrived. discarding the preview.");
+    } else if let Err(e) = std::fs::copy(filepath, &jpg_filepath) {
+        error!("cannot publish preview {uuid}. {e}");
+    } else {
+        info!("published camera jpg {uuid} of {node} as {jpg_filepath:?} until {raw} is developed.");
+    }
+    if let Err(e) = std::fs::remove_file(filepath) {
+        error!("cannot delete preview. {e}");
+    }
+}
+
+/// Sends settings in the format of the peer's protocol version.
+async fn send_settings(ws: &mut Ws, peer: &Peer, settings: Settings, cancel_behaviour: CancelBehaviour) -> Result<(), WebSocketError> {
+    if peer.protocol_version < PHASES_PROTOCOL_VERSION && !settings.phases.is_empty() {
+        warn!("{name} does not support phases. it only receives daytime and nighttime.", name = peer.name);
+    }
+    if peer.protocol_version < BRACKET_PROTOCOL_VERSION && settings.has_bracket() {
+        warn!("{name} does not support exposure brackets. it only captures the base exposure.", name = peer.name);
+    }
+    if peer.protocol_version < STACK_PROTOCOL_VERSION && settings.has_stack() {
+        warn!("{name} does not support subframe stacks. it captures single frames.", name = peer.name);
+    }
+    let b = common::processor::encode(&PMsg::SetSettings { settings, cancel_behaviour }, peer.protocol_version).unwrap();
+    ws.send(tokio_tungstenite::tungstenite::Message::Binary(b))
+        .await
+        .map_err(WebSocketError::Write)
+}
+
+/// Answers an upload. Peers older than `ACK_PROTOCOL_VERSION` do not understand acks and get nothing.
+async fn acknowledge(ws: &mut Ws, protocol_version: u32, msg: PMsg) -> Result<(), WebSocketError> {
+    if protocol_version >= ACK_PROTOCOL_VERSION {
+        debug!("sending {msg:?}");
+        ws.send(tokio_tungstenite::tungstenite::Message::Binary(bincode::serialize(&msg).unwrap()))
+            .await
+            .map_err(WebSocketError::Write)?;
+    }
+    Ok(())
+}
+
+/// `bracket` holds the offset in EV, the raw and the settings of the further frames of an exposure bracket.
+/// `subframes` holds the raws of the further subframes, stacked with the base frame using `method`.
+/// Matching darks of `node` are subtracted from every raw before development. Frames of a `camera` are filed below its id.
+#[allow(clippy::too_many_arguments)]
+fn scuffed_postprocesssing(node: &str, camera: Option<&str>, settings: Option<&DNTime>, file_type: FileType, filepath: PathBuf, bracket: Vec<(f64, PathBuf, Option<DNTime>)>, subframes: Vec<PathBuf>, method: Method, uuid: Uuid, time: DateTime<Local>, is_night: bool) -> Result<(), Box<dyn std::error::Error>> {
     let start = Instant::now();
+    let tmp = PathBuf::from(&CONFIG.general.tmp_path);
+    let mut intermediates = develop::Intermediates::default();
     
     let raw_filepath = filepath;
-    let new_raw_filepath = {
-        let mut buf = PathBuf::new();
-        buf.push(format!("images-raws"));
-        buf.push(format!("{ts}.cr2", ts = time.format("%Y-%m-%d %H:%M:%S")));
-        buf
-    };
-    let jpg_filepath = {
-        let mut buf = PathBuf::new();
-        buf.push(format!("images"));
-        buf.push(format!("{ts}.jpg", ts = time.format("%Y-%m-%d %H:%M:%S")));
-        buf
+    let raws_directory = filing_directory("images-raws", camera);
+    let new_raw_filepath = raws_directory.join(format!("{ts}.{ext}", ts = time.format("%Y-%m-%d %H:%M:%S"), ext = file_type.ext()));
+    let jpg_filepath = filing_directory("images", camera)
+        .join(format!("{ts}.jpg", ts = time.format("%Y-%m-%d %H:%M:%S")));
+
+    let developed = if !subframes.is_empty() {
+        if !bracket.is_empty() { warn!("{uuid} is stacked. its bracket is not merged."); }
+        let raws = std::iter::once(raw_filepath.clone()).chain(subframes.iter().cloned()).collect::<Vec<_>>();
+        let profiles = raws.iter().map(|r| darks::profile(node, settings, r, &tmp, &mut intermediates)).collect::<Vec<_>>();
+        match stack::stack(&raws, &profiles, method, is_night, &tmp, &jpg_filepath) {
+            Ok(()) => {
+                info!("stacked {n} subframes of {uuid}. method={method:?}.", n = raws.len());
+                true
+            },
+            Err(e) => {
+                error!("unable to stack subframes of {uuid}. processing the first subframe only. {e}");
+                false
+            },
+        }
+    } else if bracket.is_empty() || !CONFIG.hdr.merge {
+        false
+    } else {
+        let frames = std::iter::once((raw_filepath.clone(), settings)).chain(bracket.iter().map(|(_, f, s)| (f.clone(), s.as_ref())));
+        let (raws, profiles): (Vec<_>, Vec<_>) = frames.map(|(r, s)| {
+            let profile = darks::profile(node, s, &r, &tmp, &mut intermediates);
+            (r, profile)
+        }).unzip();
+        match hdr::merge(&raws, &profiles, is_night, &tmp, &jpg_filepath) {
+            Ok(()) => {
+                info!("merged bracket of {n} frames of {uuid}.", n = raws.len());
+                true
+            },
+            Err(e) => {
+                error!("unable to merge bracket of {uuid}. processing the base exposure only. {e}");
+                false
+            },
+        }
     };
 
-    let mut cmd = vec!["-Y"];
-    cmd.push("-j90");
-
-    cmd.push("-p"); cmd.push(if is_night { "profiles/nighttime.pp3" } else { "profiles/daytime.pp3" } );
-    cmd.push("-o"); cmd.push(jpg_filepath.to_str().unwrap());
-    cmd.push("-c"); cmd.push(raw_filepath.to_str().unwrap());
-
-    if let Err(e) = std::process::Command::new("rawtherapee-cli")
-        .args(&cmd)
-        .output() {
-        return Err(Box::new(e));
+    if !developed && file_type == FileType::Jpeg {
+        // developed by the camera already.
+        if let Err(e) = std::fs::copy(&raw_filepath, &jpg_filepath) {
+            error!("cannot copy jpg of {uuid}. {e}");
+        }
+    } else if !developed {
+        let mut cmd = vec!["-Y"];
+        cmd.push("-j90");
+
+        cmd.push("-p"); cmd.push(if is_night { "profiles/nighttime.pp3" } else { "profiles/daytime.pp3" } );
+        let profile = darks::profile(node, settings, &raw_filepath, &tmp, &mut intermediates);
+        if let Some(profile) = &profile { cmd.push("-p"); cmd.push(profile.to_str().unwrap()); }
+        cmd.push("-o"); cmd.push(jpg_filepath.to_str().unwrap());
+        cmd.push("-c"); cmd.push(raw_filepath.to_str().unwrap());
+
+        if let Err(e) = std::process::Command::new("rawtherapee-cli")
+            .args(&cmd)
+            .output() {
+            return Err(Box::new(e));
+        }
     }
     
     if !jpg_filepath.exists() {
@@ -250,5 +587,54 @@ fn scuffed_postprocesssing(filepath: PathBuf, uuid: Uuid, time: DateTime<Local>,
         error!("cannot delete raw. {e}");
     }
 
+    for (i, subframe_filepath) in subframes.into_iter().enumerate() {
+        let new_subframe_filepath = raws_directory
+            .join(format!("{ts} #{n}.{ext}", ts = time.format("%Y-%m-%d %H:%M:%S"), n = i + 2, ext = file_type.ext()));
+        if let Err(e) = std::fs::copy(&subframe_filepath, &new_subframe_filepath) {
+            error!("cannot copy subframe raw to new location. {e}");
+        }
+        if let Err(e) = std::fs::remove_file(&subframe_filepath) {
+            error!("cannot delete subframe raw. {e}");
+        }
+    }
+
+    for (offset, bracket_filepath, _) in bracket {
+        let new_bracket_filepath = raws_directory
+            .join(format!("{ts} {offset:+}EV.{ext}", ts = time.format("%Y-%m-%d %H:%M:%S"), ext = file_type.ext()));
+        if let Err(e) = std::fs::copy(&bracket_filepath, &new_bracket_filepath) {
+            error!("cannot copy bracket raw to new location. {e}");
+        }
+        if let Err(e) = std::fs::remove_file(&bracket_filepath) {
+            error!("cannot delete bracket raw. {e}");
+        }
+    }
+
+    debug!("processed {uuid} in {:.1} seconds.", start.elapsed().as_secs_f64());
+
     Ok(())
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn screen_duplicates_and_invalid_cameras() {
+        let path = std::env::temp_dir().join(format!("ledger-{uuid}", uuid = Uuid::new_v4()));
+        let mut ledger = Ledger::load(path.clone());
+        let (stored, new) = (Uuid::new_v4(), Uuid::new_v4());
+        ledger.insert(stored).unwrap();
+
+        assert!(matches!(screen(&ledger, "east", stored, None), Some(PMsg::Ack { uuid }) if uuid == stored));
+        assert!(matches!(screen(&ledger, "east", stored, Some("../west")), Some(PMsg::Ack { uuid }) if uuid == stored));
+        assert!(screen(&ledger, "east", new, None).is_none());
+        assert!(screen(&ledger, "east", new, Some("west")).is_none());
+        assert!(matches!(screen(&ledger, "east", new, Some("../west")), Some(PMsg::Nack { uuid, retry: false, .. }) if uuid == new));
+
+        // duplicates are recognized after a restart, and recorded once.
+        ledger.insert(stored).unwrap();
+        let ledger = Ledger::load(path.clone());
+        assert!(matches!(screen(&ledger, "east", stored, None), Some(PMsg::Ack { .. })));
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 1);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::sync::atomic::{ AtomicU64, Ordering };
+use std::time::Duration;
+
+use log::{ warn, debug };
+use thiserror::Error;
+use tokio::sync::{ mpsc, oneshot };
+use tokio::time::timeout;
+
+use common::processor::Message;
+
+const PUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Live connections of capture nodes by name, so settings and calibration requests can be pushed to them.
+pub struct Registry {
+    peers: Mutex<HashMap<String, Entry>>,
+    next_id: AtomicU64,
+}
+
+struct Entry {
+    id: u64,
+    addr: SocketAddr,
+    protocol_version: u32,
+    /// keys of the camera settings the node announced.
+    settings_keys: Vec<String>,
+    tx: mpsc::Sender<Push>,
+}
+
+/// Message to be sent to a node. `delivered` is answered once it was written to its connection.
+pub struct Push {
+    pub msg: Message,
+    pub delivered: oneshot::Sender<Result<(), String>>,
+}
+
+/// Keeps a connection registered until dropped.
+pub struct Registration {
+    name: String,
+    id: u64,
+    pub rx: mpsc::Receiver<Push>,
+}
+
+pub struct Node {
+    pub name: String,
+    pub addr: SocketAddr,
+    pub protocol_version: u32,
+}
+
+#[derive(Error, Debug)]
+pub enum PushError {
+    #[error("{0} is not connected.")]
+    NotConnected(String),
+
+    #[error("{0} did not take the message within {PUSH_TIMEOUT:?}.")]
+    Timeout(String),
+
+    #[error("sending to {0} failed. {1}")]
+    Failed(String, String),
+}
+
+impl Registry {
+    pub fn new() -> Registry {
+        Registry { peers: Mutex::new(HashMap::new()), next_id: AtomicU64::new(0) }
+    }
+
+    /// Registers a connection. A node connecting again replaces its older connection.
+    pub fn register(&self, name: &str, addr: SocketAddr, protocol_version: u32, settings_keys: Vec<String>) -> Registration {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = mpsc::channel(1);
+        if let Some(old) = self.peers.lock().unwrap().insert(name.into(), Entry { id, addr, protocol_version, settings_keys, tx }) {
+            warn!("{name} connected again from {addr}. the connection from {old} no longer receives settings.", old = old.addr);
+        }
+        Registration { name: name.into(), id, rx }
+    }
+
+    pub fn nodes(&self) -> Vec<Node> {
+        let mut nodes: Vec<Node> = self.peers.lock().unwrap().iter()
+            .map(|(name, e)| Node { name: name.clone(), addr: e.addr, protocol_version: e.protocol_version })
+            .collect();
+        nodes.sort_by(|a, b| a.name.cmp(&b.name));
+        nodes
+    }
+
+    /// Names of the connected nodes that announced the camera settings `key`.
+    pub fn users(&self, key: &str) -> Vec<String> {
+        let mut names: Vec<String> = self.peers.lock().unwrap().iter()
+            .filter(|(_, e)| e.settings_keys.iter().any(|k| k == key))
+            .map(|(name, _)| name.clone())
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Sends `msg` to `name` and waits until it was written to its connection.
+    pub async fn push(&self, name: &str, msg: Message) -> Result<(), PushError> {
+        let tx = match self.peers.lock().unwrap().get(name) {
+            Some(e) => e.tx.clone(),
+            None => return Err(PushError::NotConnected(name.into())),
+        };
+        let (delivered, rx) = oneshot::channel();
+        let result = timeout(PUSH_TIMEOUT, async {
+            tx.send(Push { msg, delivered }).await
+                .map_err(|_| PushError::NotConnected(name.into()))?;
+            rx.await.map_err(|_| PushError::NotConnected(name.into()))?
+                .map_err(|e| PushError::Failed(name.into(), e))
+        }).await;
+        result.unwrap_or_else(|_| Err(PushError::Timeout(name.into())))
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let mut peers = crate::REGISTRY.peers.lock().unwrap();
+        if peers.get(&self.name).is_some_and(|e| e.id == self.id) {
+            peers.remove(&self.name);
+            debug!("{name} unregistered.", name = self.name);
+        }
+    }
+}
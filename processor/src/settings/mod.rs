@@ -0,0 +1,156 @@
+use std::path::{ Path, PathBuf };
+
+use log::{ error, info };
+use thiserror::Error;
+
+use common::capture::settings::Settings;
+
+const BUILTIN: &str = include_str!("default.toml");
+
+#[derive(Error, Debug)]
+pub enum SettingsError {
+    #[error("invalid settings. {0}")]
+    Invalid(config_rs::ConfigError),
+
+    #[error("unable to write {0:?}. {1}")]
+    IO(PathBuf, std::io::Error),
+}
+
+/// Settings of the node `name`, validated with the `deserialize_*` rules of `Settings`.
+/// Layers `general.settings/nodes/<name>.toml` over `general.settings/default.toml` over the built-in defaults,
+/// so a file only has to contain what differs. Files are read on every call, edits apply on the next request.
+pub fn load(name: &str) -> Result<Settings, config_rs::ConfigError> {
+    load_path(&crate::CONFIG.general.settings, node_path(name))
+}
+
+/// Settings of the cameras with the key `key`, see `cameras.settings` of the node.
+/// Layers `general.settings/cameras/<key>.toml` over the same defaults as `load`.
+pub fn load_camera(key: &str) -> Result<Settings, config_rs::ConfigError> {
+    load_path(&crate::CONFIG.general.settings, camera_path(key))
+}
+
+/// Replaces `general.settings/nodes/<name>.toml` with `toml` if the resulting settings are valid.
+pub fn store(name: &str, toml: &str) -> Result<Settings, SettingsError> {
+    store_path(&crate::CONFIG.general.settings, name, node_path(name), toml)
+}
+
+/// Replaces `general.settings/cameras/<key>.toml` with `toml` if the resulting settings are valid.
+pub fn store_camera(key: &str, toml: &str) -> Result<Settings, SettingsError> {
+    store_path(&crate::CONFIG.general.settings, key, camera_path(key), toml)
+}
+
+/// Layers `path` over `dir/default.toml` over the built-in defaults.
+fn load_path(dir: &Path, path: PathBuf) -> Result<Settings, config_rs::ConfigError> {
+    builder(dir)
+        .add_source(config_rs::File::from(path).required(false))
+        .build()?
+        .try_deserialize()
+}
+
+/// Validates `toml` layered like `load_path` and only then replaces `path` with it.
+fn store_path(dir: &Path, name: &str, path: PathBuf, toml: &str) -> Result<Settings, SettingsError> {
+    let settings = builder(dir)
+        .add_source(config_rs::File::from_str(toml, config_rs::FileFormat::Toml))
+        .build()
+        .and_then(|c| c.try_deserialize::<Settings>())
+        .map_err(SettingsError::Invalid)?;
+
+    let tmp_path = path.with_extension("toml.partial");
+    std::fs::create_dir_all(path.parent().unwrap())
+        .and_then(|()| std::fs::write(&tmp_path, toml))
+        .and_then(|()| std::fs::rename(&tmp_path, &path))
+        .map_err(|e| SettingsError::IO(path.clone(), e))?;
+    info!("stored settings of {name} in {path:?}.");
+    Ok(settings)
+}
+
+/// Logs every settings file in `general.settings` that would be refused.
+pub fn check() {
+    if let Err(e) = builder(&crate::CONFIG.general.settings).build().and_then(|c| c.try_deserialize::<Settings>()) {
+        error!("invalid default settings. {e}");
+    }
+
+    check_dir("nodes", load);
+    check_dir("cameras", load_camera);
+}
+
+fn check_dir(kind: &str, load: fn(&str) -> Result<Settings, config_rs::ConfigError>) {
+    let dir = crate::CONFIG.general.settings.join(kind);
+    let entries = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            info!("{dir:?} does not exist. all {kind} use the default settings.");
+            return;
+        },
+        Err(e) => {
+            error!("unable to read {dir:?}. {e}");
+            return;
+        },
+    };
+    for path in entries.filter_map(|e| e.ok()).map(|e| e.path()) {
+        if path.extension().and_then(|e| e.to_str()) != Some("toml") { continue; }
+        if let Some(name) = path.file_stem().and_then(|s| s.to_str()) {
+            if let Err(e) = load(name) {
+                error!("invalid settings for {name}. {e}");
+            }
+        }
+    }
+}
+
+fn builder(dir: &Path) -> config_rs::ConfigBuilder<config_rs::builder::DefaultState> {
+    config_rs::Config::builder()
+        .add_source(config_rs::File::from_str(BUILTIN, config_rs::FileFormat::Toml))
+        .add_source(config_rs::File::from(dir.join("default.toml")).required(false))
+}
+
+fn node_path(name: &str) -> PathBuf {
+    crate::CONFIG.general.settings.join("nodes").join(format!("{name}.toml"))
+}
+
+fn camera_path(key: &str) -> PathBuf {
+    crate::CONFIG.general.settings.join("cameras").join(format!("{key}.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::capture::settings::dntime::Iso;
+
+    fn dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("settings-{uuid}", uuid = uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("nodes")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn load_layers_node_over_default_over_builtin() {
+        let dir = dir();
+        let node = dir.join("nodes").join("east.toml");
+        assert_eq!(load_path(&dir, node.clone()).unwrap().horizon, -0.67);
+
+        std::fs::write(dir.join("default.toml"), "horizon = -6.0\n[nighttime]\niso = { Manual = 1600 }\n").unwrap();
+        let s = load_path(&dir, node.clone()).unwrap();
+        assert_eq!((s.horizon, s.nighttime.iso, s.daytime.iso), (-6.0, Iso::Manual(1600), Iso::Manual(100)));
+
+        std::fs::write(&node, "horizon = -3.0\n").unwrap();
+        let s = load_path(&dir, node).unwrap();
+        assert_eq!((s.horizon, s.nighttime.iso, s.daytime.iso), (-3.0, Iso::Manual(1600), Iso::Manual(100)));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn store_refuses_invalid_settings() {
+        let dir = dir();
+        let node = dir.join("nodes").join("east.toml");
+        std::fs::write(&node, "horizon = -3.0\n").unwrap();
+
+        assert!(matches!(store_path(&dir, "east", node.clone(), "horizon = "), Err(SettingsError::Invalid(_))));
+        assert!(matches!(store_path(&dir, "east", node.clone(), "horizon = 100.0\n"), Err(SettingsError::Invalid(_))));
+        assert_eq!(std::fs::read_to_string(&node).unwrap(), "horizon = -3.0\n");
+        assert!(!node.with_extension("toml.partial").exists());
+
+        assert_eq!(store_path(&dir, "east", node.clone(), "horizon = -4.0\n").unwrap().horizon, -4.0);
+        assert_eq!(load_path(&dir, node).unwrap().horizon, -4.0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,28 @@
+use std::path::{Path, PathBuf};
+
+use common::capture::settings::stack::Method;
+
+use crate::CONFIG;
+use crate::develop::{self, Intermediates, ToolError};
+
+/// Develops the raws of the subframes of a frame, aligns them and stacks them into the jpg `output`.
+/// `profiles` holds the dark profile of each raw. Intermediate files are written to `tmp` and removed afterwards.
+pub fn stack(raws: &[PathBuf], profiles: &[Option<PathBuf>], method: Method, is_night: bool, tmp: &Path, output: &Path) -> Result<(), ToolError> {
+    let mut intermediates = Intermediates::default();
+    let mut tiffs = develop::tiffs(raws, profiles, is_night, tmp, &mut intermediates)?;
+    if CONFIG.stack.align {
+        tiffs = develop::align(&CONFIG.stack.align_image_stack, &tiffs, tmp, &mut intermediates)?;
+    }
+
+    let mut args = tiffs.iter().map(|t| t.to_str().unwrap()).collect::<Vec<_>>();
+    args.push("-evaluate-sequence");
+    args.push(match method {
+        Method::Mean => "mean",
+        Method::Median => "median",
+    });
+    args.push("-quality"); args.push("90");
+    args.push(output.to_str().unwrap());
+    develop::run(&CONFIG.stack.convert, &args)?;
+
+    develop::expect(&CONFIG.stack.convert, output)
+}
@@ -0,0 +1,87 @@
+use std::sync::Arc;
+
+use log::info;
+use tokio::io::{ AsyncRead, AsyncWrite };
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::rustls::{ Certificate, ServerConfig };
+use tokio_rustls::rustls::server::{ AllowAnyAuthenticatedClient, AllowAnyAnonymousOrAuthenticatedClient, NoClientAuth };
+
+use common::tls::{ load_certs, load_key, load_roots };
+
+use crate::config::tls::ClientAuth;
+
+/// Plain tcp or tls stream of a capture node.
+pub trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+/// Builds the acceptor for `wss://` from the `tls` section. `None` if tls is disabled.
+/// Panics on unusable certificates, just like on an invalid config.
+pub fn acceptor() -> Option<TlsAcceptor> {
+    let tls = &crate::CONFIG.tls;
+    if !tls.enable { return None; }
+
+    let certs = load_certs(&tls.cert).unwrap_or_else(|e| panic!("unable to load tls.cert. {e}"));
+    let key = load_key(&tls.key).unwrap_or_else(|e| panic!("unable to load tls.key. {e}"));
+
+    let verifier = match tls.client_auth {
+        ClientAuth::None => NoClientAuth::new(),
+        client_auth => {
+            let path = tls.client_ca.as_ref().unwrap_or_else(|| panic!("tls.client_ca is required if tls.client_auth is not none."));
+            let roots = load_roots(path).unwrap_or_else(|e| panic!("unable to load tls.client_ca. {e}"));
+            if client_auth == ClientAuth::Required {
+                AllowAnyAuthenticatedClient::new(roots)
+            } else {
+                AllowAnyAnonymousOrAuthenticatedClient::new(roots)
+            }
+        },
+    };
+
+    let config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_client_cert_verifier(verifier)
+        .with_single_cert(certs, key)
+        .unwrap_or_else(|e| panic!("unable to use tls.cert and tls.key. {e}"));
+
+    info!("serving wss:// with client_auth={client_auth:?}.", client_auth = tls.client_auth);
+    Some(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Checks that a client certificate was issued for the node `name`, so one node's certificate cannot
+/// be used to connect as another. The name has to be a dNSName in the certificate's subjectAltName.
+pub fn authorize(cert: &Certificate, name: &str) -> Result<(), String> {
+    let dns_name = webpki::DnsNameRef::try_from_ascii_str(name).map_err(|_| format!("{name} cannot be matched against a client certificate."))?;
+    let cert = webpki::EndEntityCert::try_from(cert.0.as_slice()).map_err(|e| format!("unable to parse the client certificate of {name}. {e:?}"))?;
+    cert.verify_is_valid_for_dns_name(dns_name).map_err(|_| format!("the client certificate is not issued for {name}."))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// self-signed, with the subjectAltName DNS:east.
+    const EAST: &str = "\
+-----BEGIN CERTIFICATE-----
+MIIBgjCCASmgAwIBAgIUL6K63Oua9UVN36h+ncU/1ElhE04wCgYIKoZIzj0EAwIw
+DzENMAsGA1UEAwwEZWFzdDAgFw0yNjEwMTgwNzQ1MzRaGA8yMTI2MDkyNDA3NDUz
+NFowDzENMAsGA1UEAwwEZWFzdDBZMBMGByqGSM49AgEGCCqGSM49AwEHA0IABIGz
+JaZz8t6UrJ9iU0eJ28NtYZeIkzps6KYtcpeQx5wS9to8exWQPK9x/xixzaOOXDmj
+Mk7T2LKc/fOjqdRUwc+jYTBfMB0GA1UdDgQWBBRZiXmfJAYVLbomUs6inHu4liLN
+JjAfBgNVHSMEGDAWgBRZiXmfJAYVLbomUs6inHu4liLNJjAPBgNVHREECDAGggRl
+YXN0MAwGA1UdEwEB/wQCMAAwCgYIKoZIzj0EAwIDRwAwRAIgVfDX7c3LVScI+EWY
+7yJM0ExU1cVJWMun0c6AIBz0vD4CIApSMyJAM6z2AT3PWJCn0xAIXFHCyuM6fRGf
+9koCOrSK
+-----END CERTIFICATE-----
+";
+
+    fn east() -> Certificate {
+        Certificate(rustls_pemfile::certs(&mut EAST.as_bytes()).unwrap().remove(0))
+    }
+
+    #[test]
+    fn authorize_by_subject_alt_name() {
+        assert_eq!(authorize(&east(), "east"), Ok(()));
+        assert_eq!(authorize(&east(), "west"), Err(String::from("the client certificate is not issued for west.")));
+        assert_eq!(authorize(&east(), "east 2"), Err(String::from("east 2 cannot be matched against a client certificate.")));
+        assert!(authorize(&Certificate(vec![1, 2, 3]), "east").is_err());
+    }
+}
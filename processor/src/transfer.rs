@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+use log::{ warn, debug };
+use tokio::fs::{ self, OpenOptions };
+use tokio::io::{ AsyncReadExt, AsyncWriteExt };
+use uuid::Uuid;
+
+use common::capture::CaptureResult;
+
+/// an upload that fails its checksum this often is refused for good.
+const MAX_ATTEMPTS: u32 = 3;
+
+lazy_static!{
+    /// failed checksum verifications of each upload, across connections.
+    static ref FAILED: Mutex<HashMap<Uuid, u32>> = Mutex::new(HashMap::new());
+}
+
+/// A chunked upload announced by `UploadBegin`.
+/// Chunks are appended to `{uuid}.partial` in `general.tmp_path`, which survives disconnects so transfers can be resumed.
+pub struct Incoming {
+    uuid: Uuid,
+    size: u64,
+    checksum: u32,
+    /// protocol version of the node, which decides the layout of the upload.
+    protocol_version: u32,
+}
+
+pub enum Progress {
+    /// more data is expected, starting at this offset.
+    Resume(u64),
+    /// all bytes were received and verified.
+    Complete(Box<CaptureResult>),
+    /// the upload is unusable. the partial file was deleted.
+    /// a retry is worth it if the bytes were mangled on the way, but not if the node sends them like that.
+    Corrupt { reason: String, retry: bool },
+}
+
+impl Incoming {
+    /// Picks up where an earlier attempt of this upload stopped.
+    pub async fn begin(uuid: Uuid, size: u64, checksum: u32, protocol_version: u32) -> std::io::Result<(Incoming, Progress)> {
+        let incoming = Incoming { uuid, size, checksum, protocol_version };
+        fs::create_dir_all(&crate::CONFIG.general.tmp_path).await?;
+
+        let received = incoming.received().await?;
+        let progress = if received > size {
+            warn!("partial {uuid} is larger than announced. starting over.");
+            fs::remove_file(incoming.path()).await?;
+            Progress::Resume(0)
+        } else if received == size {
+            incoming.finish().await?
+        } else {
+            Progress::Resume(received)
+        };
+        Ok((incoming, progress))
+    }
+
+    /// Appends `data` if it continues the partial file.
+    /// Returns `None` if the chunk was appended or already known and no answer is required.
+    pub async fn chunk(&self, offset: u64, data: &[u8]) -> std::io::Result<Option<Progress>> {
+        let received = self.received().await?;
+        if !fits(offset, data.len() as u64, self.size) {
+            if received > 0 { fs::remove_file(self.path()).await?; }
+            let reason = format!("chunk of {len}B at {offset} exceeds the announced {size}B.", len = data.len(), size = self.size);
+            return Ok(Some(Progress::Corrupt { reason, retry: false }));
+        }
+        match place(offset, data.len() as u64, received) {
+            Placement::Append => (),
+            Placement::Duplicate => {
+                debug!("ignoring duplicate chunk of {uuid} at {offset}.", uuid = self.uuid);
+                return Ok(None);
+            },
+            Placement::Gap => {
+                warn!("chunk of {uuid} at {offset} does not continue at {received}.", uuid = self.uuid);
+                return Ok(Some(Progress::Resume(received)));
+            },
+        }
+
+        let mut f = OpenOptions::new().create(true).append(true).open(self.path()).await?;
+        f.write_all(data).await?;
+        f.flush().await?;
+
+        if received + data.len() as u64 >= self.size {
+            Ok(Some(self.finish().await?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    async fn finish(&self) -> std::io::Result<Progress> {
+        let checksum = self.checksum().await?;
+        if checksum != self.checksum {
+            fs::remove_file(self.path()).await?;
+            let attempts = {
+                let mut failed = FAILED.lock().unwrap();
+                let attempts = failed.entry(self.uuid).or_default();
+                *attempts += 1;
+                *attempts
+            };
+            let retry = attempts < MAX_ATTEMPTS;
+            if !retry { FAILED.lock().unwrap().remove(&self.uuid); }
+            let reason = format!("checksum mismatch. expected {expected:08x}, got {checksum:08x}. attempt {attempts} of {MAX_ATTEMPTS}.", expected = self.checksum);
+            return Ok(Progress::Corrupt { reason, retry });
+        }
+        FAILED.lock().unwrap().remove(&self.uuid);
+
+        let b = fs::read(self.path()).await?;
+        fs::remove_file(self.path()).await?;
+        // spool files written before the node was updated still carry an older layout.
+        let decoded = common::capture::decode_chunked(&b, self.protocol_version).or_else(|e| {
+            debug!("upload {uuid} is not in the layout of protocol version {version}. trying older layouts.", uuid = self.uuid, version = self.protocol_version);
+            common::capture::decode_result(&b).map_err(|_| e)
+        });
+        match decoded {
+            Ok(c) if c.uuid == self.uuid => Ok(Progress::Complete(Box::new(c))),
+            Ok(c) => Ok(Progress::Corrupt { reason: format!("upload contains {uuid}.", uuid = c.uuid), retry: false }),
+            Err(e) => Ok(Progress::Corrupt { reason: format!("cannot deserialize upload. {e}"), retry: false }),
+        }
+    }
+
+    /// crc32 of the partial file, read in pieces.
+    async fn checksum(&self) -> std::io::Result<u32> {
+        let mut f = fs::File::open(self.path()).await?;
+        let mut hasher = crc32fast::Hasher::new();
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let n = f.read(&mut buf).await?;
+            if n == 0 { return Ok(hasher.finalize()); }
+            hasher.update(&buf[..n]);
+        }
+    }
+
+    async fn received(&self) -> std::io::Result<u64> {
+        match fs::metadata(self.path()).await {
+            Ok(m) => Ok(m.len()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(0),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn path(&self) -> PathBuf {
+        crate::CONFIG.general.tmp_path.join(format!("{uuid}.partial", uuid = self.uuid.as_hyphenated()))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Placement {
+    /// the chunk continues the partial file.
+    Append,
+    /// the chunk lies within what was already received.
+    Duplicate,
+    /// the chunk leaves a gap or overlaps the end of the partial file.
+    Gap,
+}
+
+/// Where a chunk of `len` bytes at `offset` falls relative to the `received` bytes of the partial file.
+/// `offset` comes from the node, so a chunk ending beyond `u64` is a gap.
+fn place(offset: u64, len: u64, received: u64) -> Placement {
+    if offset == received { return Placement::Append; }
+    match offset.checked_add(len) {
+        Some(end) if end <= received => Placement::Duplicate,
+        _ => Placement::Gap,
+    }
+}
+
+/// Whether a chunk of `len` bytes at `offset` ends within the announced `size`.
+fn fits(offset: u64, len: u64, size: u64) -> bool {
+    offset.checked_add(len).is_some_and(|end| end <= size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn place_chunks() {
+        assert_eq!(place(0, 10, 0), Placement::Append);
+        assert_eq!(place(10, 10, 10), Placement::Append);
+        assert_eq!(place(0, 10, 10), Placement::Duplicate);
+        assert_eq!(place(5, 5, 20), Placement::Duplicate);
+        assert_eq!(place(20, 10, 10), Placement::Gap);
+        assert_eq!(place(5, 10, 10), Placement::Gap);
+        assert_eq!(place(u64::MAX, 10, 5), Placement::Gap);
+    }
+
+    #[test]
+    fn fits_announced_size() {
+        assert!(fits(0, 100, 100));
+        assert!(fits(90, 10, 100));
+        assert!(!fits(90, 11, 100));
+        assert!(!fits(200, 0, 100));
+        assert!(!fits(u64::MAX, 10, u64::MAX));
+    }
+}